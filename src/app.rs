@@ -75,12 +75,12 @@ To build and run a Rust embedded application, simply use \"bobbin run\" with opt
 --example and --release parameters, just as you would use cargo or cargo directly. bobbin-cli will
 use these parameters as well as the local .cargo/config and Cargo.toml file to determine the path of
 the output file. It will then execute the appropriate flash loader application for your device (OpenOCD,
-JLinkExe, bossac or teensy_loader_cli), using objcopy as needed to convert to the required format.
+JLinkExe, bossac or teensy_loader_cli), converting the output to binary or Intel HEX format as needed.
 
 Some devices require manual intervention to enter bootloader mode.
 
 By default, if your selected debugger has a detected virtual serial port, bobbin-cli will connect to that
-serial port [NOTE: currently hard-coded to 115,200 baud] and display all output. Use Control-C to terminate
+serial port (115,200 baud 8N1 unless configured otherwise) and display all output. Use Control-C to terminate
 this console viewer. You can use the --console parameter to manually specify a serial device, or
 --noconsole if you do not want run the console viewer at all.
 
@@ -101,6 +101,9 @@ pub fn app() -> App<'static, 'static> {
         .arg(Arg::with_name("config").long("config").short("c").help("Specify the bobbin config file path"))
         .arg(Arg::with_name("host").long("host").takes_value(true).help("Specify the host to list."))
         .arg(Arg::with_name("device").long("device").short("d").takes_value(true).help("Specify a device ID prefix for filtering"))
+        .arg(Arg::with_name("format").long("format").takes_value(true).global(true)
+            .possible_values(&["text", "json", "tsv"])
+            .help("Output format for list, info and check"))
         // .arg(Arg::with_name("vendor-id").long("vendor-id").takes_value(true))
         // .arg(Arg::with_name("product-id").long("product-id").takes_value(true))
         // .arg(Arg::with_name("serial-number").long("serial-number").takes_value(true))
@@ -112,6 +115,10 @@ pub fn app() -> App<'static, 'static> {
             .about("Display a list of debug devices")
         )
         .subcommand(SubCommand::with_name("info")
+            .arg(Arg::with_name("probe").long("probe")
+                .help("Connect to each probe and display its firmware, target voltage and core ID"))
+            .arg(Arg::with_name("jlink-device").long("jlink-device").takes_value(true).help("Specify the J-Link device identifier"))
+            .arg(Arg::with_name("blackmagic-mode").long("blackmagic-mode").takes_value(true).help("Specify the Black Magic mode (swd or jtag)"))
             .about("Display detailed information about selected debug devices")        
         )
         
@@ -162,11 +169,54 @@ pub fn app() -> App<'static, 'static> {
             )            
             .arg(Arg::with_name("console-speed").long("console-speed").takes_value(true)
                 .help("Specify the baud rate of the serial device.")
+            )
+            .arg(Arg::with_name("console-format").long("console-format").takes_value(true)
+                .help("Specify the data bits, parity and stop bits of the serial device (e.g. 8N1).")
+            )
+            .arg(Arg::with_name("console-flow-control").long("console-flow-control").takes_value(true)
+                .possible_values(&["none", "software", "hardware"])
+                .help("Specify the flow control of the serial device.")
             )            
+            .arg(Arg::with_name("rtt").long("rtt")
+                .help("Use SEGGER RTT through the debugger as the console instead of a serial device."))
+            .arg(Arg::with_name("rtt-address").long("rtt-address").takes_value(true)
+                .help("Specify the address of the RTT control block (default: the _SEGGER_RTT symbol, or search RAM)."))
+            .arg(Arg::with_name("rtt-channel").long("rtt-channel").takes_value(true)
+                .help("Specify the RTT channel to use (default 0)."))
+            .arg(Arg::with_name("semihosting").long("semihosting")
+                .help("Reset the target with semihosting enabled and use its semihosting output as the console."))
+            .arg(Arg::with_name("no-defmt").long("no-defmt")
+                .help("Don't decode the console output of a program that uses defmt."))
+            .arg(Arg::with_name("log").long("log").takes_value(true)
+                .help("Append the console session to this file, with host timestamps."))
+            .arg(Arg::with_name("log-dir").long("log-dir").takes_value(true)
+                .help("Write the console session to a new file in this directory, named after the device and time."))
+            .arg(Arg::with_name("log-input").long("log-input")
+                .help("Also log the lines sent to the device."))
+            .arg(Arg::with_name("log-max-size").long("log-max-size").takes_value(true)
+                .help("Rotate the console log when it reaches this size (e.g. 10M)."))
+            .arg(Arg::with_name("view").long("view").takes_value(true)
+                .possible_values(&["text", "hex", "cobs", "slip"])
+                .help("Display the console output as text, a hexdump, or COBS or SLIP frames; lines typed are sent as hex payloads."))
             .arg(Arg::with_name("noconsole").long("no-console").help("Don't attempt to open a serial console after running."))
             .arg(Arg::with_name("itm").long("itm").help("Display the ITM trace output after running."))
             .arg(Arg::with_name("itm-target-clock").long("itm-target-clock").min_values(0).max_values(1)
-                .help("Set the ITM Target's Clock Speed"))
+                .help("Set the ITM Target's Clock Speed in Hz, or \"auto\" to detect it"))
+            .arg(Arg::with_name("itm-trace-clock").long("itm-trace-clock").takes_value(true)
+                .help("Set the SWO Trace Clock Speed in Hz (default 2000000)"))
+            .arg(Arg::with_name("itm-output").long("itm-output").takes_value(true)
+                .possible_values(&["ports", "text", "raw"])
+                .help("Write the selected stimulus ports as-is, timestamped text or the raw trace."))
+            .arg(Arg::with_name("itm-port").long("itm-port").takes_value(true).multiple(true).number_of_values(1)
+                .help("Route a stimulus port: <port>[:text|hex|defmt][=stdout|file:<path>|pipe:<path>|udp:<addr>|tcp:<addr>]. May be repeated."))
+            .arg(Arg::with_name("itm-enable-mask").long("itm-enable-mask").takes_value(true)
+                .help("Set the ITM_TER stimulus port enable mask (default 0xffffffff)."))
+            .arg(Arg::with_name("itm-privilege-mask").long("itm-privilege-mask").takes_value(true)
+                .help("Set the ITM_TPR privilege mask (default 0xffffffff)."))
+            .arg(Arg::with_name("itm-capture").long("itm-capture").takes_value(true)
+                .help("Also save the raw trace to a file."))
+            .arg(Arg::with_name("watch").long("watch")
+                .help("Rebuild, reload and reopen the console when the source files change."))
             .about("Load and run an application on the selected device after a successful build.")
         )
         .subcommand(SubCommand::with_name("test")
@@ -191,11 +241,67 @@ pub fn app() -> App<'static, 'static> {
             )            
             .arg(Arg::with_name("console-speed").long("console-speed").takes_value(true)
                 .help("Specify the baud rate of the serial device.")
+            )
+            .arg(Arg::with_name("console-format").long("console-format").takes_value(true)
+                .help("Specify the data bits, parity and stop bits of the serial device (e.g. 8N1).")
+            )
+            .arg(Arg::with_name("console-flow-control").long("console-flow-control").takes_value(true)
+                .possible_values(&["none", "software", "hardware"])
+                .help("Specify the flow control of the serial device.")
             )            
+            .arg(Arg::with_name("rtt").long("rtt")
+                .help("Use SEGGER RTT through the debugger as the console instead of a serial device."))
+            .arg(Arg::with_name("rtt-address").long("rtt-address").takes_value(true)
+                .help("Specify the address of the RTT control block (default: the _SEGGER_RTT symbol, or search RAM)."))
+            .arg(Arg::with_name("rtt-channel").long("rtt-channel").takes_value(true)
+                .help("Specify the RTT channel to use (default 0)."))
+            .arg(Arg::with_name("semihosting").long("semihosting")
+                .help("Reset the target with semihosting enabled and use its semihosting output as the console."))
+            .arg(Arg::with_name("no-defmt").long("no-defmt")
+                .help("Don't decode the console output of a program that uses defmt."))
+            .arg(Arg::with_name("log").long("log").takes_value(true)
+                .help("Append the console session to this file, with host timestamps."))
+            .arg(Arg::with_name("log-dir").long("log-dir").takes_value(true)
+                .help("Write the console session to a new file in this directory, named after the device and time."))
+            .arg(Arg::with_name("log-input").long("log-input")
+                .help("Also log the lines sent to the device."))
+            .arg(Arg::with_name("log-max-size").long("log-max-size").takes_value(true)
+                .help("Rotate the console log when it reaches this size (e.g. 10M)."))
             .arg(Arg::with_name("noconsole").long("no-console").help("Don't attempt to open a serial console after running."))
             .arg(Arg::with_name("itm").long("itm").help("Display the ITM trace output after running."))
             .arg(Arg::with_name("itm-target-clock").long("itm-target-clock").min_values(0).max_values(1)
-                .help("Set the ITM Target's Clock Speed"))
+                .help("Set the ITM Target's Clock Speed in Hz, or \"auto\" to detect it"))
+            .arg(Arg::with_name("itm-trace-clock").long("itm-trace-clock").takes_value(true)
+                .help("Set the SWO Trace Clock Speed in Hz (default 2000000)"))
+            .arg(Arg::with_name("itm-output").long("itm-output").takes_value(true)
+                .possible_values(&["ports", "text", "raw"])
+                .help("Write the selected stimulus ports as-is, timestamped text or the raw trace."))
+            .arg(Arg::with_name("itm-port").long("itm-port").takes_value(true).multiple(true).number_of_values(1)
+                .help("Route a stimulus port: <port>[:text|hex|defmt][=stdout|file:<path>|pipe:<path>|udp:<addr>|tcp:<addr>]. May be repeated."))
+            .arg(Arg::with_name("itm-enable-mask").long("itm-enable-mask").takes_value(true)
+                .help("Set the ITM_TER stimulus port enable mask (default 0xffffffff)."))
+            .arg(Arg::with_name("itm-privilege-mask").long("itm-privilege-mask").takes_value(true)
+                .help("Set the ITM_TPR privilege mask (default 0xffffffff)."))
+            .arg(Arg::with_name("itm-capture").long("itm-capture").takes_value(true)
+                .help("Also save the raw trace to a file."))
+            .arg(Arg::with_name("line-timeout").long("line-timeout").takes_value(true)
+                .help("Specify the maximum number of seconds between lines of output (0 to disable)."))
+            .arg(Arg::with_name("test-timeout").long("test-timeout").takes_value(true)
+                .help("Specify the maximum number of seconds for the entire test (0 to disable)."))
+            .arg(Arg::with_name("boot-timeout").long("boot-timeout").takes_value(true)
+                .help("Specify the maximum number of seconds before the first line of output (0 to disable)."))
+            .arg(Arg::with_name("pass-marker").long("pass-marker").takes_value(true).multiple(true).number_of_values(1)
+                .help("Specify a regular expression that completes the test successfully."))
+            .arg(Arg::with_name("fail-marker").long("fail-marker").takes_value(true).multiple(true).number_of_values(1)
+                .help("Specify a regular expression that fails the test."))
+            .arg(Arg::with_name("exit-marker").long("exit-marker").takes_value(true).multiple(true).number_of_values(1)
+                .help("Specify a regular expression that ends the test with a return code, as <code>=<regex>."))
+            .arg(Arg::with_name("report").long("report").takes_value(true)
+                .help("Write the test results to a JUnit XML or TAP file."))
+            .arg(Arg::with_name("report-format").long("report-format").takes_value(true)
+                .possible_values(&["junit", "tap"])
+                .help("Specify the report format (default: tap for .tap files, otherwise junit)."))
+            .arg(Arg::with_name("no-fault").long("no-fault").help("Don't analyze the fault registers if the test ends with an exception."))
             .about("Load and test an application on the selected device after a successful build.")
         )
         .subcommand(SubCommand::with_name("halt").about("Halt the selected device."))
@@ -208,6 +314,13 @@ pub fn app() -> App<'static, 'static> {
             )            
             .arg(Arg::with_name("console-speed").long("console-speed").takes_value(true)
                 .help("Specify the baud rate of the serial device.")
+            )
+            .arg(Arg::with_name("console-format").long("console-format").takes_value(true)
+                .help("Specify the data bits, parity and stop bits of the serial device (e.g. 8N1).")
+            )
+            .arg(Arg::with_name("console-flow-control").long("console-flow-control").takes_value(true)
+                .possible_values(&["none", "software", "hardware"])
+                .help("Specify the flow control of the serial device.")
             )            
             .arg(Arg::with_name("noconsole").long("no-console")
                 .help("Don't attempt to open a serial console after resuming.")
@@ -227,6 +340,13 @@ pub fn app() -> App<'static, 'static> {
             .arg(Arg::with_name("console-speed").long("console-speed").takes_value(true)
                 .help("Specify the baud rate of the serial device.")
             )
+            .arg(Arg::with_name("console-format").long("console-format").takes_value(true)
+                .help("Specify the data bits, parity and stop bits of the serial device (e.g. 8N1).")
+            )
+            .arg(Arg::with_name("console-flow-control").long("console-flow-control").takes_value(true)
+                .possible_values(&["none", "software", "hardware"])
+                .help("Specify the flow control of the serial device.")
+            )
             .arg(Arg::with_name("noconsole").long("no-console")
                 .help("Don't attempt to open a serial console after resuming.")
             )
@@ -241,11 +361,62 @@ pub fn app() -> App<'static, 'static> {
             )            
             .arg(Arg::with_name("console-speed").long("console-speed").takes_value(true)
                 .help("Specify the baud rate of the serial device.")
+            )
+            .arg(Arg::with_name("console-format").long("console-format").takes_value(true)
+                .help("Specify the data bits, parity and stop bits of the serial device (e.g. 8N1).")
+            )
+            .arg(Arg::with_name("console-flow-control").long("console-flow-control").takes_value(true)
+                .possible_values(&["none", "software", "hardware"])
+                .help("Specify the flow control of the serial device.")
             )            
-            .about("View the serial output of the selected device.")
+            .arg(Arg::with_name("rtt").long("rtt")
+                .help("Use SEGGER RTT through the debugger as the console instead of a serial device."))
+            .arg(Arg::with_name("rtt-address").long("rtt-address").takes_value(true)
+                .help("Specify the address of the RTT control block (default: the _SEGGER_RTT symbol, or search RAM)."))
+            .arg(Arg::with_name("rtt-channel").long("rtt-channel").takes_value(true)
+                .help("Specify the RTT channel to use (default 0)."))
+            .arg(Arg::with_name("jlink-device").long("jlink-device").takes_value(true).help("Specify the J-Link device identifier"))
+            .arg(Arg::with_name("defmt").long("defmt").takes_value(true)
+                .help("Decode the console output as defmt frames using the table in this ELF file."))
+            .arg(Arg::with_name("log").long("log").takes_value(true)
+                .help("Append the console session to this file, with host timestamps."))
+            .arg(Arg::with_name("log-dir").long("log-dir").takes_value(true)
+                .help("Write the console session to a new file in this directory, named after the device and time."))
+            .arg(Arg::with_name("log-input").long("log-input")
+                .help("Also log the lines sent to the device."))
+            .arg(Arg::with_name("log-max-size").long("log-max-size").takes_value(true)
+                .help("Rotate the console log when it reaches this size (e.g. 10M)."))
+            .arg(Arg::with_name("escape").long("escape").takes_value(true)
+                .help("Specify the letter of the Ctrl key that starts a terminal command (default a, for Ctrl-A)."))
+            .arg(Arg::with_name("line-mode").long("line-mode")
+                .help("Send lines from stdin instead of running an interactive terminal."))
+            .arg(Arg::with_name("view").long("view").takes_value(true)
+                .possible_values(&["text", "hex", "cobs", "slip"])
+                .help("Display the console output as text, a hexdump, or COBS or SLIP frames; lines typed are sent as hex payloads."))
+            .arg(Arg::with_name("send-file").long("send-file").takes_value(true)
+                .help("Send the contents of this file after opening the console, as one frame with --view cobs or slip."))
+            .about("View the serial output of the selected device, in an interactive terminal if stdin is a terminal.")
         )
         .subcommand(SubCommand::with_name("itm")
-            .arg(Arg::with_name("itm-target-clock").long("itm-target-clock").help("Set the ITM Target's Clock Speed"))
+            .arg(Arg::with_name("itm-target-clock").long("itm-target-clock").takes_value(true)
+                .help("Set the ITM Target's Clock Speed in Hz, or \"auto\" to detect it"))
+            .arg(Arg::with_name("itm-trace-clock").long("itm-trace-clock").takes_value(true)
+                .help("Set the SWO Trace Clock Speed in Hz (default 2000000)"))
+            .arg(Arg::with_name("itm-output").long("itm-output").takes_value(true)
+                .possible_values(&["ports", "text", "raw"])
+                .help("Write the selected stimulus ports as-is, timestamped text or the raw trace."))
+            .arg(Arg::with_name("itm-port").long("itm-port").takes_value(true).multiple(true).number_of_values(1)
+                .help("Route a stimulus port: <port>[:text|hex|defmt][=stdout|file:<path>|pipe:<path>|udp:<addr>|tcp:<addr>]. May be repeated."))
+            .arg(Arg::with_name("itm-enable-mask").long("itm-enable-mask").takes_value(true)
+                .help("Set the ITM_TER stimulus port enable mask (default 0xffffffff)."))
+            .arg(Arg::with_name("itm-privilege-mask").long("itm-privilege-mask").takes_value(true)
+                .help("Set the ITM_TPR privilege mask (default 0xffffffff)."))
+            .arg(Arg::with_name("itm-capture").long("itm-capture").takes_value(true)
+                .help("Also save the raw trace to a file."))
+            .arg(Arg::with_name("itm-replay").long("itm-replay").takes_value(true)
+                .help("Decode a trace saved with --itm-capture instead of reading from a device."))
+            .arg(Arg::with_name("defmt").long("defmt").takes_value(true)
+                .help("Use the defmt table in this ELF file for ports with the defmt framing."))
             .about("View the ITM output of the selected device.")
         )
         .subcommand(SubCommand::with_name("screen")
@@ -257,8 +428,17 @@ pub fn app() -> App<'static, 'static> {
             )            
             .arg(Arg::with_name("console-speed").long("console-speed").takes_value(true)
                 .help("Specify the baud rate of the serial device.")
-            )    
-            .about("Connect to the serial port of the selected device using screen.")
+            )
+            .arg(Arg::with_name("console-format").long("console-format").takes_value(true)
+                .help("Specify the data bits, parity and stop bits of the serial device (e.g. 8N1).")
+            )
+            .arg(Arg::with_name("console-flow-control").long("console-flow-control").takes_value(true)
+                .possible_values(&["none", "software", "hardware"])
+                .help("Specify the flow control of the serial device.")
+            )
+            .arg(Arg::with_name("escape").long("escape").takes_value(true)
+                .help("Specify the letter of the Ctrl key that starts a terminal command (default a, for Ctrl-A)."))
+            .about("Connect to the serial port of the selected device in an interactive terminal.")
         )
         .subcommand(SubCommand::with_name("openocd")
             .about("Start OpenOCD for the selected device")
@@ -278,5 +458,91 @@ pub fn app() -> App<'static, 'static> {
             .arg(Arg::with_name("no-build").long("no-build").help("Don't build before attempting to load."))
             .about("Start gdb using the build output as the target.")
         )
-    //.subcommand(SubCommand::with_name("objdump"))
+        .subcommand(SubCommand::with_name("objdump")
+            .arg(Arg::with_name("binary").index(1).takes_value(true).help("Specify the path of the binary file to analyze."))
+            .arg(Arg::with_name("target").long("target").takes_value(true).help("Pass a --target parameter to cargo"))
+            .arg(Arg::with_name("bin").long("bin").takes_value(true).help("Pass a --bin parameter to cargo"))
+            .arg(Arg::with_name("example").long("example").takes_value(true).help("Pass a --example parameter to cargo"))
+            .arg(Arg::with_name("release").long("release").help("Pass a --release parameter to cargo"))
+            .arg(Arg::with_name("features").long("features").takes_value(true).help("Pass a --features parameter to cargo"))
+            .arg(Arg::with_name("xargo").long("xargo").help("Use xargo instead of cargo"))
+            .arg(Arg::with_name("no-build").long("no-build").help("Don't build before analyzing."))
+            .arg(Arg::with_name("limit").long("limit").takes_value(true).help("Number of symbols to display (default 20)"))
+            .arg(Arg::with_name("disassemble").long("disassemble").help("Disassemble using arm-none-eabi-objdump"))
+            .about("Display sections, symbols and the vector table of the build output.")
+        )
+        .subcommand(SubCommand::with_name("profile")
+            .arg(Arg::with_name("binary").index(1).takes_value(true).help("Specify the path of the binary file to symbolize against."))
+            .arg(Arg::with_name("target").long("target").takes_value(true).help("Pass a --target parameter to cargo"))
+            .arg(Arg::with_name("bin").long("bin").takes_value(true).help("Pass a --bin parameter to cargo"))
+            .arg(Arg::with_name("example").long("example").takes_value(true).help("Pass a --example parameter to cargo"))
+            .arg(Arg::with_name("release").long("release").help("Pass a --release parameter to cargo"))
+            .arg(Arg::with_name("features").long("features").takes_value(true).help("Pass a --features parameter to cargo"))
+            .arg(Arg::with_name("xargo").long("xargo").help("Use xargo instead of cargo"))
+            .arg(Arg::with_name("no-build").long("no-build").help("Don't build before profiling."))
+            .arg(Arg::with_name("itm-target-clock").long("itm-target-clock").takes_value(true)
+                .help("Set the ITM Target's Clock Speed in Hz, or \"auto\" to detect it"))
+            .arg(Arg::with_name("itm-trace-clock").long("itm-trace-clock").takes_value(true)
+                .help("Set the SWO Trace Clock Speed in Hz (default 2000000)"))
+            .arg(Arg::with_name("rate").long("rate").takes_value(true)
+                .help("Number of PC samples per second (default 10000)"))
+            .arg(Arg::with_name("duration").long("duration").takes_value(true)
+                .help("Number of seconds to collect samples (default 5)"))
+            .arg(Arg::with_name("limit").long("limit").takes_value(true).help("Number of functions to display (default 20)"))
+            .arg(Arg::with_name("folded").long("folded").takes_value(true)
+                .help("Also write the profile to a file in folded-stack format for flame graph tools"))
+            .arg(Arg::with_name("itm-capture").long("itm-capture").takes_value(true)
+                .help("Also save the raw trace to a file."))
+            .arg(Arg::with_name("itm-replay").long("itm-replay").takes_value(true)
+                .help("Profile a trace saved with --itm-capture instead of reading from a device."))
+            .about("Sample the program counter of the running target over SWO and display a function profile.")
+        )
+        .subcommand(SubCommand::with_name("mem")
+            .subcommand(SubCommand::with_name("read")
+                .arg(Arg::with_name("address").index(1).required(true).help("Start address (decimal or 0x-prefixed hex)"))
+                .arg(Arg::with_name("length").index(2).help("Number of bytes to read (default one access)"))
+            .arg(Arg::with_name("width").long("width").takes_value(true)
+                .possible_values(&["8", "16", "32"])
+                .help("Access width in bits (default 32)"))
+            .arg(Arg::with_name("jlink-device").long("jlink-device").takes_value(true).help("Specify the J-Link device identifier"))
+            .arg(Arg::with_name("blackmagic-mode").long("blackmagic-mode").takes_value(true).help("Specify the Black Magic mode (swd or jtag)"))
+                .about("Read target memory and display it as a hexdump.")
+            )
+            .subcommand(SubCommand::with_name("write")
+                .arg(Arg::with_name("address").index(1).required(true).help("Address (decimal or 0x-prefixed hex)"))
+                .arg(Arg::with_name("value").index(2).required(true).help("Value to write (decimal or 0x-prefixed hex)"))
+            .arg(Arg::with_name("width").long("width").takes_value(true)
+                .possible_values(&["8", "16", "32"])
+                .help("Access width in bits (default 32)"))
+            .arg(Arg::with_name("jlink-device").long("jlink-device").takes_value(true).help("Specify the J-Link device identifier"))
+            .arg(Arg::with_name("blackmagic-mode").long("blackmagic-mode").takes_value(true).help("Specify the Black Magic mode (swd or jtag)"))
+                .about("Write a value to target memory.")
+            )
+            .subcommand(SubCommand::with_name("dump")
+                .arg(Arg::with_name("address").index(1).required(true).help("Start address (decimal or 0x-prefixed hex)"))
+                .arg(Arg::with_name("length").index(2).required(true).help("Number of bytes to dump"))
+                .arg(Arg::with_name("output").short("o").long("output").takes_value(true).required(true)
+                    .help("File to write the memory contents to"))
+            .arg(Arg::with_name("width").long("width").takes_value(true)
+                .possible_values(&["8", "16", "32"])
+                .help("Access width in bits (default 32)"))
+            .arg(Arg::with_name("jlink-device").long("jlink-device").takes_value(true).help("Specify the J-Link device identifier"))
+            .arg(Arg::with_name("blackmagic-mode").long("blackmagic-mode").takes_value(true).help("Specify the Black Magic mode (swd or jtag)"))
+                .about("Save target memory to a binary file.")
+            )
+            .about("Read, write or dump target memory through the debugger.")
+        )
+        .subcommand(SubCommand::with_name("fault")
+            .arg(Arg::with_name("binary").index(1).takes_value(true).help("Specify the path of the binary file to symbolize against."))
+            .arg(Arg::with_name("target").long("target").takes_value(true).help("Pass a --target parameter to cargo"))
+            .arg(Arg::with_name("bin").long("bin").takes_value(true).help("Pass a --bin parameter to cargo"))
+            .arg(Arg::with_name("example").long("example").takes_value(true).help("Pass a --example parameter to cargo"))
+            .arg(Arg::with_name("release").long("release").help("Pass a --release parameter to cargo"))
+            .arg(Arg::with_name("features").long("features").takes_value(true).help("Pass a --features parameter to cargo"))
+            .arg(Arg::with_name("xargo").long("xargo").help("Use xargo instead of cargo"))
+            .arg(Arg::with_name("no-build").long("no-build").help("Don't build before analyzing."))
+            .arg(Arg::with_name("jlink-device").long("jlink-device").takes_value(true).help("Specify the J-Link device identifier"))
+            .arg(Arg::with_name("blackmagic-mode").long("blackmagic-mode").takes_value(true).help("Specify the Black Magic mode (swd or jtag)"))
+            .about("Halt the selected device and display its fault status and a symbolized backtrace.")
+        )
 }
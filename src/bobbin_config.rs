@@ -6,6 +6,13 @@ pub struct BobbinConfig {
     pub builder: Option<BuilderConfig>,
     pub loader: Option<LoaderConfig>,
     pub itm: Option<ItmConfig>,
+    pub test: Option<TestConfig>,
+    pub device: Option<Vec<DeviceConfig>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DevicesConfig {
+    pub device: Option<Vec<DeviceConfig>>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -24,12 +31,73 @@ pub struct ConsoleConfig {
     pub device: Option<String>,
     pub path: Option<String>,
     pub speed: Option<u32>,
+    pub format: Option<String>,
+    #[serde(rename = "flow-control")]
+    pub flow_control: Option<String>,
+    pub rtt: Option<bool>,
+    #[serde(rename = "rtt-address")]
+    pub rtt_address: Option<String>,
+    #[serde(rename = "rtt-channel")]
+    pub rtt_channel: Option<u32>,
+    pub semihosting: Option<bool>,
+    pub defmt: Option<bool>,
+    #[serde(rename = "log-dir")]
+    pub log_dir: Option<String>,
+    #[serde(rename = "log-input")]
+    pub log_input: Option<bool>,
+    #[serde(rename = "log-max-size")]
+    pub log_max_size: Option<String>,
+    #[serde(rename = "log-keep")]
+    pub log_keep: Option<u32>,
+    pub escape: Option<String>,
+    pub view: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TestConfig {
+    #[serde(rename = "line-timeout")]
+    pub line_timeout: Option<u64>,
+    pub timeout: Option<u64>,
+    #[serde(rename = "boot-timeout")]
+    pub boot_timeout: Option<u64>,
+    pub marker: Option<Vec<TestMarkerConfig>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TestMarkerConfig {
+    pub pattern: String,
+    pub result: Option<String>,
+    #[serde(rename = "exit-code")]
+    pub exit_code: Option<i32>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ItmConfig {
     #[serde(rename = "target-clock")]
-    pub target_clock: Option<u32>,
+    pub target_clock: Option<ClockConfig>,
+    #[serde(rename = "trace-clock")]
+    pub trace_clock: Option<u32>,
+    pub output: Option<String>,
+    #[serde(rename = "enable-mask")]
+    pub enable_mask: Option<String>,
+    #[serde(rename = "privilege-mask")]
+    pub privilege_mask: Option<String>,
+    pub port: Option<Vec<ItmPortConfig>>,
+}
+
+/// A clock frequency in Hz, or "auto" to detect it from the target.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ClockConfig {
+    Hz(u32),
+    Name(String),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ItmPortConfig {
+    pub port: u16,
+    pub sink: Option<String>,
+    pub framing: Option<String>,
 }
 
 
@@ -42,4 +110,41 @@ pub struct LoaderConfig {
     #[serde(rename = "blackmagic-mode")]
     pub blackmagic_mode: Option<String>,
     pub offset: Option<String>,
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceConfig {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub device_type: String,
+    pub loader: Option<String>,
+    pub debugger: Option<String>,
+    #[serde(rename = "cdc-interface")]
+    pub cdc_interface: Option<String>,
+    #[serde(rename = "gdb-interface")]
+    pub gdb_interface: Option<String>,
+    #[serde(rename = "macos-cdc-path")]
+    pub macos_cdc_path: Option<String>,
+    #[serde(rename = "macos-gdb-path")]
+    pub macos_gdb_path: Option<String>,
+    #[serde(rename = "macos-bossa-path")]
+    pub macos_bossa_path: Option<String>,
+    #[serde(rename = "macos-legacy-cdc-path")]
+    pub macos_legacy_cdc_path: Option<String>,
+    #[serde(rename = "macos-legacy-gdb-path")]
+    pub macos_legacy_gdb_path: Option<String>,
+    #[serde(rename = "macos-legacy-bossa-path")]
+    pub macos_legacy_bossa_path: Option<String>,
+    #[serde(rename = "msd-volume")]
+    pub msd_volume: Option<String>,
+    #[serde(rename = "openocd-serial")]
+    pub openocd_serial: Option<String>,
+    #[serde(rename = "trace-endpoints")]
+    pub trace_endpoints: Option<Vec<u8>>,
+    #[serde(rename = "console-speed")]
+    pub console_speed: Option<u32>,
+    #[serde(rename = "console-format")]
+    pub console_format: Option<String>,
+    #[serde(rename = "console-flow-control")]
+    pub console_flow_control: Option<String>,
+}
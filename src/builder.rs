@@ -1,8 +1,11 @@
+use std::env;
+use std::io::{self, BufRead, BufReader, Write};
 use std::path::PathBuf;
-use std::process::Command;
+use std::process::{Command, Stdio};
 use config::Config;
 use clap::ArgMatches;
 use printer::Printer;
+use serde_json::{self, Value};
 use Result;
 
 pub fn build_path(cfg: &Config, args: &ArgMatches, cmd_args: &ArgMatches) -> Result<PathBuf> {
@@ -14,7 +17,13 @@ pub fn build_path(cfg: &Config, args: &ArgMatches, cmd_args: &ArgMatches) -> Res
         return Ok(PathBuf::from("--"))
     }
 
-    let mut dst = PathBuf::from("target");
+    let mut dst = if let Some(dir) = env::var_os("CARGO_TARGET_DIR") {
+        PathBuf::from(dir)
+    } else if let Some(dir) = cfg.cargo_target_dir() {
+        PathBuf::from(dir)
+    } else {
+        PathBuf::from("target")
+    };
 
     if let Some(t) = cmd_args.value_of("target") {
         dst.push(t)
@@ -83,11 +92,31 @@ pub fn build_xargo(
     } else if let Some(value) = cfg.target() {
         cmd.arg("--target").arg(value);
     }
+    cmd.arg("--message-format=json");
+    cmd.stdout(Stdio::piped());
     out.verbose(cmd_name, &format!("{:?}", cmd))?;
-    if !cmd.status()?.success() {
+    let mut child = cmd.spawn()?;
+    let mut artifacts: Vec<Artifact> = Vec::new();
+    if let Some(stdout) = child.stdout.take() {
+        for line in BufReader::new(stdout).lines() {
+            handle_message(&line?, &mut artifacts)?;
+        }
+    }
+    if !child.wait()?.success() {
         bail!("build failed");
     }
-    let dst = build_path(cfg, args, cmd_args)?;
+    let dst = if artifacts.len() > 1 {
+        let mut msg = String::from("More than one executable was built; use --bin or --example to select one:");
+        for a in artifacts.iter() {
+            msg.push_str(&format!("\n    {} {} ({})", a.kind, a.name, a.path.display()));
+        }
+        bail!(msg);
+    } else if let Some(a) = artifacts.pop() {
+        a.path
+    } else {
+        return Ok(None)
+    };
+    out.verbose("artifact", &format!("{}", dst.display()))?;
     if dst.is_file() {
         let mut cmd = Command::new("arm-none-eabi-size");
         out.verbose("size", &format!("{:?}", cmd))?;
@@ -100,3 +129,50 @@ pub fn build_xargo(
         Ok(None)
     }
 }
+
+/// An executable reported by a cargo compiler-artifact message.
+struct Artifact {
+    name: String,
+    kind: String,
+    path: PathBuf,
+}
+
+/// Handles a line of `cargo build --message-format=json` output, printing compiler diagnostics
+/// to stderr and collecting executables.
+fn handle_message(line: &str, artifacts: &mut Vec<Artifact>) -> Result<()> {
+    let msg: Value = match serde_json::from_str(line) {
+        Ok(msg) => msg,
+        Err(_) => {
+            println!("{}", line);
+            return Ok(())
+        }
+    };
+    match msg["reason"].as_str() {
+        Some("compiler-message") => {
+            if let Some(rendered) = msg["message"]["rendered"].as_str() {
+                let stderr = io::stderr();
+                let mut stderr = stderr.lock();
+                stderr.write_all(rendered.as_bytes())?;
+                stderr.flush()?;
+            }
+        },
+        Some("compiler-artifact") => {
+            if let Some(path) = msg["executable"].as_str() {
+                let target = &msg["target"];
+                let kind = match target["kind"][0].as_str() {
+                    Some("example") => "example",
+                    Some("test") => "test",
+                    Some("bench") => "bench",
+                    _ => "bin",
+                };
+                artifacts.push(Artifact {
+                    name: String::from(target["name"].as_str().unwrap_or("")),
+                    kind: String::from(kind),
+                    path: PathBuf::from(path),
+                });
+            }
+        },
+        _ => {},
+    }
+    Ok(())
+}
@@ -8,7 +8,9 @@ pub struct CargoConfig {
 
 #[derive(Debug, Deserialize)]
 pub struct BuildConfig {
-    pub target: Option<String>,    
+    pub target: Option<String>,
+    #[serde(rename = "target-dir")]
+    pub target_dir: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -25,10 +25,25 @@ pub fn which(exec: &str) -> Result<String, Error> {
         let caps = re.captures(&out.stdout).unwrap();
         Ok(String::from_utf8_lossy(&caps[1]).into_owned())
     } else {
-        panic!("error");
+        Err(Error::Status)
     }
 }
 
+pub type VersionFn = fn() -> Result<String, Error>;
+
+/// The external tools reported by `bobbin check`: display name, executable and version probe.
+pub const TOOLS: &[(&str, &str, VersionFn)] = &[
+    ("Rust", "rustc", rust_version),
+    ("Cargo", "cargo", cargo_version),
+    ("Xargo", "xargo", xargo_version),
+    ("GCC", "arm-none-eabi-gcc", gcc_version),
+    ("OpenOCD", "openocd", openocd_version),
+    ("JLink", "JLinkExe", jlink_version),
+    ("Bossa", "bossac", bossac_version),
+    ("Teensy", "teensy_loader_cli", teensy_version),
+    ("dfu-util", "dfu-util", dfu_util_version),
+];
+
 pub fn rust_version() -> Result<String, Error> {
     let out = Command::new("rustc").arg("--version").output()?;
     if out.status.success() {
@@ -3,11 +3,12 @@ use clap::ArgMatches;
 use config::Config;
 use printer::Printer;
 use std::io::{self, Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::*;
 // use std::os::unix::io::*;
 use std::os::unix::process::CommandExt;
-// use std::fs::File;
+use std::fs::File;
+use std::time::{Duration, Instant};
 
 use device;
 use builder;
@@ -15,6 +16,19 @@ use loader;
 use debugger;
 use console;
 use check;
+use elf;
+use record;
+use report;
+use watch;
+use itm;
+use mem;
+use fault;
+use rtt;
+use defmt;
+use logfile;
+use terminal;
+use view;
+use profile;
 use tempfile;
 
 pub fn check(
@@ -23,17 +37,32 @@ pub fn check(
     cmd_args: &ArgMatches,
     out: &mut Printer,
 ) -> Result<()> {
-    writeln!(out, "    Bobbin {}", crate_version!())?;
-    writeln!(out, "      Rust {}", check::rust_version().unwrap_or(String::from("Not Found")))?;
-    writeln!(out, "     Cargo {}", check::cargo_version().unwrap_or(String::from("Not Found")))?;
-    writeln!(out, "     Xargo {}", check::xargo_version().unwrap_or(String::from("Not Found")))?;
-    writeln!(out, "       GCC {}", check::gcc_version().unwrap_or(String::from("Not Found")))?;
-    writeln!(out, "   OpenOCD {}", check::openocd_version().unwrap_or(String::from("Not Found")))?;
-    writeln!(out, "     JLink {}", check::jlink_version().unwrap_or(String::from("Not Found")))?;
-    writeln!(out, "     Bossa {}", check::bossac_version().unwrap_or(String::from("Not Found")))?;    
-    writeln!(out, "    Teensy {}", check::teensy_version().unwrap_or(String::from("Not Found")))?;
-    writeln!(out, "  dfu-util {}", check::dfu_util_version().unwrap_or(String::from("Not Found")))?;
-    Ok(())
+    let format = record::format(args, cmd_args)?;
+    if format == record::Format::Text {
+        writeln!(out, "{:>10} {}", "Bobbin", crate_version!())?;
+        for &(name, _, version) in check::TOOLS {
+            writeln!(out, "{:>10} {}", name, version().unwrap_or(String::from("Not Found")))?;
+        }
+        return Ok(())
+    }
+
+    let mut tools = vec![record::ToolRecord {
+        name: String::from("Bobbin"),
+        version: Some(String::from(crate_version!())),
+        path: ::std::env::current_exe().ok().map(|p| p.display().to_string()),
+    }];
+    for &(name, exec, version) in check::TOOLS {
+        tools.push(record::ToolRecord {
+            name: String::from(name),
+            version: version().ok(),
+            path: check::which(exec).ok(),
+        });
+    }
+    if format == record::Format::Json {
+        record::write_json(out, &tools)
+    } else {
+        record::write_tsv(out, &tools)
+    }
 }
 
 pub fn list(
@@ -55,12 +84,20 @@ pub fn list(
             cmd.arg("--verbose");
         }                
         cmd.arg("list");
+        if let Some(format) = cmd_args.value_of("format").or_else(|| args.value_of("format")) {
+            cmd.arg("--format").arg(format);
+        }
         cmd.exec();
         unreachable!()
     }
 
     let filter = device::filter(cfg, args, cmd_args);
-    let devices = device::search(&filter);
+    let devices = device::search(cfg, &filter);
+
+    match record::format(args, cmd_args)? {
+        record::Format::Text => {},
+        format => return write_devices(out, format, &devices?, &[]),
+    }
 
     writeln!(out, "{:08} {:08}  {:40} {:24}",
         "ID",
@@ -102,14 +139,37 @@ pub fn info(
             cmd.arg("--verbose");
         }                
         cmd.arg("info");
+        if let Some(format) = cmd_args.value_of("format").or_else(|| args.value_of("format")) {
+            cmd.arg("--format").arg(format);
+        }
+        if cmd_args.is_present("probe") {
+            cmd.arg("--probe");
+            if let Some(v) = cfg.jlink_device(cmd_args) {
+                cmd.arg("--jlink-device").arg(v);
+            }
+            if let Some(v) = cfg.blackmagic_mode(cmd_args) {
+                cmd.arg("--blackmagic-mode").arg(v);
+            }
+        }
         cmd.exec();
         unreachable!()
     }
 
     let filter = device::filter(cfg, args, cmd_args);
-    let devices = device::search(&filter)?;
+    let devices = device::search(cfg, &filter)?;
+
+    let probes: Vec<Option<Result<debugger::ProbeInfo>>> = if cmd_args.is_present("probe") {
+        devices.iter().map(|d| probe_info(cfg, args, cmd_args, out, d.as_ref())).collect()
+    } else {
+        devices.iter().map(|_| None).collect()
+    };
+
+    match record::format(args, cmd_args)? {
+        record::Format::Text => {},
+        format => return write_devices(out, format, &devices, &probes),
+    }
 
-    for d in devices.iter() {
+    for (d, probe) in devices.iter().zip(probes.iter()) {
         let u = d.usb();
         writeln!(out, "{:16} {}", "ID", d.hash())?;
         writeln!(out, "{:16} {:04x}", "Vendor ID", u.vendor_id)?;
@@ -145,11 +205,74 @@ pub fn info(
         if let Some(openocd_serial) = d.openocd_serial() {
             writeln!(out, "{:16} {}", "OpenOCD Serial", openocd_serial)?;
         }
+        match *probe {
+            Some(Ok(ref info)) => {
+                if let Some(ref firmware) = info.firmware {
+                    writeln!(out, "{:16} {}", "Probe Firmware", firmware)?;
+                }
+                if let Some(ref api) = info.api {
+                    writeln!(out, "{:16} {}", "Probe API", api)?;
+                }
+                if let Some(voltage) = info.voltage {
+                    writeln!(out, "{:16} {:.2} V", "Target Voltage", voltage)?;
+                }
+                if let Some(ref mode) = info.mode {
+                    writeln!(out, "{:16} {}", "Probe Mode", mode)?;
+                }
+                if let Some(core_id) = info.core_id {
+                    writeln!(out, "{:16} {:08x}", "Core ID", core_id)?;
+                }
+                if let Some(cpuid) = info.cpuid {
+                    match info.core() {
+                        Some(core) => writeln!(out, "{:16} {:08x} ({})", "CPUID", cpuid, core)?,
+                        None => writeln!(out, "{:16} {:08x}", "CPUID", cpuid)?,
+                    }
+                }
+            }
+            Some(Err(ref e)) => writeln!(out, "{:16} {}", "Probe Error", e)?,
+            None => {}
+        }
         writeln!(out, "")?;
     }
     Ok(())
 }
 
+/// Reads the probe and target details through the device's debugger, or returns None if the device
+/// has no debugger.
+fn probe_info(
+    cfg: &Config,
+    args: &ArgMatches,
+    cmd_args: &ArgMatches,
+    out: &mut Printer,
+    device: &device::Device,
+) -> Option<Result<debugger::ProbeInfo>> {
+    let dbg_type = device.debugger_type()?;
+    Some(match debugger::debugger(dbg_type) {
+        Some(dbg) => dbg.probe_info(cfg, args, cmd_args, out, device),
+        None => Err(format!("Unknown debugger type: {}", dbg_type).into()),
+    })
+}
+
+fn write_devices(
+    out: &mut Printer,
+    format: record::Format,
+    devices: &[Box<device::Device>],
+    probes: &[Option<Result<debugger::ProbeInfo>>],
+) -> Result<()> {
+    let records: Vec<record::DeviceRecord> = devices.iter().enumerate()
+        .map(|(i, d)| {
+            let mut record = record::DeviceRecord::new(d.as_ref());
+            record.probe = probes.get(i).and_then(|p| p.as_ref()).map(record::ProbeRecord::new);
+            record
+        })
+        .collect();
+    if format == record::Format::Json {
+        record::write_json(out, &records)
+    } else {
+        record::write_tsv(out, &records)
+    }
+}
+
 pub fn build(
     cfg: &Config,
     args: &ArgMatches,
@@ -173,7 +296,20 @@ pub fn load(
         bail!("No build output available to load");
     };
 
+    if cmd_args.is_present("watch") && (cmd_args.is_present("stdin") || cmd_args.is_present("itm")) {
+        bail!("--watch can't be used with --stdin or --itm");
+    }
+
     if let Some(host) = args.value_of("host").or_else(|| cfg.filter_host()) {
+        if cmd_args.is_present("watch") {
+            bail!("--watch is not supported for remote hosts");
+        }
+        if cmd_args.is_present("report") {
+            bail!("--report is not supported for remote hosts");
+        }
+        if cmd_args.is_present("log") || cmd_args.is_present("log-dir") {
+            bail!("Console logs are not supported for remote hosts");
+        }
         let mut cmd = Command::new("rsync");
         cmd.arg(dst.clone());
         let device = args.value_of("device").or_else(|| cfg.filter_device()).unwrap_or_else(|| "bobbin");
@@ -216,8 +352,57 @@ pub fn load(
             cmd.arg("--blackmagic_mode").arg(arg);
         }
 
-        if let Some(arg) = cfg.console(cmd_args) {
-            cmd.arg("--console").arg(arg);
+        if subcmd != "load" {
+            if let Some(arg) = cfg.console(cmd_args) {
+                cmd.arg("--console").arg(arg);
+            }
+            if let Some(arg) = cfg.console_speed(cmd_args) {
+                cmd.arg("--console-speed").arg(arg);
+            }
+            if let Some(arg) = cfg.console_format(cmd_args) {
+                cmd.arg("--console-format").arg(arg);
+            }
+            if let Some(arg) = cfg.console_flow_control(cmd_args) {
+                cmd.arg("--console-flow-control").arg(arg);
+            }
+            if cfg.console_rtt(cmd_args) {
+                cmd.arg("--rtt");
+            }
+            if let Some(arg) = cfg.rtt_address(cmd_args) {
+                cmd.arg("--rtt-address").arg(arg);
+            }
+            if let Some(arg) = cfg.rtt_channel(cmd_args) {
+                cmd.arg("--rtt-channel").arg(arg);
+            }
+            if cfg.console_semihosting(cmd_args) {
+                cmd.arg("--semihosting");
+            }
+            if !cfg.console_defmt(cmd_args) {
+                cmd.arg("--no-defmt");
+            }
+        }
+
+        if subcmd == "run" {
+            if let Some(arg) = cfg.console_view(cmd_args) {
+                cmd.arg("--view").arg(arg);
+            }
+        }
+
+        if subcmd == "test" {
+            if let Some(arg) = cfg.test_line_timeout(cmd_args) {
+                cmd.arg("--line-timeout").arg(arg);
+            }
+            if let Some(arg) = cfg.test_timeout(cmd_args) {
+                cmd.arg("--test-timeout").arg(arg);
+            }
+            if let Some(arg) = cfg.test_boot_timeout(cmd_args) {
+                cmd.arg("--boot-timeout").arg(arg);
+            }
+            // Markers from .bobbin/config are passed on the command line like the other options.
+            for marker in report::markers(cfg, cmd_args)? {
+                let (name, value) = marker.arg();
+                cmd.arg(name).arg(value);
+            }
         }
 
         cmd.arg(format!("/tmp/{}/{}", device, dst.file_name().unwrap().to_str().unwrap()));
@@ -228,7 +413,7 @@ pub fn load(
     }
 
     let filter = device::filter(cfg, args, cmd_args);
-    let mut devices = device::search(&filter)?;
+    let mut devices = device::search(cfg, &filter)?;
 
     let device = if devices.len() == 0 {
         bail!("No matching devices found.");
@@ -249,19 +434,31 @@ pub fn load(
         bail!("Selected device has no associated loader");
     };
 
-    let con = if !cmd_args.is_present("noconsole") && !cmd_args.is_present("itm") {
-        if args.is_present("run") || args.is_present("test") {
-            if let Some(cdc_path) = cfg.console(cmd_args) {
-                let mut con = console::open(&cdc_path)?;
-                con.clear()?;
-                Some(con)
-            } else if let Some(cdc_path) = device.cdc_path() {
-                let mut con = console::open(&cdc_path)?;
-                con.clear()?;
-                Some(con)
-            } else {
-                None
-            }
+    let use_console = !cmd_args.is_present("noconsole") && !cmd_args.is_present("itm") &&
+        (args.is_present("run") || args.is_present("test"));
+    // The RTT control block is set up by the firmware, and semihosting resets the target, so these
+    // consoles are opened after loading.
+    let debugger_path = if !use_console {
+        None
+    } else if cfg.console_semihosting(cmd_args) {
+        if cfg.console_rtt(cmd_args) {
+            bail!("RTT and semihosting consoles can't be used together");
+        }
+        Some(SEMIHOSTING_PATH)
+    } else if cfg.console_rtt(cmd_args) {
+        Some(RTT_PATH)
+    } else {
+        None
+    };
+
+    let con = if use_console && debugger_path.is_none() {
+        let settings = console::settings(cfg, cmd_args, device.as_ref())?;
+        if let Some(cdc_path) = cfg.console(cmd_args).or_else(|| device.cdc_path()) {
+            out.verbose("console", &format!("{} {}", cdc_path, settings))?;
+            let mut con = console::open(&cdc_path, &settings)?;
+            con.set_reconnect(console::Reconnect::new(cfg, device.as_ref(), cfg.console(cmd_args)));
+            con.clear()?;
+            Some(con)
         } else {
             None
         }
@@ -297,36 +494,285 @@ pub fn load(
     }
     out.info("Loader", "Load Complete")?;
 
+    let elf = if dst.as_path() == Path::new("--") { None } else { Some(dst.as_path()) };
+    let mut con = if let Some(path) = debugger_path {
+        Some(debugger_console(cfg, args, cmd_args, out, device.as_ref(), path, elf)?)
+    } else {
+        con
+    };
+    if let Some(ref mut con) = con {
+        if let Some(table) = defmt_table(cfg, cmd_args, out, elf)? {
+            con.set_defmt(table);
+        }
+        start_log(cfg, cmd_args, out, device.as_ref(), con)?;
+        // The test protocol reads text lines, so the binary views are only used by run.
+        if args.is_present("run") {
+            con.set_view(view::view(cfg, cmd_args)?);
+        }
+    }
+
     if cmd_args.is_present("itm") {
         if device.can_trace_itm() {
             out.info("ITM", "Starting ITM Trace")?;
-            let target_clk = if let Some(v) = cmd_args.value_of("itm-target-clock") {
-                v.parse::<u32>()?
-            } else {
-                if let Some(v) = cfg.itm_target_clock() {
-                    v
-                } else {
-                    bail!("itm-target-clock is required for ITM trace.")
-                }
-            };
-            let trace_clk = 2_000_000;
-            device.trace_itm(target_clk, trace_clk)?;
-
+            let mut output = itm_output(cfg, cmd_args, out, elf)?;
+            let trace = itm::trace_config(cfg, cmd_args, output.mode(), device.as_ref(), out)?;
+            device.trace_itm(&trace, &mut |data| output.write(data).map(|_| true))?;
         } else {
             bail!("Currently selected device does not support ITM trace");
         }
     } else if let Some(mut con) = con {
         out.info("Console", "Opening Console")?;
         if args.is_present("test") {
-            con.test(&args, &cmd_args)?;
+            let name = dst.file_stem().and_then(|s| s.to_str()).unwrap_or("bobbin");
+            let timeouts = console::timeouts(cfg, cmd_args)?;
+            let markers = report::markers(cfg, cmd_args)?;
+            let run = con.test(name, &timeouts, markers)?;
+            // Close the console first; an RTT console may be using the debug probe.
+            drop(con);
+            if run.outcome == Some(report::Outcome::Exception) && !cmd_args.is_present("no-fault") {
+                if let Err(e) = fault_report(cfg, args, cmd_args, out, device.as_ref(), elf) {
+                    out.info("Fault", &format!("Unable to analyze the exception: {}", e))?;
+                }
+            }
+            if let Some(path) = cmd_args.value_of("report") {
+                let path = Path::new(path);
+                let format = if let Some(format) = cmd_args.value_of("report-format") {
+                    report::Format::from_name(format)?
+                } else {
+                    report::Format::from_path(path)
+                };
+                report::write(path, format, &run)?;
+                out.verbose("report", &format!("Wrote {}", path.display()))?;
+            }
+            out.flush()?;
+            exit(run.exit_code());
+        } else if cmd_args.is_present("watch") {
+            watch(cfg, args, cmd_args, out, device.as_ref(), ldr.as_ref(), Some(con))?;
         } else {
             con.view()?;
+            if let Some(code) = con.exit_code() {
+                out.info("Console", &format!("Program exited with code {}", code))?;
+            }
         }
+    } else if cmd_args.is_present("watch") {
+        watch(cfg, args, cmd_args, out, device.as_ref(), ldr.as_ref(), None)?;
     }
 
     Ok(())
 }
 
+/// Rebuilds and reloads the application whenever the source tree (or the binary, if one was
+/// given) changes, reopening the console after each load. A failed build leaves the current
+/// console session running.
+fn watch(
+    cfg: &Config,
+    args: &ArgMatches,
+    cmd_args: &ArgMatches,
+    out: &mut Printer,
+    device: &device::Device,
+    ldr: &loader::Load,
+    mut con: Option<console::Console>,
+) -> Result<()> {
+    let con_path = con.as_ref().map(|c| (String::from(c.path()), *c.settings(), c.reconnect().cloned()));
+    let debugger_path = match con.as_ref().map(|c| c.path()) {
+        Some(RTT_PATH) => Some(RTT_PATH),
+        Some(SEMIHOSTING_PATH) => Some(SEMIHOSTING_PATH),
+        _ => None,
+    };
+    let roots = if let Some(binary) = cmd_args.value_of("binary") {
+        vec![PathBuf::from(binary)]
+    } else {
+        vec![PathBuf::from(".")]
+    };
+    let mut watcher = watch::Watcher::new(roots, cfg.cargo_target_dir())?;
+    let stdin_rx = console::stdin_lines();
+    // The session log, while there is no console to write it.
+    let mut log = None;
+    out.info("Watch", "Watching for changes")?;
+    loop {
+        let mut changed = false;
+        if let Some(ref mut con) = con {
+            con.view_until(&stdin_rx, || {
+                changed = watcher.changed()?;
+                Ok(changed)
+            })?;
+            if let Some(code) = con.exit_code() {
+                out.info("Console", &format!("Program exited with code {}", code))?;
+            }
+        }
+        // Wait here if the console ended without a change, e.g. because the program exited.
+        if !changed {
+            watcher.wait()?;
+        }
+        out.info("Watch", "Change detected, rebuilding")?;
+        let dst = match builder::build(cfg, args, cmd_args, out) {
+            Ok(Some(dst)) => dst,
+            Ok(None) => {
+                out.error("Watch", "No build output available to load")?;
+                continue
+            },
+            Err(e) => {
+                out.error("Watch", &format!("{}", e))?;
+                continue
+            },
+        };
+        // Close the console before loading; some probes reset their serial port. The session log
+        // continues with the new console.
+        if let Some(l) = con.as_mut().and_then(|c| c.take_log()) {
+            log = Some(l);
+        }
+        con = None;
+        if let Err(e) = ldr.load(cfg, args, cmd_args, out, device, dst.as_path()) {
+            out.error("Loader", &format!("{}", e))?;
+        } else {
+            out.info("Loader", "Load Complete")?;
+        }
+        if let Some(path) = debugger_path {
+            // Keep watching if the console can't be opened, e.g. because the new build doesn't
+            // start; the next change reloads it.
+            match debugger_console(cfg, args, cmd_args, out, device, path, Some(dst.as_path())) {
+                Ok(c) => {
+                    out.info("Console", "Opening Console")?;
+                    con = Some(c);
+                }
+                Err(e) => out.error("Console", &format!("{}", e))?,
+            }
+        } else if let Some((ref cdc_path, ref settings, ref reconnect)) = con_path {
+            // Likewise if the serial port doesn't come back, e.g. because the new build never
+            // brings up USB.
+            match console::reopen(cdc_path, settings, reconnect.as_ref()) {
+                Ok(mut c) => {
+                    c.clear()?;
+                    out.info("Console", "Opening Console")?;
+                    con = Some(c);
+                }
+                Err(e) => out.error("Console", &format!("{}", e))?,
+            }
+        }
+        if let Some(ref mut con) = con {
+            match defmt_table(cfg, cmd_args, out, Some(dst.as_path())) {
+                Ok(Some(table)) => con.set_defmt(table),
+                Ok(None) => {}
+                Err(e) => out.error("defmt", &format!("{}", e))?,
+            }
+            if let Some(mut log) = log.take() {
+                log.reset()?;
+                con.set_log(log);
+            }
+            match view::view(cfg, cmd_args) {
+                Ok(view) => con.set_view(view),
+                Err(e) => out.error("Console", &format!("{}", e))?,
+            }
+        }
+    }
+}
+
+/// Loads the defmt table from the file given with --defmt, or from `elf` unless defmt decoding
+/// is disabled. Returns None if the program doesn't use defmt.
+fn defmt_table(
+    cfg: &Config,
+    cmd_args: &ArgMatches,
+    out: &mut Printer,
+    elf: Option<&Path>,
+) -> Result<Option<defmt::Table>> {
+    let (path, table) = if let Some(path) = cmd_args.value_of("defmt") {
+        match defmt::Table::load(path)? {
+            Some(table) => (Path::new(path), table),
+            None => bail!("{} has no {} section", path, defmt::SECTION),
+        }
+    } else {
+        let path = match elf {
+            Some(path) if cfg.console_defmt(cmd_args) => path,
+            _ => return Ok(None),
+        };
+        // The binary may not be an ELF file, in which case it isn't decoded.
+        match elf::Elf::open(path).ok().map(|elf| defmt::Table::parse(&elf)) {
+            Some(Ok(Some(table))) => (path, table),
+            Some(Err(e)) => return Err(e),
+            _ => return Ok(None),
+        }
+    };
+    out.verbose("defmt", &format!("{} strings from {}", table.len(), path.display()))?;
+    Ok(Some(table))
+}
+
+/// Starts logging the console session if --log or --log-dir (or [console] log-dir) was given.
+fn start_log(
+    cfg: &Config,
+    cmd_args: &ArgMatches,
+    out: &mut Printer,
+    device: &device::Device,
+    con: &mut console::Console,
+) -> Result<()> {
+    if let Some(settings) = logfile::settings(cfg, cmd_args)? {
+        let log = logfile::Log::open(&settings, Some(device), con.path())?;
+        out.info("Log", &format!("Logging to {}", log.path().display()))?;
+        con.set_log(log);
+    }
+    Ok(())
+}
+
+/// Returns the escape key for the interactive terminal: Ctrl-A unless set by --escape or the
+/// [console] section of .bobbin/config.
+fn terminal_escape(cfg: &Config, cmd_args: &ArgMatches) -> Result<u8> {
+    match cfg.console_escape(cmd_args) {
+        Some(value) => terminal::parse_escape(&value),
+        None => Ok(1),
+    }
+}
+
+/// Returns the ITM output, with the defmt table loaded if a port uses the defmt framing.
+fn itm_output(
+    cfg: &Config,
+    cmd_args: &ArgMatches,
+    out: &mut Printer,
+    elf: Option<&Path>,
+) -> Result<itm::Output> {
+    let mut output = itm::output(cfg, cmd_args)?;
+    if output.uses_defmt() {
+        match defmt_table(cfg, cmd_args, out, elf)? {
+            Some(table) => output.set_defmt(table),
+            None => bail!("The defmt ITM port framing needs a program that uses defmt"),
+        }
+    }
+    Ok(output)
+}
+
+const RTT_PATH: &str = "rtt";
+const SEMIHOSTING_PATH: &str = "semihosting";
+
+/// Opens a console through the device's debugger: RTT or semihosting, depending on `path`. RTT
+/// uses the `_SEGGER_RTT` symbol in `elf` to find the control block if no address was given.
+fn debugger_console(
+    cfg: &Config,
+    args: &ArgMatches,
+    cmd_args: &ArgMatches,
+    out: &mut Printer,
+    device: &device::Device,
+    path: &str,
+    elf: Option<&Path>,
+) -> Result<console::Console> {
+    let dbg = if let Some(dbg) = device.debugger_type() {
+        out.verbose("debugger", dbg)?;
+        if let Some(dbg) = debugger::debugger(dbg) {
+            dbg
+        } else {
+            bail!("Unknown debugger type: {}", dbg);
+        }
+    } else {
+        bail!("Selected device has no associated debugger");
+    };
+    let port = if path == SEMIHOSTING_PATH {
+        out.info("Semihosting", "Resetting device with semihosting enabled")?;
+        dbg.semihosting(cfg, args, cmd_args, out, device)?
+    } else {
+        let settings = rtt::settings(cfg, cmd_args, elf)?;
+        out.verbose("rtt", &format!("{}", settings))?;
+        dbg.rtt(cfg, args, cmd_args, out, device, &settings)?
+    };
+    Ok(console::Console::new(port, path, &console::Settings::default()))
+}
+
 pub fn control(
     cfg: &Config,
     args: &ArgMatches,
@@ -374,6 +820,15 @@ pub fn control(
 
         if let Some(arg) = cfg.console(cmd_args) {
             cmd.arg("--console").arg(arg);
+        }
+        if let Some(arg) = cfg.console_speed(cmd_args) {
+            cmd.arg("--console-speed").arg(arg);
+        }
+        if let Some(arg) = cfg.console_format(cmd_args) {
+            cmd.arg("--console-format").arg(arg);
+        }
+        if let Some(arg) = cfg.console_flow_control(cmd_args) {
+            cmd.arg("--console-flow-control").arg(arg);
         }        
         out.verbose("Remote", &format!("{:?}", cmd))?;
 
@@ -382,7 +837,7 @@ pub fn control(
     }
 
     let filter = device::filter(cfg, args, cmd_args);
-    let mut devices = device::search(&filter)?;
+    let mut devices = device::search(cfg, &filter)?;
 
     let device = if devices.len() == 0 {
         bail!("No matching devices found.");
@@ -422,6 +877,113 @@ pub fn control(
     Ok(())
 }
 
+pub fn mem(
+    cfg: &Config,
+    args: &ArgMatches,
+    cmd_args: &ArgMatches,
+    out: &mut Printer,
+) -> Result<()> {
+    let (action, mem_args) = match cmd_args.subcommand() {
+        (action, Some(mem_args)) => (action, mem_args),
+        _ => bail!("Specify a mem subcommand: read, write or dump"),
+    };
+
+    if let Some(host) = args.value_of("host").or_else(|| cfg.filter_host()) {
+        if action == "dump" {
+            bail!("mem dump is not supported for remote hosts");
+        }
+        let mut cmd = Command::new("ssh");
+        cmd.arg("-q");
+        cmd.arg("-t");
+        cmd.arg(host);
+        cmd.arg(".cargo/bin/bobbin");
+        if let Some(device) = cfg.device(args) {
+            cmd.arg("--device").arg(device);
+        }
+        if args.is_present("verbose") {
+            cmd.arg("--verbose");
+        }
+        cmd.arg("mem").arg(action);
+        for name in &["address", "length", "value"] {
+            if let Some(v) = mem_args.value_of(name) {
+                cmd.arg(v);
+            }
+        }
+        if let Some(v) = mem_args.value_of("width") {
+            cmd.arg("--width").arg(v);
+        }
+        if let Some(arg) = cfg.jlink_device(mem_args) {
+            cmd.arg("--jlink-device").arg(arg);
+        }
+        if let Some(arg) = cfg.blackmagic_mode(mem_args) {
+            cmd.arg("--blackmagic-mode").arg(arg);
+        }
+        out.verbose("Remote", &format!("{:?}", cmd))?;
+
+        cmd.exec();
+        unreachable!()
+    }
+
+    let width = mem::Width::from_name(mem_args.value_of("width").unwrap_or("32"))?;
+    let addr = mem::parse_number("address", mem_args.value_of("address").unwrap())?;
+    let len = if let Some(v) = mem_args.value_of("length") {
+        mem::parse_number("length", v)? as usize
+    } else {
+        width.bytes()
+    };
+    mem::check_range(addr, len, width)?;
+    let value = if let Some(v) = mem_args.value_of("value") {
+        let value = mem::parse_number("value", v)?;
+        if value > width.max() {
+            bail!("Value 0x{:x} does not fit in {} bits", value, width.bits());
+        }
+        value
+    } else {
+        0
+    };
+
+    let filter = device::filter(cfg, args, mem_args);
+    let mut devices = device::search(cfg, &filter)?;
+
+    let device = if devices.is_empty() {
+        bail!("No matching devices found.");
+    } else if devices.len() > 1 {
+        bail!("More than one device found ({})", devices.len());
+    } else {
+        devices.remove(0)
+    };
+
+    let dbg = if let Some(dbg) = device.debugger_type() {
+        out.verbose("debugger", dbg)?;
+        if let Some(dbg) = debugger::debugger(dbg) {
+            dbg
+        } else {
+            bail!("Unknown debugger type: {}", dbg);
+        }
+    } else {
+        bail!("Selected device has no associated debugger");
+    };
+
+    match action {
+        "read" => {
+            let data = dbg.memory(cfg, args, mem_args, out, device.as_ref(), &mem::Op::Read { addr, len, width })?;
+            mem::hexdump(out, addr, &data, width)?;
+        }
+        "write" => {
+            dbg.memory(cfg, args, mem_args, out, device.as_ref(), &mem::Op::Write { addr, value, width })?;
+            out.info("Memory", &format!("Wrote 0x{:01$x} to 0x{2:08x}", value, width.bytes() * 2, addr))?;
+        }
+        "dump" => {
+            let path = mem_args.value_of("output").unwrap();
+            let data = dbg.memory(cfg, args, mem_args, out, device.as_ref(), &mem::Op::Read { addr, len, width })?;
+            File::create(path)?.write_all(&data)?;
+            out.info("Memory", &format!("Dumped {} bytes from 0x{:08x} to {}", data.len(), addr, path))?;
+        }
+        _ => bail!("Unknown mem subcommand: {}", action),
+    }
+    Ok(())
+}
+
 pub fn openocd(
     cfg: &Config,
     args: &ArgMatches,
@@ -448,7 +1010,7 @@ pub fn openocd(
     }
 
     let filter = device::filter(cfg, args, cmd_args);
-    let mut devices = device::search(&filter)?;
+    let mut devices = device::search(cfg, &filter)?;
 
     let device = if devices.len() == 0 {
         bail!("No matching devices found.");
@@ -492,7 +1054,7 @@ pub fn jlink(
     }
 
     let filter = device::filter(cfg, args, cmd_args);
-    let mut devices = device::search(&filter)?;
+    let mut devices = device::search(cfg, &filter)?;
 
     let device = if devices.len() == 0 {
         bail!("No matching devices found.");
@@ -548,7 +1110,7 @@ pub fn gdb(
     }
 
     let filter = device::filter(cfg, args, cmd_args);
-    let mut devices = device::search(&filter)?;
+    let mut devices = device::search(cfg, &filter)?;
 
     let device = if devices.len() == 0 {
         bail!("No matching devices found.");
@@ -600,13 +1162,52 @@ pub fn console(
         cmd.arg("console");
         if let Some(arg) = cfg.console(cmd_args) {
             cmd.arg("--console").arg(arg);
+        }
+        if let Some(arg) = cfg.console_speed(cmd_args) {
+            cmd.arg("--console-speed").arg(arg);
+        }
+        if let Some(arg) = cfg.console_format(cmd_args) {
+            cmd.arg("--console-format").arg(arg);
+        }
+        if let Some(arg) = cfg.console_flow_control(cmd_args) {
+            cmd.arg("--console-flow-control").arg(arg);
+        }
+        if cfg.console_rtt(cmd_args) {
+            cmd.arg("--rtt");
+        }
+        if let Some(arg) = cfg.rtt_address(cmd_args) {
+            cmd.arg("--rtt-address").arg(arg);
+        }
+        if let Some(arg) = cfg.rtt_channel(cmd_args) {
+            cmd.arg("--rtt-channel").arg(arg);
+        }
+        if let Some(arg) = cfg.jlink_device(cmd_args) {
+            cmd.arg("--jlink-device").arg(arg);
         }                
+        if cmd_args.is_present("defmt") {
+            bail!("--defmt is not supported for remote hosts");
+        }
+        if cmd_args.is_present("log") || cmd_args.is_present("log-dir") {
+            bail!("Console logs are not supported for remote hosts");
+        }
+        if let Some(arg) = cfg.console_escape(cmd_args) {
+            cmd.arg("--escape").arg(arg);
+        }
+        if cmd_args.is_present("line-mode") {
+            cmd.arg("--line-mode");
+        }
+        if let Some(arg) = cfg.console_view(cmd_args) {
+            cmd.arg("--view").arg(arg);
+        }
+        if cmd_args.is_present("send-file") {
+            bail!("--send-file is not supported for remote hosts");
+        }
         cmd.exec();
         unreachable!()
     }
 
     let filter = device::filter(cfg, args, cmd_args);
-    let mut devices = device::search(&filter)?;
+    let mut devices = device::search(cfg, &filter)?;
 
     let device = if devices.len() == 0 {
         bail!("No matching devices found.");
@@ -616,14 +1217,33 @@ pub fn console(
         devices.remove(0)
     };
 
-    if let Some(cdc_path) = cfg.console(cmd_args) {
-        let mut con = console::open(&cdc_path)?;
-        con.view()?
-    } else if let Some(cdc_path) = device.cdc_path() {
-        let mut con = console::open(&cdc_path)?;
-        con.view()?
+    let settings = console::settings(cfg, cmd_args, device.as_ref())?;
+    let elf = cmd_args.value_of("defmt").map(Path::new);
+    let mut con = if cfg.console_rtt(cmd_args) {
+        debugger_console(cfg, args, cmd_args, out, device.as_ref(), RTT_PATH, elf)?
+    } else if let Some(cdc_path) = cfg.console(cmd_args).or_else(|| device.cdc_path()) {
+        out.verbose("console", &format!("{} {}", cdc_path, settings))?;
+        let mut con = console::open(&cdc_path, &settings)?;
+        con.set_reconnect(console::Reconnect::new(cfg, device.as_ref(), cfg.console(cmd_args)));
+        con
     } else {
         bail!("No console found for device");
+    };
+    if let Some(table) = defmt_table(cfg, cmd_args, out, None)? {
+        con.set_defmt(table);
+    }
+    start_log(cfg, cmd_args, out, device.as_ref(), &mut con)?;
+    let view = view::view(cfg, cmd_args)?;
+    con.set_view(view);
+    if let Some(path) = cmd_args.value_of("send-file") {
+        let message = con.send_file(path)?;
+        out.info("Console", &message)?;
+    }
+    // Binary views send hex payloads a line at a time, so they don't use the terminal.
+    if view == view::View::Text && terminal::is_terminal() && !cmd_args.is_present("line-mode") {
+        con.terminal(terminal_escape(cfg, cmd_args)?)?;
+    } else {
+        con.view()?;
     }
 
     Ok(())
@@ -650,13 +1270,25 @@ pub fn screen(
         cmd.arg("screen");
         if let Some(arg) = cfg.console(cmd_args) {
             cmd.arg("--console").arg(arg);
-        }                
+        }
+        if let Some(arg) = cfg.console_speed(cmd_args) {
+            cmd.arg("--console-speed").arg(arg);
+        }
+        if let Some(arg) = cfg.console_format(cmd_args) {
+            cmd.arg("--console-format").arg(arg);
+        }
+        if let Some(arg) = cfg.console_flow_control(cmd_args) {
+            cmd.arg("--console-flow-control").arg(arg);
+        }
+        if let Some(arg) = cfg.console_escape(cmd_args) {
+            cmd.arg("--escape").arg(arg);
+        }
         cmd.exec();
         unreachable!()
     }
 
     let filter = device::filter(cfg, args, cmd_args);
-    let mut devices = device::search(&filter)?;
+    let mut devices = device::search(cfg, &filter)?;
 
     let device = if devices.len() == 0 {
         bail!("No matching devices found.");
@@ -666,30 +1298,101 @@ pub fn screen(
         devices.remove(0)
     };
 
-    let mut cmd = Command::new("screen");
-    if let Some(cdc_path) = cfg.console(cmd_args) {
-        cmd.arg(cdc_path);
-    } else if let Some(cdc_path) = device.cdc_path() {
-        cmd.arg(cdc_path);
-    } else {
-        bail!("No serial device path found");
-    }
-    cmd.arg("115200");
-    cmd.exec();
-
-    let status = cmd.status()?;
-    if !status.success() {
-        bail!("screen failed")
-    }
-    Ok(())
+    let settings = console::settings(cfg, cmd_args, device.as_ref())?;
+    let escape = terminal_escape(cfg, cmd_args)?;
+    let cdc_path = match cfg.console(cmd_args).or_else(|| device.cdc_path()) {
+        Some(cdc_path) => cdc_path,
+        None => bail!("No serial device path found"),
+    };
+    out.verbose("screen", &format!("{} {}", cdc_path, settings))?;
+    let mut con = console::open(&cdc_path, &settings)?;
+    con.set_reconnect(console::Reconnect::new(cfg, device.as_ref(), cfg.console(cmd_args)));
+    con.terminal(escape)
 }
 
+const VECTOR_NAMES: [&str; 16] = [
+    "Initial SP", "Reset", "NMI", "HardFault", "MemManage", "BusFault", "UsageFault", "Reserved",
+    "Reserved", "Reserved", "Reserved", "SVCall", "DebugMonitor", "Reserved", "PendSV", "SysTick",
+];
+
 pub fn objdump(
     cfg: &Config,
     args: &ArgMatches,
     cmd_args: &ArgMatches,
     out: &mut Printer,
 ) -> Result<()> {
+    let dst = if let Some(dst) = builder::build(cfg, args, cmd_args, out)? {
+        dst
+    } else {
+        bail!("No build output available for objdump");
+    };
+
+    let elf = elf::Elf::open(&dst)?;
+    let symbols = elf.symbols()?;
+    let limit = if let Some(v) = cmd_args.value_of("limit") {
+        v.parse::<usize>()?
+    } else {
+        20
+    };
+
+    writeln!(out, "{:16} {}", "File", dst.display())?;
+    writeln!(out, "{:16} {}", "Class", if elf.is_64 { "ELF64" } else { "ELF32" })?;
+    writeln!(out, "{:16} {}", "Machine", elf.machine_name())?;
+    writeln!(out, "{:16} 0x{:08x}", "Entry Point", elf.entry)?;
+    if let Some((sym, offset)) = elf::symbolize(&symbols, elf.entry & !1) {
+        writeln!(out, "{:16} {}+0x{:x}", "Entry Symbol", elf::demangle(&sym.name), offset)?;
+    }
+    writeln!(out)?;
+
+    writeln!(out, "{:3} {:24} {:10} {:10} {:5}", "Idx", "Section", "Address", "Size", "Flags")?;
+    for (i, s) in elf.sections.iter().enumerate().skip(1) {
+        writeln!(out, "{:3} {:24} 0x{:08x} 0x{:08x} {:5}", i, s.name, s.addr, s.size, s.flag_string())?;
+    }
+    writeln!(out)?;
+
+    if elf.machine == elf::EM_ARM {
+        let vectors = elf.section(".vector_table")
+            .or_else(|| elf.section(".isr_vector"))
+            .or_else(|| elf.section(".vectors"));
+        if let Some(vectors) = vectors {
+            writeln!(out, "Vector Table at 0x{:08x}", vectors.addr)?;
+            let count = (vectors.size / 4) as usize;
+            for (i, vector) in VECTOR_NAMES.iter().enumerate().take(count) {
+                let value = if let Some(value) = elf.read_u32(vectors.addr + (i as u64) * 4) {
+                    value as u64
+                } else {
+                    break;
+                };
+                let name = if i > 0 && value != 0 {
+                    elf::symbolize(&symbols, value & !1).map(|(sym, _)| elf::demangle(&sym.name))
+                } else {
+                    None
+                };
+                writeln!(out, "  {:14} 0x{:08x} {}", vector, value, name.unwrap_or_default())?;
+            }
+            if count > VECTOR_NAMES.len() {
+                writeln!(out, "  {} external interrupt vectors", count - VECTOR_NAMES.len())?;
+            }
+            writeln!(out)?;
+        }
+    }
+
+    let mut sized: Vec<&elf::Symbol> = symbols.iter().filter(|s| s.size > 0).collect();
+    sized.sort_by(|a, b| b.size.cmp(&a.size).then(a.value.cmp(&b.value)));
+    writeln!(out, "{:10} {:10} {:6} Symbol", "Size", "Address", "Type")?;
+    for s in sized.iter().take(limit) {
+        writeln!(out, "{:10} 0x{:08x} {:6} {}", s.size, s.addr(), s.kind_str(), elf::demangle(&s.name))?;
+    }
+
+    if cmd_args.is_present("disassemble") {
+        writeln!(out)?;
+        let mut cmd = Command::new("arm-none-eabi-objdump");
+        cmd.arg("--disassemble").arg("--demangle").arg(&dst);
+        out.verbose("objdump", &format!("{:?}", cmd))?;
+        if !cmd.status()?.success() {
+            bail!("arm-none-eabi-objdump failed");
+        }
+    }
     Ok(())
 }
 
@@ -699,7 +1402,18 @@ pub fn itm(
     cmd_args: &ArgMatches,
     out: &mut Printer,
 ) -> Result<()> {
+    if let Some(path) = cmd_args.value_of("itm-replay") {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        let mut output = itm_output(cfg, cmd_args, out, None)?;
+        output.write(&data)?;
+        return output.finish()
+    }
+
     if let Some(host) = args.value_of("host").or_else(|| cfg.filter_host()) {
+        if cmd_args.is_present("itm-capture") {
+            bail!("--itm-capture is not supported for remote hosts");
+        }
         let mut cmd = Command::new("ssh");
         cmd.arg("-q");
         cmd.arg("-t");
@@ -712,12 +1426,35 @@ pub fn itm(
             cmd.arg("--verbose");
         }                
         cmd.arg("itm");
+        if let Some(v) = cfg.itm_target_clock(cmd_args) {
+            cmd.arg("--itm-target-clock").arg(v);
+        }
+        if let Some(v) = cfg.itm_trace_clock(cmd_args) {
+            cmd.arg("--itm-trace-clock").arg(v);
+        }
+        if let Some(v) = cfg.itm_output(cmd_args) {
+            cmd.arg("--itm-output").arg(v);
+        }
+        if let Some(ports) = cmd_args.values_of("itm-port") {
+            for port in ports {
+                cmd.arg("--itm-port").arg(port);
+            }
+        }
+        if let Some(v) = cfg.itm_enable_mask(cmd_args) {
+            cmd.arg("--itm-enable-mask").arg(v);
+        }
+        if let Some(v) = cfg.itm_privilege_mask(cmd_args) {
+            cmd.arg("--itm-privilege-mask").arg(v);
+        }
+        if cmd_args.is_present("defmt") {
+            bail!("--defmt is not supported for remote hosts");
+        }
         cmd.exec();
         unreachable!()
     }
 
     let filter = device::filter(cfg, args, cmd_args);
-    let mut devices = device::search(&filter)?;
+    let mut devices = device::search(cfg, &filter)?;
 
     let device = if devices.len() == 0 {
         bail!("No matching devices found.");
@@ -729,19 +1466,164 @@ pub fn itm(
 
     if device.can_trace_itm() {
         out.info("ITM", "Starting ITM Trace")?;
-        let target_clk = if let Some(v) = cmd_args.value_of("itm-target-clock") {
-            v.parse::<u32>()?
-        } else {
-            if let Some(v) = cfg.itm_target_clock() {
-                v
-            } else {
-                bail!("itm-target-clock is required for ITM trace.")
-            }
-        };
-        let trace_clk = 2_000_000;
-        device.trace_itm(target_clk, trace_clk)?;
+        let mut output = itm_output(cfg, cmd_args, out, None)?;
+        let trace = itm::trace_config(cfg, cmd_args, output.mode(), device.as_ref(), out)?;
+        device.trace_itm(&trace, &mut |data| output.write(data).map(|_| true))?;
     } else {
         bail!("Currently selected device does not support ITM trace");
     }
     Ok(())
 }
+
+pub fn fault(
+    cfg: &Config,
+    args: &ArgMatches,
+    cmd_args: &ArgMatches,
+    out: &mut Printer,
+) -> Result<()> {
+    if args.value_of("host").or_else(|| cfg.filter_host()).is_some() {
+        bail!("fault is not supported for remote hosts");
+    }
+
+    let dst = builder::build(cfg, args, cmd_args, out)?;
+
+    let filter = device::filter(cfg, args, cmd_args);
+    let mut devices = device::search(cfg, &filter)?;
+
+    let device = if devices.is_empty() {
+        bail!("No matching devices found.");
+    } else if devices.len() > 1 {
+        bail!("More than one device found ({})", devices.len());
+    } else {
+        devices.remove(0)
+    };
+
+    fault_report(cfg, args, cmd_args, out, device.as_ref(), dst.as_deref())
+}
+
+/// Halts the device and prints its fault status and backtrace, symbolized with the ELF at `elf`
+/// if one is available.
+fn fault_report(
+    cfg: &Config,
+    args: &ArgMatches,
+    cmd_args: &ArgMatches,
+    out: &mut Printer,
+    device: &device::Device,
+    elf: Option<&Path>,
+) -> Result<()> {
+    let dbg = if let Some(dbg) = device.debugger_type() {
+        out.verbose("debugger", dbg)?;
+        if let Some(dbg) = debugger::debugger(dbg) {
+            dbg
+        } else {
+            bail!("Unknown debugger type: {}", dbg);
+        }
+    } else {
+        bail!("Selected device has no associated debugger");
+    };
+
+    let symbols = match elf.map(|path| elf::Elf::open(path).and_then(|e| e.symbols())) {
+        Some(Ok(symbols)) => symbols,
+        Some(Err(e)) => {
+            out.verbose("fault", &format!("No symbols available: {}", e))?;
+            Vec::new()
+        }
+        None => Vec::new(),
+    };
+
+    out.info("Fault", "Reading fault status")?;
+    let fault = fault::analyze(dbg.as_ref(), cfg, args, cmd_args, out, device)?;
+    fault::write(out, &fault, &symbols)
+}
+
+pub fn profile(
+    cfg: &Config,
+    args: &ArgMatches,
+    cmd_args: &ArgMatches,
+    out: &mut Printer,
+) -> Result<()> {
+    if args.value_of("host").or_else(|| cfg.filter_host()).is_some() {
+        bail!("profile is not supported for remote hosts");
+    }
+
+    let dst = if let Some(dst) = builder::build(cfg, args, cmd_args, out)? {
+        dst
+    } else {
+        bail!("No build output available for profile");
+    };
+    let symbols = elf::Elf::open(&dst)?.symbols()?;
+    let limit = if let Some(v) = cmd_args.value_of("limit") {
+        v.parse::<usize>()?
+    } else {
+        20
+    };
+
+    let mut profile = profile::Profile::new();
+    if let Some(path) = cmd_args.value_of("itm-replay") {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        profile.add(&data);
+    } else {
+        let duration = if let Some(v) = cmd_args.value_of("duration") {
+            v.parse::<u64>()?
+        } else {
+            5
+        };
+        let rate = if let Some(v) = cmd_args.value_of("rate") {
+            v.parse::<u32>()?
+        } else {
+            10_000
+        };
+
+        let filter = device::filter(cfg, args, cmd_args);
+        let mut devices = device::search(cfg, &filter)?;
+
+        let device = if devices.is_empty() {
+            bail!("No matching devices found.");
+        } else if devices.len() > 1 {
+            bail!("More than one device found ({})", devices.len());
+        } else {
+            devices.remove(0)
+        };
+
+        if !device.can_trace_itm() {
+            bail!("Currently selected device does not support ITM trace");
+        }
+
+        let mut trace = itm::trace_config(cfg, cmd_args, itm::Mode::Ports, device.as_ref(), out)?;
+        let sampling = itm::PcSampling::with_rate(trace.target_clk, rate);
+        trace.pc_sampling = Some(sampling);
+        out.info("Profile", &format!(
+            "Sampling every {} cycles ({} Hz) for {} seconds",
+            sampling.period(),
+            trace.target_clk / sampling.period(),
+            duration,
+        ))?;
+
+        let mut capture = if let Some(path) = cmd_args.value_of("itm-capture") {
+            Some(File::create(path)?)
+        } else {
+            None
+        };
+        let start = Instant::now();
+        device.trace_itm(&trace, &mut |data| {
+            if let Some(ref mut capture) = capture {
+                capture.write_all(data)?;
+            }
+            profile.add(data);
+            Ok(start.elapsed() < Duration::from_secs(duration))
+        })?;
+    }
+
+    out.info("Profile", &format!("{} samples from {}", profile.total(), dst.display()))?;
+    if profile.overflows() > 0 {
+        out.info("Profile", &format!(
+            "{} trace overflows; some samples were lost (try a lower --rate)",
+            profile.overflows(),
+        ))?;
+    }
+    if let Some(path) = cmd_args.value_of("folded") {
+        profile.write_folded(&mut File::create(path)?, &symbols)?;
+    }
+    profile.write_flat(out, &symbols, limit)
+}
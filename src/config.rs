@@ -1,4 +1,4 @@
-use bobbin_config::BobbinConfig;
+use bobbin_config::{BobbinConfig, DevicesConfig, DeviceConfig, ClockConfig, ItmConfig, ItmPortConfig, TestConfig, TestMarkerConfig};
 use cargo_config::CargoConfig;
 use clap::ArgMatches;
 use Result;
@@ -6,12 +6,14 @@ use toml;
 use std::io::Read;
 use std::fs::File;
 use std::path::Path;
+use std::env;
 
 
 pub fn config(args: &ArgMatches) -> Result<Config> {    
     Ok(Config {
         bobbin: read_bobbin()?,
         cargo: read_cargo()?,
+        devices: read_devices()?,
     })
 }
 
@@ -19,6 +21,7 @@ pub fn config(args: &ArgMatches) -> Result<Config> {
 pub struct Config {
     pub bobbin: Option<BobbinConfig>,
     pub cargo: Option<CargoConfig>,
+    pub devices: Option<DevicesConfig>,
 }
 
 impl Config {
@@ -53,6 +56,17 @@ impl Config {
         None
     }
 
+    pub fn cargo_target_dir(&self) -> Option<&str> {
+        if let Some(ref cargo) = self.cargo {
+            if let Some(ref build) = cargo.build {
+                if let Some(ref target_dir) = build.target_dir {
+                    return Some(target_dir)
+                }
+            }
+        }
+        None
+    }
+
     pub fn filter_host(&self) -> Option<&str> {    
         if let Some(ref bobbin) = self.bobbin {
             if let Some(ref filter) = bobbin.filter {
@@ -79,8 +93,23 @@ impl Config {
         None        
     }
 
+    pub fn cfg_devices(&self) -> Vec<DeviceConfig> {
+        let mut items: Vec<DeviceConfig> = Vec::new();
+        if let Some(ref bobbin) = self.bobbin {
+            if let Some(ref device) = bobbin.device {
+                items.extend(device.iter().cloned());
+            }
+        }
+        if let Some(ref devices) = self.devices {
+            if let Some(ref device) = devices.device {
+                items.extend(device.iter().cloned());
+            }
+        }
+        items
+    }
+
     pub fn console(&self, args: &ArgMatches) -> Option<String> {
-        args.value_of("console").or_else(|| self.cfg_console()).map(String::from)
+        args.value_of("console").or_else(|| args.value_of("console-path")).or_else(|| self.cfg_console()).map(String::from)
     }
 
     pub fn cfg_console(&self) -> Option<&str> {    
@@ -94,13 +123,291 @@ impl Config {
         None        
     }
 
-    pub fn itm_target_clock(&self) -> Option<u32> {
+    pub fn console_speed(&self, args: &ArgMatches) -> Option<String> {
+        args.value_of("console-speed").map(String::from).or_else(|| self.cfg_console_speed().map(|v| v.to_string()))
+    }
+
+    pub fn cfg_console_speed(&self) -> Option<u32> {
+        if let Some(ref bobbin) = self.bobbin {
+            if let Some(ref console) = bobbin.console {
+                return console.speed
+            }
+        }
+        None
+    }
+
+    pub fn console_format(&self, args: &ArgMatches) -> Option<String> {
+        args.value_of("console-format").or_else(|| self.cfg_console_format()).map(String::from)
+    }
+
+    pub fn cfg_console_format(&self) -> Option<&str> {
+        if let Some(ref bobbin) = self.bobbin {
+            if let Some(ref console) = bobbin.console {
+                if let Some(ref format) = console.format {
+                    return Some(format)
+                }
+            }
+        }
+        None
+    }
+
+    pub fn console_flow_control(&self, args: &ArgMatches) -> Option<String> {
+        args.value_of("console-flow-control").or_else(|| self.cfg_console_flow_control()).map(String::from)
+    }
+
+    pub fn cfg_console_flow_control(&self) -> Option<&str> {
+        if let Some(ref bobbin) = self.bobbin {
+            if let Some(ref console) = bobbin.console {
+                if let Some(ref flow_control) = console.flow_control {
+                    return Some(flow_control)
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns true if the console should use RTT through the debugger instead of a serial port.
+    pub fn console_rtt(&self, args: &ArgMatches) -> bool {
+        args.is_present("rtt") || self.cfg_console_rtt()
+    }
+
+    pub fn cfg_console_rtt(&self) -> bool {
+        if let Some(ref bobbin) = self.bobbin {
+            if let Some(ref console) = bobbin.console {
+                return console.rtt.unwrap_or(false)
+            }
+        }
+        false
+    }
+
+    pub fn rtt_address(&self, args: &ArgMatches) -> Option<String> {
+        args.value_of("rtt-address").or_else(|| self.cfg_rtt_address()).map(String::from)
+    }
+
+    pub fn cfg_rtt_address(&self) -> Option<&str> {
+        if let Some(ref bobbin) = self.bobbin {
+            if let Some(ref console) = bobbin.console {
+                if let Some(ref address) = console.rtt_address {
+                    return Some(address)
+                }
+            }
+        }
+        None
+    }
+
+    pub fn rtt_channel(&self, args: &ArgMatches) -> Option<String> {
+        args.value_of("rtt-channel").map(String::from).or_else(|| self.cfg_rtt_channel().map(|v| v.to_string()))
+    }
+
+    pub fn cfg_rtt_channel(&self) -> Option<u32> {
+        if let Some(ref bobbin) = self.bobbin {
+            if let Some(ref console) = bobbin.console {
+                return console.rtt_channel
+            }
+        }
+        None
+    }
+
+    /// Returns true if the console should be the semihosting console of the target program.
+    pub fn console_semihosting(&self, args: &ArgMatches) -> bool {
+        args.is_present("semihosting") || self.cfg_console_semihosting()
+    }
+
+    pub fn cfg_console_semihosting(&self) -> bool {
+        if let Some(ref bobbin) = self.bobbin {
+            if let Some(ref console) = bobbin.console {
+                return console.semihosting.unwrap_or(false)
+            }
+        }
+        false
+    }
+
+    /// Returns true if console output from a program that uses defmt should be decoded.
+    pub fn console_defmt(&self, args: &ArgMatches) -> bool {
+        !args.is_present("no-defmt") && self.cfg_console_defmt()
+    }
+
+    pub fn cfg_console_defmt(&self) -> bool {
+        if let Some(ref bobbin) = self.bobbin {
+            if let Some(ref console) = bobbin.console {
+                return console.defmt.unwrap_or(true)
+            }
+        }
+        true
+    }
+
+    pub fn console_log_dir(&self, args: &ArgMatches) -> Option<String> {
+        args.value_of("log-dir").or_else(|| self.cfg_console_log_dir()).map(String::from)
+    }
+
+    pub fn cfg_console_log_dir(&self) -> Option<&str> {
+        if let Some(ref bobbin) = self.bobbin {
+            if let Some(ref console) = bobbin.console {
+                if let Some(ref dir) = console.log_dir {
+                    return Some(dir)
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns true if the lines sent to the device should be written to the session log.
+    pub fn console_log_input(&self, args: &ArgMatches) -> bool {
+        args.is_present("log-input") || self.cfg_console_log_input()
+    }
+
+    pub fn cfg_console_log_input(&self) -> bool {
+        if let Some(ref bobbin) = self.bobbin {
+            if let Some(ref console) = bobbin.console {
+                return console.log_input.unwrap_or(false)
+            }
+        }
+        false
+    }
+
+    pub fn console_log_max_size(&self, args: &ArgMatches) -> Option<String> {
+        args.value_of("log-max-size").or_else(|| self.cfg_console_log_max_size()).map(String::from)
+    }
+
+    pub fn cfg_console_log_max_size(&self) -> Option<&str> {
+        if let Some(ref bobbin) = self.bobbin {
+            if let Some(ref console) = bobbin.console {
+                if let Some(ref size) = console.log_max_size {
+                    return Some(size)
+                }
+            }
+        }
+        None
+    }
+
+    pub fn cfg_console_log_keep(&self) -> Option<u32> {
+        if let Some(ref bobbin) = self.bobbin {
+            if let Some(ref console) = bobbin.console {
+                return console.log_keep
+            }
+        }
+        None
+    }
+
+    /// Returns the key that starts a local command in the interactive terminal.
+    pub fn console_escape(&self, args: &ArgMatches) -> Option<String> {
+        args.value_of("escape").or_else(|| self.cfg_console_escape()).map(String::from)
+    }
+
+    pub fn cfg_console_escape(&self) -> Option<&str> {
+        if let Some(ref bobbin) = self.bobbin {
+            if let Some(ref console) = bobbin.console {
+                if let Some(ref escape) = console.escape {
+                    return Some(escape)
+                }
+            }
+        }
+        None
+    }
+
+    pub fn console_view(&self, args: &ArgMatches) -> Option<String> {
+        args.value_of("view").or_else(|| self.cfg_console_view()).map(String::from)
+    }
+
+    pub fn cfg_console_view(&self) -> Option<&str> {
+        if let Some(ref bobbin) = self.bobbin {
+            if let Some(ref console) = bobbin.console {
+                if let Some(ref view) = console.view {
+                    return Some(view)
+                }
+            }
+        }
+        None
+    }
+
+    fn cfg_test(&self) -> Option<&TestConfig> {
+        if let Some(ref bobbin) = self.bobbin {
+            if let Some(ref test) = bobbin.test {
+                return Some(test)
+            }
+        }
+        None
+    }
+
+    pub fn test_line_timeout(&self, args: &ArgMatches) -> Option<String> {
+        args.value_of("line-timeout").map(String::from)
+            .or_else(|| self.cfg_test().and_then(|t| t.line_timeout).map(|v| v.to_string()))
+    }
+
+    pub fn test_timeout(&self, args: &ArgMatches) -> Option<String> {
+        args.value_of("test-timeout").map(String::from)
+            .or_else(|| self.cfg_test().and_then(|t| t.timeout).map(|v| v.to_string()))
+    }
+
+    pub fn test_boot_timeout(&self, args: &ArgMatches) -> Option<String> {
+        args.value_of("boot-timeout").map(String::from)
+            .or_else(|| self.cfg_test().and_then(|t| t.boot_timeout).map(|v| v.to_string()))
+    }
+
+    pub fn cfg_test_markers(&self) -> Vec<TestMarkerConfig> {
+        if let Some(test) = self.cfg_test() {
+            if let Some(ref marker) = test.marker {
+                return marker.clone()
+            }
+        }
+        Vec::new()
+    }
+
+    fn cfg_itm(&self) -> Option<&ItmConfig> {
         if let Some(ref bobbin) = self.bobbin {
             if let Some(ref itm) = bobbin.itm {
-                return itm.target_clock
+                return Some(itm)
             }
         }
-        None        
+        None
+    }
+
+    pub fn itm_target_clock(&self, args: &ArgMatches) -> Option<String> {
+        args.value_of("itm-target-clock").map(String::from).or_else(|| self.cfg_itm_target_clock())
+    }
+
+    pub fn cfg_itm_target_clock(&self) -> Option<String> {
+        match self.cfg_itm().and_then(|itm| itm.target_clock.as_ref()) {
+            Some(ClockConfig::Hz(hz)) => Some(hz.to_string()),
+            Some(ClockConfig::Name(name)) => Some(name.clone()),
+            None => None,
+        }
+    }
+
+    pub fn itm_trace_clock(&self, args: &ArgMatches) -> Option<String> {
+        args.value_of("itm-trace-clock").map(String::from).or_else(|| self.cfg_itm_trace_clock().map(|v| v.to_string()))
+    }
+
+    pub fn cfg_itm_trace_clock(&self) -> Option<u32> {
+        self.cfg_itm().and_then(|itm| itm.trace_clock)
+    }
+
+    pub fn itm_output(&self, args: &ArgMatches) -> Option<String> {
+        args.value_of("itm-output").or_else(|| self.cfg_itm_output()).map(String::from)
+    }
+
+    pub fn cfg_itm_output(&self) -> Option<&str> {
+        self.cfg_itm().and_then(|itm| itm.output.as_deref())
+    }
+
+    pub fn itm_enable_mask(&self, args: &ArgMatches) -> Option<String> {
+        args.value_of("itm-enable-mask").or_else(|| self.cfg_itm_enable_mask()).map(String::from)
+    }
+
+    pub fn cfg_itm_enable_mask(&self) -> Option<&str> {
+        self.cfg_itm().and_then(|itm| itm.enable_mask.as_deref())
+    }
+
+    pub fn itm_privilege_mask(&self, args: &ArgMatches) -> Option<String> {
+        args.value_of("itm-privilege-mask").or_else(|| self.cfg_itm_privilege_mask()).map(String::from)
+    }
+
+    pub fn cfg_itm_privilege_mask(&self) -> Option<&str> {
+        self.cfg_itm().and_then(|itm| itm.privilege_mask.as_deref())
+    }
+
+    pub fn cfg_itm_ports(&self) -> Vec<ItmPortConfig> {
+        self.cfg_itm().and_then(|itm| itm.port.clone()).unwrap_or_default()
     }
 
     pub fn jlink_device(&self, args: &ArgMatches) -> Option<String> {
@@ -185,9 +492,21 @@ pub fn read_bobbin() -> Result<Option<BobbinConfig>> {
 }
 
 pub fn read_cargo() -> Result<Option<CargoConfig>> {
+    // Cargo uses .cargo/config if both it and .cargo/config.toml exist.
     if let Some(s) = read_file("./.cargo/config")? {
         Ok(Some(toml::from_str(&s)?))
+    } else if let Some(s) = read_file("./.cargo/config.toml")? {
+        Ok(Some(toml::from_str(&s)?))
     } else {
         Ok(None)
     }
 }
+
+pub fn read_devices() -> Result<Option<DevicesConfig>> {
+    if let Some(home) = env::home_dir() {
+        if let Some(s) = read_file(home.join(".bobbin").join("devices.toml"))? {
+            return Ok(Some(toml::from_str(&s)?))
+        }
+    }
+    Ok(None)
+}
@@ -1,32 +1,719 @@
-use serial::{self, SerialPort};
+use serial::{self, SerialPort, CharSize, Parity, StopBits, FlowControl};
 use clap::ArgMatches;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt;
 use std::time::{Duration, Instant};
-use std::io::{Read, Write};
+use std::fs::File;
+use std::path::Path;
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
 use std::process;
-use std::thread::spawn;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::thread::{self, spawn, JoinHandle};
 
+use termcolor::{ColorChoice, StandardStream};
+use termios;
+
+use bobbin_config::DeviceConfig;
+use config::Config;
+use defmt;
+use logfile;
+use terminal;
+use view;
+use report::{Marker, Outcome, TestRun};
+use device::{self, Device};
 use Result;
 
-pub fn open(path: &str) -> Result<Console> {
+/// Serial line settings for the console, defaulting to 115200 8N1 without flow control.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    pub speed: u32,
+    pub char_size: CharSize,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            speed: 115_200,
+            char_size: CharSize::Bits8,
+            parity: Parity::ParityNone,
+            stop_bits: StopBits::Stop1,
+            flow_control: FlowControl::FlowNone,
+        }
+    }
+}
+
+impl Settings {
+    pub fn set_speed(&mut self, value: &str) -> Result<()> {
+        self.speed = match value.parse::<u32>() {
+            Ok(speed) if speed > 0 => speed,
+            _ => bail!("Invalid console speed: {}", value),
+        };
+        Ok(())
+    }
+
+    /// Sets data bits, parity and stop bits from a string such as "8N1" or "7E2".
+    pub fn set_format(&mut self, value: &str) -> Result<()> {
+        let b = value.as_bytes();
+        if b.len() != 3 {
+            bail!("Invalid console format: {} (expected e.g. 8N1)", value);
+        }
+        self.char_size = match b[0] {
+            b'5' => CharSize::Bits5,
+            b'6' => CharSize::Bits6,
+            b'7' => CharSize::Bits7,
+            b'8' => CharSize::Bits8,
+            _ => bail!("Invalid console data bits: {}", b[0] as char),
+        };
+        self.parity = match b[1] {
+            b'N' | b'n' => Parity::ParityNone,
+            b'O' | b'o' => Parity::ParityOdd,
+            b'E' | b'e' => Parity::ParityEven,
+            _ => bail!("Invalid console parity: {}", b[1] as char),
+        };
+        self.stop_bits = match b[2] {
+            b'1' => StopBits::Stop1,
+            b'2' => StopBits::Stop2,
+            _ => bail!("Invalid console stop bits: {}", b[2] as char),
+        };
+        Ok(())
+    }
+
+    pub fn set_flow_control(&mut self, value: &str) -> Result<()> {
+        self.flow_control = match value {
+            "none" => FlowControl::FlowNone,
+            "software" => FlowControl::FlowSoftware,
+            "hardware" => FlowControl::FlowHardware,
+            _ => bail!("Invalid console flow control: {} (expected none, software or hardware)", value),
+        };
+        Ok(())
+    }
+
+    pub fn format(&self) -> String {
+        let data_bits = match self.char_size {
+            CharSize::Bits5 => '5',
+            CharSize::Bits6 => '6',
+            CharSize::Bits7 => '7',
+            CharSize::Bits8 => '8',
+        };
+        let parity = match self.parity {
+            Parity::ParityNone => 'N',
+            Parity::ParityOdd => 'O',
+            Parity::ParityEven => 'E',
+        };
+        let stop_bits = match self.stop_bits {
+            StopBits::Stop1 => '1',
+            StopBits::Stop2 => '2',
+        };
+        format!("{}{}{}", data_bits, parity, stop_bits)
+    }
+
+    pub fn flow_control_name(&self) -> &'static str {
+        match self.flow_control {
+            FlowControl::FlowNone => "none",
+            FlowControl::FlowSoftware => "software",
+            FlowControl::FlowHardware => "hardware",
+        }
+    }
+}
+
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} flow:{}", self.speed, self.format(), self.flow_control_name())
+    }
+}
+
+/// Resolves the console settings for a device.
+///
+/// The per-device defaults from the device table are applied first, then the [console] section
+/// of .bobbin/config, then the --console-speed, --console-format and --console-flow-control options.
+pub fn settings(cfg: &Config, cmd_args: &ArgMatches, device: &Device) -> Result<Settings> {
+    let mut settings = Settings::default();
+    if let Some(speed) = device.console_speed() {
+        settings.speed = speed;
+    }
+    if let Some(format) = device.console_format() {
+        settings.set_format(format)?;
+    }
+    if let Some(flow_control) = device.console_flow_control() {
+        settings.set_flow_control(flow_control)?;
+    }
+    if let Some(speed) = cfg.console_speed(cmd_args) {
+        settings.set_speed(&speed)?;
+    }
+    if let Some(format) = cfg.console_format(cmd_args) {
+        settings.set_format(&format)?;
+    }
+    if let Some(flow_control) = cfg.console_flow_control(cmd_args) {
+        settings.set_flow_control(&flow_control)?;
+    }
+    Ok(settings)
+}
+
+/// Timeouts for `bobbin test`. A timeout of None never expires.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timeouts {
+    /// Maximum time between lines of output.
+    pub line: Option<Duration>,
+    /// Maximum time for the entire test run.
+    pub test: Option<Duration>,
+    /// Maximum time before the first line of output.
+    pub boot: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            line: Some(Duration::from_secs(5)),
+            test: Some(Duration::from_secs(15)),
+            boot: None,
+        }
+    }
+}
+
+fn parse_timeout(name: &str, value: &str) -> Result<Option<Duration>> {
+    match value.parse::<u64>() {
+        Ok(0) => Ok(None),
+        Ok(secs) => Ok(Some(Duration::from_secs(secs))),
+        Err(_) => bail!("Invalid {} timeout: {} (expected seconds)", name, value),
+    }
+}
+
+/// Resolves the test timeouts from the [test] section of .bobbin/config and the --line-timeout,
+/// --test-timeout and --boot-timeout options. Values are in seconds and 0 disables a timeout.
+/// The boot timeout defaults to the line timeout.
+pub fn timeouts(cfg: &Config, cmd_args: &ArgMatches) -> Result<Timeouts> {
+    let mut timeouts = Timeouts::default();
+    if let Some(value) = cfg.test_line_timeout(cmd_args) {
+        timeouts.line = parse_timeout("line", &value)?;
+    }
+    if let Some(value) = cfg.test_timeout(cmd_args) {
+        timeouts.test = parse_timeout("test", &value)?;
+    }
+    if let Some(value) = cfg.test_boot_timeout(cmd_args) {
+        timeouts.boot = parse_timeout("boot", &value)?;
+    } else {
+        timeouts.boot = timeouts.line;
+    }
+    Ok(timeouts)
+}
+
+/// Spawns a thread that reads lines from stdin for forwarding to the console.
+pub fn stdin_lines() -> Receiver<String> {
+    let stdin = ::std::io::stdin();
+    let (stdin_tx, stdin_rx) = channel();
+
+    spawn(move || {
+        loop {
+            let mut stdin_input = String::new();
+            match stdin.read_line(&mut stdin_input) {
+                Ok(0) => {
+                    // process::exit(0)
+                },
+                Ok(_) => {
+                    let _ = stdin_tx.send(stdin_input);
+                },
+                Err(_) => {
+                    process::exit(1)
+                }
+            }
+
+        }
+    });
+    stdin_rx
+}
+
+/// A console transport: a serial port, or a console provided by the debugger (RTT or
+/// semihosting).
+pub trait Port: Read + Write {
+    /// Sets how long a read waits for data before timing out.
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()>;
+
+    /// Returns the exit code of the target program once it has exited and all of its output has
+    /// been read, for consoles that report it.
+    fn exit_code(&self) -> Option<i32> {
+        None
+    }
+
+    /// Sends a break condition, for serial ports.
+    fn send_break(&mut self) -> Result<()> {
+        bail!("This console does not support sending a break")
+    }
+
+    /// Sets the DTR control line, for serial ports.
+    fn set_dtr(&mut self, level: bool) -> Result<()> {
+        bail!("This console does not have a DTR line")
+    }
+
+    /// Sets the RTS control line, for serial ports.
+    fn set_rts(&mut self, level: bool) -> Result<()> {
+        bail!("This console does not have an RTS line")
+    }
+
+    /// Changes the baud rate, for serial ports.
+    fn set_speed(&mut self, speed: u32) -> Result<()> {
+        bail!("This console does not have a baud rate")
+    }
+}
+
+impl Port for serial::SystemPort {
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        SerialPort::set_timeout(self, timeout)?;
+        Ok(())
+    }
+
+    fn send_break(&mut self) -> Result<()> {
+        termios::tcsendbreak(self.as_raw_fd(), 0)?;
+        Ok(())
+    }
+
+    fn set_dtr(&mut self, level: bool) -> Result<()> {
+        SerialPort::set_dtr(self, level)?;
+        Ok(())
+    }
+
+    fn set_rts(&mut self, level: bool) -> Result<()> {
+        SerialPort::set_rts(self, level)?;
+        Ok(())
+    }
+
+    fn set_speed(&mut self, speed: u32) -> Result<()> {
+        self.reconfigure(&|s| s.set_baud_rate(serial::BaudRate::from_speed(speed as usize)))?;
+        Ok(())
+    }
+}
+
+enum Event {
+    Data(Vec<u8>),
+    Exit(i32),
+    Error(String),
+}
+
+/// The target side of a console run on a background thread by `spawn_port`.
+pub struct Link {
+    is_ready: Cell<bool>,
+    ready: Sender<::std::result::Result<(), String>>,
+    events: Sender<Event>,
+    input_rx: Receiver<Vec<u8>>,
+    input: RefCell<Vec<u8>>,
+    closed: Cell<bool>,
+}
+
+impl Link {
+    /// Signals that the console is connected, letting `spawn_port` return.
+    pub fn ready(&self) {
+        self.is_ready.set(true);
+        let _ = self.ready.send(Ok(()));
+    }
+
+    /// Sends output to the console, returning false if the console has been closed.
+    pub fn send(&self, data: Vec<u8>) -> bool {
+        if self.events.send(Event::Data(data)).is_err() {
+            self.closed.set(true);
+        }
+        !self.closed.get()
+    }
+
+    /// Reports that the target program has exited.
+    pub fn exit(&self, code: i32) {
+        let _ = self.events.send(Event::Exit(code));
+    }
+
+    fn poll(&self) {
+        loop {
+            match self.input_rx.try_recv() {
+                Ok(data) => self.input.borrow_mut().extend(data),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.closed.set(true);
+                    break
+                }
+            }
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.poll();
+        self.closed.get()
+    }
+
+    pub fn has_input(&self) -> bool {
+        self.poll();
+        !self.input.borrow().is_empty()
+    }
+
+    /// Passes the input typed into the console to `f`, which returns how many bytes it used.
+    pub fn consume_input<F>(&self, f: F) -> Result<()>
+        where F: FnOnce(&[u8]) -> Result<usize>
+    {
+        self.poll();
+        let mut input = self.input.borrow_mut();
+        if !input.is_empty() {
+            let n = f(&input)?;
+            input.drain(..n);
+        }
+        Ok(())
+    }
+}
+
+/// Runs `f` on a background thread and returns a console port connected to its `Link`. Returns
+/// once `f` calls `Link::ready`, or with the error that stopped `f` before then.
+pub fn spawn_port<F>(f: F) -> Result<Box<Port>>
+    where F: FnOnce(&Link) -> Result<()> + Send + 'static
+{
+    let (ready, ready_rx) = channel();
+    let (events, events_rx) = channel();
+    let (input_tx, input_rx) = channel();
+    let link = Link {
+        is_ready: Cell::new(false),
+        ready,
+        events,
+        input_rx,
+        input: RefCell::new(Vec::new()),
+        closed: Cell::new(false),
+    };
+    let handle = thread::spawn(move || {
+        if let Err(e) = f(&link) {
+            let message = format!("{}", e);
+            if link.is_ready.get() {
+                let _ = link.events.send(Event::Error(message));
+            } else {
+                let _ = link.ready.send(Err(message));
+            }
+        }
+    });
+    match ready_rx.recv() {
+        Ok(Ok(())) => {}
+        Ok(Err(message)) => bail!("{}", message),
+        Err(_) => bail!("Console stopped unexpectedly"),
+    }
+    Ok(Box::new(ChannelPort {
+        events_rx,
+        input_tx: Some(input_tx),
+        handle: Some(handle),
+        buffer: Vec::new(),
+        timeout: Duration::from_millis(100),
+        closed: false,
+        exit_code: None,
+    }))
+}
+
+/// A console port connected to a `Link` on a background thread.
+struct ChannelPort {
+    events_rx: Receiver<Event>,
+    input_tx: Option<Sender<Vec<u8>>>,
+    handle: Option<JoinHandle<()>>,
+    buffer: Vec<u8>,
+    timeout: Duration,
+    closed: bool,
+    exit_code: Option<i32>,
+}
+
+impl Read for ChannelPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffer.is_empty() {
+            if self.closed {
+                thread::sleep(self.timeout);
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "console closed"))
+            }
+            match self.events_rx.recv_timeout(self.timeout) {
+                Ok(Event::Data(data)) => self.buffer = data,
+                Ok(Event::Exit(code)) => {
+                    self.closed = true;
+                    self.exit_code = Some(code);
+                    return Err(io::Error::new(io::ErrorKind::BrokenPipe, "target program exited"))
+                }
+                Ok(Event::Error(message)) => {
+                    self.closed = true;
+                    let _ = writeln!(io::stderr(), "error: {}", message);
+                    return Err(io::Error::other(message))
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "console read timed out"))
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.closed = true;
+                    return Err(io::Error::new(io::ErrorKind::BrokenPipe, "console closed"))
+                }
+            }
+        }
+        let n = buf.len().min(self.buffer.len());
+        buf[..n].copy_from_slice(&self.buffer[..n]);
+        self.buffer.drain(..n);
+        Ok(n)
+    }
+}
+
+impl Write for ChannelPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.input_tx {
+            Some(ref tx) if tx.send(buf.to_vec()).is_ok() => Ok(buf.len()),
+            _ => Err(io::Error::new(io::ErrorKind::BrokenPipe, "console closed")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Port for ChannelPort {
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+}
+
+impl Drop for ChannelPort {
+    // Wait for the background thread to finish, so that the debug probe it was using is free.
+    fn drop(&mut self) {
+        self.input_tx = None;
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Finds a serial console again after its device has been disconnected, e.g. because it
+/// re-enumerated after being reset or reloaded.
+#[derive(Debug, Clone)]
+pub struct Reconnect {
+    table: Vec<DeviceConfig>,
+    hash: String,
+    path: Option<String>,
+}
+
+impl Reconnect {
+    /// `path` is the serial port given with --console, if any; otherwise the serial port of the
+    /// device with the same ID is looked up again.
+    pub fn new(cfg: &Config, device: &Device, path: Option<String>) -> Reconnect {
+        Reconnect { table: device::table(cfg), hash: device.hash(), path }
+    }
+
+    /// Returns the path of the serial port if the device is connected.
+    pub fn find(&self) -> Option<String> {
+        let devices = device::enumerate_table(&self.table).ok()?;
+        let device = devices.into_iter().find(|d| d.hash() == self.hash)?;
+        match self.path {
+            Some(ref path) if Path::new(path).exists() => Some(path.clone()),
+            Some(_) => None,
+            None => device.cdc_path(),
+        }
+    }
+}
+
+pub fn open(path: &str, settings: &Settings) -> Result<Console> {
     let mut port = try!(serial::open(path));
-    try!(port.reconfigure(&|settings| {
-        settings.set_baud_rate(serial::Baud115200).unwrap();
-        settings.set_flow_control(serial::FlowControl::FlowNone);
+    try!(port.reconfigure(&|s| {
+        s.set_baud_rate(serial::BaudRate::from_speed(settings.speed as usize))?;
+        s.set_char_size(settings.char_size);
+        s.set_parity(settings.parity);
+        s.set_stop_bits(settings.stop_bits);
+        s.set_flow_control(settings.flow_control);
         Ok(())
     }));
-    Ok(Console { port: port })
+    Ok(Console::new(Box::new(port), path, settings))
+}
+
+/// Opens the console, retrying for a few seconds while the serial port is unavailable, as it
+/// may be after the device has been reset or reloaded.
+///
+/// With `reconnect`, the serial port is looked up again on each attempt, in case the device
+/// re-enumerated with a different path, and the console will reconnect if it is disconnected.
+pub fn reopen(path: &str, settings: &Settings, reconnect: Option<&Reconnect>) -> Result<Console> {
+    let mut retries = 20;
+    loop {
+        let path = reconnect.and_then(|r| r.find()).unwrap_or_else(|| String::from(path));
+        match open(&path, settings) {
+            Ok(mut con) => {
+                if let Some(reconnect) = reconnect {
+                    con.set_reconnect(reconnect.clone());
+                }
+                return Ok(con)
+            }
+            Err(e) => {
+                if retries == 0 {
+                    return Err(e)
+                }
+                retries -= 1;
+                ::std::thread::sleep(Duration::from_millis(250));
+            }
+        }
+    }
 }
 
 pub struct Console {
-    port: serial::SystemPort,
+    port: Box<Port>,
+    path: String,
+    settings: Settings,
+    defmt: Option<defmt::Decoder>,
+    log: Option<logfile::Log>,
+    viewer: Option<view::Viewer>,
+    reconnect: Option<Reconnect>,
+    disconnected: bool,
+    timeout: Duration,
 }
 
 impl Console {
+    pub fn new(port: Box<Port>, path: &str, settings: &Settings) -> Console {
+        Console {
+            port: port,
+            path: String::from(path),
+            settings: *settings,
+            defmt: None,
+            log: None,
+            viewer: None,
+            reconnect: None,
+            disconnected: false,
+            timeout: Duration::from_millis(100),
+        }
+    }
+
+    /// Reconnects to the device when the serial port is disconnected, instead of ending the
+    /// session.
+    pub fn set_reconnect(&mut self, reconnect: Reconnect) {
+        self.reconnect = Some(reconnect);
+    }
+
+    pub fn reconnect(&self) -> Option<&Reconnect> {
+        self.reconnect.as_ref()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.timeout = timeout;
+        self.port.set_timeout(timeout)
+    }
+
+    /// Reads from the port. Once the serial port has been disconnected, reads time out until the
+    /// device is back and the port has been reopened.
+    fn read_port(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.disconnected && !self.try_reconnect() {
+            thread::sleep(Duration::from_millis(250));
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "console disconnected"))
+        }
+        let result = self.port.read(buf);
+        if self.reconnect.is_some() && is_disconnect(&result) {
+            self.disconnected = true;
+            let hash = self.reconnect.as_ref().map(|r| String::from(&r.hash[..8])).unwrap_or_default();
+            notice(&format!("Disconnected from {}; waiting for device {} to reconnect", self.path, hash));
+            self.log_note("disconnected");
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "console disconnected"))
+        }
+        result
+    }
+
+    /// Reopens the serial port if the device is back, returning true if it was.
+    fn try_reconnect(&mut self) -> bool {
+        let path = match self.reconnect.as_ref().and_then(|r| r.find()) {
+            Some(path) => path,
+            None => return false,
+        };
+        // The port may not be usable for a moment after it appears; try again on the next read.
+        let con = match open(&path, &self.settings) {
+            Ok(con) => con,
+            Err(_) => return false,
+        };
+        self.port = con.port;
+        if self.port.set_timeout(self.timeout).is_err() {
+            return false
+        }
+        self.path = path;
+        self.disconnected = false;
+        notice(&format!("Reconnected on {}", self.path));
+        let note = format!("reconnected on {}", self.path);
+        self.log_note(&note);
+        true
+    }
+
+    /// Writes to the port, discarding the data while the serial port is disconnected.
+    fn write_port(&mut self, data: &[u8]) -> Result<()> {
+        if !self.disconnected {
+            self.port.write_all(data)?;
+        }
+        Ok(())
+    }
+
+    fn log_note(&mut self, text: &str) {
+        if let Some(ref mut log) = self.log {
+            let _ = log.note(text);
+        }
+    }
+
+    /// Decodes the console output as defmt frames using `table`.
+    pub fn set_defmt(&mut self, table: defmt::Table) {
+        self.defmt = Some(defmt::Decoder::new(table));
+    }
+
+    /// Displays the console output with `view`, and sends lines typed into the console as hex
+    /// payloads framed for it.
+    pub fn set_view(&mut self, view: view::View) {
+        self.viewer = match view {
+            view::View::Text => None,
+            view => Some(view::Viewer::new(view)),
+        };
+    }
+
+    /// Writes the session to `log`.
+    pub fn set_log(&mut self, log: logfile::Log) {
+        self.log = Some(log);
+    }
+
+    /// Removes the session log, so that it can continue with another console.
+    pub fn take_log(&mut self) -> Option<logfile::Log> {
+        self.log.take()
+    }
+
+    fn log_received(&mut self, data: &[u8]) -> Result<()> {
+        match self.log {
+            Some(ref mut log) => log.received(data),
+            None => Ok(()),
+        }
+    }
+
+    fn log_frame(&mut self, frame: &defmt::Frame) -> Result<()> {
+        self.log_line(&frame.to_string())
+    }
+
+    fn log_line(&mut self, line: &str) -> Result<()> {
+        match self.log {
+            Some(ref mut log) => log.received_line(line),
+            None => Ok(()),
+        }
+    }
+
+    fn send(&mut self, data: &[u8]) -> Result<()> {
+        self.write_port(data)?;
+        self.log_sent(data)
+    }
+
+    fn log_sent(&mut self, data: &[u8]) -> Result<()> {
+        match self.log {
+            Some(ref mut log) => log.sent(data),
+            None => Ok(()),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
     pub fn clear(&mut self) -> Result<()> {
         let mut buf = [0u8; 1024];
-        self.port.set_timeout(Duration::from_millis(10))?;
+        self.set_timeout(Duration::from_millis(10))?;
         loop {
-            match self.port.read(&mut buf[..]) {
+            match self.read_port(&mut buf[..]) {
                 Ok(0) => return Ok(()),
                 Ok(_) => {}
                 Err(_) => return Ok(()),
@@ -35,64 +722,287 @@ impl Console {
     }
 
     pub fn view(&mut self) -> Result<()> {
-        self.port.set_timeout(Duration::from_millis(100))?;
+        let stdin_rx = stdin_lines();
+        self.view_until(&stdin_rx, || Ok(false))
+    }
+
+    /// Returns the exit code of the target program, if the console reports one and it has exited.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.port.exit_code()
+    }
+
+    /// Displays the console output and forwards lines from `stdin_rx` to the device until
+    /// `done` returns true or the target program exits.
+    pub fn view_until<F>(&mut self, stdin_rx: &Receiver<String>, mut done: F) -> Result<()>
+        where F: FnMut() -> Result<bool>
+    {
+        self.set_timeout(Duration::from_millis(100))?;
         let mut buf = [0u8; 1024];
-        let stdin = ::std::io::stdin();
         let mut stdout = ::std::io::stdout();
+        let mut color_stdout = StandardStream::stdout(ColorChoice::Always);
 
-        let (stdin_tx, stdin_rx) = ::std::sync::mpsc::channel();  
-        
-        spawn(move || {
-            loop {
-                let mut stdin_input = String::new();
-                match stdin.read_line(&mut stdin_input) {
-                    Ok(0) => {
-                        // process::exit(0)
-                    },
-                    Ok(_) => {
-                        let _ = stdin_tx.send(stdin_input);
-                    },
-                    Err(_) => {
-                        process::exit(1)
+        loop {
+            match self.read_port(&mut buf[..]) {
+                Ok(n) if self.viewer.is_some() => {
+                    let lines = match self.viewer {
+                        Some(ref mut viewer) => viewer.push(&buf[..n]),
+                        None => Vec::new(),
+                    };
+                    for line in lines {
+                        writeln!(stdout, "{}", line)?;
+                        self.log_line(&line)?;
                     }
                 }
-
-            }
-        });
-        
-        loop {
-            match self.port.read(&mut buf[..]) {
                 Ok(n) => {
-                    try!(stdout.write(&buf[..n]));
+                    let frames: Vec<defmt::Frame> = match self.defmt {
+                        Some(ref mut decoder) => {
+                            decoder.push(&buf[..n]);
+                            decoder.collect()
+                        }
+                        None => {
+                            try!(stdout.write(&buf[..n]));
+                            self.log_received(&buf[..n])?;
+                            Vec::new()
+                        }
+                    };
+                    for frame in frames {
+                        frame.write_color(&mut color_stdout)?;
+                        self.log_frame(&frame)?;
+                    }
                 }
                 Err(_) => {}
             }
             match stdin_rx.try_recv() {
+                Ok(ref s) if self.viewer.is_some() => {
+                    if let Err(e) = self.send_payload(s.trim()) {
+                        writeln!(io::stderr(), "error: {}", e)?;
+                    }
+                },
                 Ok(s) => {
-                    self.port.write(s.as_bytes())?;
+                    self.send(s.as_bytes())?;
                 },
                 Err(_) => {},
             }
+            if done()? || self.port.exit_code().is_some() {
+                return Ok(())
+            }
+        }
+    }
+
+    /// Runs an interactive terminal with stdin in raw mode: each key is sent to the device as it
+    /// is typed, and the local commands in `terminal::COMMANDS` are typed after `escape`. Returns
+    /// when the user quits, stdin is closed or the target program exits.
+    pub fn terminal(&mut self, escape: u8) -> Result<()> {
+        let _raw = terminal::RawMode::enable()?;
+        let keys_rx = terminal::stdin_bytes();
+        let mut keys: VecDeque<u8> = VecDeque::new();
+        let mut buf = [0u8; 1024];
+        let mut hex: Option<usize> = None;
+        let mut dtr = true;
+        let mut rts = true;
+        let mut typed: Vec<u8> = Vec::new();
+        let escape_name = terminal::key_name(escape);
+        self.set_timeout(Duration::from_millis(20))?;
+        notice(&format!("Terminal ready: {} q to quit, {} ? for help", escape_name, escape_name));
+
+        loop {
+            if let Ok(n) = self.read_port(&mut buf[..]) {
+                self.display(&buf[..n], &mut hex)?;
+            }
+            if self.port.exit_code().is_some() {
+                return Ok(())
+            }
+            loop {
+                match keys_rx.try_recv() {
+                    Ok(data) => keys.extend(data),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            }
+            let mut out: Vec<u8> = Vec::new();
+            while let Some(key) = keys.pop_front() {
+                if key != escape {
+                    out.push(key);
+                    continue
+                }
+                let command = match next_key(&keys_rx, &mut keys) {
+                    Some(command) => command,
+                    None => return Ok(()),
+                };
+                if command == escape {
+                    out.push(escape);
+                    continue
+                }
+                // Send what was typed before the command first.
+                self.write_port(&out)?;
+                self.log_typed(&mut typed, &out)?;
+                out.clear();
+                let result = match command {
+                    b'q' | b'x' => return Ok(()),
+                    b'b' => self.port.send_break().map(|_| String::from("Sent break")),
+                    b'd' => self.port.set_dtr(!dtr).map(|_| {
+                        dtr = !dtr;
+                        format!("DTR {}", if dtr { "on" } else { "off" })
+                    }),
+                    b'r' => self.port.set_rts(!rts).map(|_| {
+                        rts = !rts;
+                        format!("RTS {}", if rts { "on" } else { "off" })
+                    }),
+                    b's' => match prompt(&keys_rx, &mut keys, "Baud rate: ") {
+                        Some(value) => self.change_speed(&value),
+                        None => Ok(String::from("Cancelled")),
+                    },
+                    b'h' => {
+                        let on = hex.is_none();
+                        if let Some(column) = hex.take() {
+                            if column > 0 {
+                                println!();
+                            }
+                        } else {
+                            hex = Some(0);
+                        }
+                        Ok(format!("Hex view {}", if on { "on" } else { "off" }))
+                    }
+                    b'f' => match prompt(&keys_rx, &mut keys, "Send file: ") {
+                        Some(path) => self.send_file(&path),
+                        None => Ok(String::from("Cancelled")),
+                    },
+                    b'?' => Ok(help(&escape_name)),
+                    _ => Ok(format!("Unknown command; {} ? for help", escape_name)),
+                };
+                match result {
+                    Ok(message) => notice(&message),
+                    Err(e) => notice(&format!("error: {}", e)),
+                }
+            }
+            if !out.is_empty() {
+                self.write_port(&out)?;
+                self.log_typed(&mut typed, &out)?;
+            }
+        }
+    }
+
+    /// Displays data received from the device in the terminal.
+    fn display(&mut self, data: &[u8], hex: &mut Option<usize>) -> Result<()> {
+        let mut stdout = io::stdout();
+        let frames: Vec<defmt::Frame> = match self.defmt {
+            Some(ref mut decoder) => {
+                decoder.push(data);
+                decoder.collect()
+            }
+            None => Vec::new(),
+        };
+        if self.defmt.is_some() {
+            let mut color_stdout = StandardStream::stdout(ColorChoice::Always);
+            for frame in frames {
+                frame.write_color(&mut color_stdout)?;
+                self.log_frame(&frame)?;
+            }
+            return Ok(())
+        }
+        self.log_received(data)?;
+        match *hex {
+            Some(ref mut column) => stdout.write_all(terminal::hex_dump(data, column).as_bytes())?,
+            None => stdout.write_all(data)?,
+        }
+        stdout.flush()?;
+        Ok(())
+    }
+
+    /// Logs keys typed into the terminal a line at a time.
+    fn log_typed(&mut self, typed: &mut Vec<u8>, data: &[u8]) -> Result<()> {
+        if self.log.is_none() {
+            return Ok(())
+        }
+        for b in data {
+            match *b {
+                b'\r' | b'\n' => {
+                    typed.push(b'\n');
+                    self.log_sent(typed)?;
+                    typed.clear();
+                }
+                0x08 | 0x7f => {
+                    typed.pop();
+                }
+                _ => typed.push(*b),
+            }
+        }
+        Ok(())
+    }
+
+    fn change_speed(&mut self, value: &str) -> Result<String> {
+        let mut settings = self.settings;
+        settings.set_speed(value.trim())?;
+        self.port.set_speed(settings.speed)?;
+        self.settings = settings;
+        Ok(format!("Baud rate {}", settings.speed))
+    }
+
+    /// Sends the contents of a file, as a single framed payload if a COBS or SLIP view is set.
+    pub fn send_file(&mut self, path: &str) -> Result<String> {
+        let mut data = Vec::new();
+        File::open(path.trim())?.read_to_end(&mut data)?;
+        let len = data.len();
+        if let Some(ref viewer) = self.viewer {
+            data = viewer.view().encode(&data);
         }
-        //Ok(())
+        let timeout = self.timeout;
+        self.set_timeout(Duration::from_secs(5))?;
+        let result = self.send(&data);
+        self.set_timeout(timeout)?;
+        result?;
+        Ok(format!("Sent {} bytes from {}", len, path.trim()))
     }
 
-    pub fn test(&mut self, args: &ArgMatches, cmd_args: &ArgMatches) -> Result<()> {
-        const LINE_TIMEOUT_MS: u64 = 5000;
-        const TEST_TIMEOUT_MS: u64 = 15000;
+    /// Sends a payload typed as hex bytes, framed for the view.
+    fn send_payload(&mut self, text: &str) -> Result<()> {
+        let payload = view::parse_hex(text)?;
+        if payload.is_empty() {
+            return Ok(())
+        }
+        let data = match self.viewer {
+            Some(ref viewer) => viewer.view().encode(&payload),
+            None => payload,
+        };
+        self.write_port(&data)?;
+        self.log_sent(format!("{}\n", text).as_bytes())
+    }
 
-        self.port.set_timeout(Duration::from_millis(100))?;
+    /// Runs the test protocol described in the report module until the run completes or times
+    /// out, echoing the console output to stdout.
+    pub fn test(&mut self, name: &str, timeouts: &Timeouts, markers: Vec<Marker>) -> Result<TestRun> {
+        self.set_timeout(Duration::from_millis(100))?;
         let mut buf = [0u8; 1024];
         let mut line: Vec<u8> = Vec::new();
+        let mut run = TestRun::new(name, markers);
         let start_time: Instant = Instant::now();
-        let mut line_time: Instant = start_time;
+        let mut line_time: Option<Instant> = None;
         loop {
-            match self.port.read(&mut buf[..]) {
+            match self.read_port(&mut buf[..]) {
+                Ok(n) if self.defmt.is_some() => {
+                    let frames: Vec<defmt::Frame> = match self.defmt {
+                        Some(ref mut decoder) => {
+                            decoder.push(&buf[..n]);
+                            decoder.collect()
+                        }
+                        None => Vec::new(),
+                    };
+                    for frame in frames {
+                        self.log_frame(&frame)?;
+                        if self.handle_frame(&mut run, &frame)?.is_some() {
+                            return Ok(run)
+                        }
+                        line_time = Some(Instant::now());
+                    }
+                }
                 Ok(n) => {
+                    self.log_received(&buf[..n])?;
                     for b in (&buf[..n]).iter() {
                         if *b == b'\n' {
-                            self.handle_line(line.as_ref())?;
-                            line_time = Instant::now();
+                            if self.handle_line(&mut run, line.as_ref())?.is_some() {
+                                return Ok(run)
+                            }
+                            line_time = Some(Instant::now());
                             line.clear();
                         } else {
                             line.push(*b);
@@ -101,34 +1011,110 @@ impl Console {
                 }
                 Err(_) => {}
             }
+            if let Some(code) = self.port.exit_code() {
+                if !line.is_empty() && self.handle_line(&mut run, line.as_ref())?.is_some() {
+                    return Ok(run)
+                }
+                println!("[exit] {}", code);
+                run.exit(code);
+                return Ok(run)
+            }
             let now = Instant::now();
-            if now.duration_since(line_time) > Duration::from_millis(LINE_TIMEOUT_MS) {
-                println!("[timeout:line]");
-                process::exit(1);
+            let (since, limit, outcome) = match line_time {
+                Some(t) => (t, timeouts.line, Outcome::LineTimeout),
+                None => (start_time, timeouts.boot, Outcome::BootTimeout),
+            };
+            if limit.is_some_and(|limit| now.duration_since(since) > limit) {
+                println!("[{}]", outcome.name());
+                run.timeout(outcome);
+                return Ok(run)
             }
-            if now.duration_since(start_time) > Duration::from_millis(TEST_TIMEOUT_MS) {
+            if timeouts.test.is_some_and(|limit| now.duration_since(start_time) > limit) {
                 println!("[timeout:test]");
-                process::exit(1);
+                run.timeout(Outcome::TestTimeout);
+                return Ok(run)
             }
         }
-        //Ok(())
     }
 
-    fn handle_line(&mut self, line: &[u8]) -> Result<()> {
+    fn handle_line(&mut self, run: &mut TestRun, line: &[u8]) -> Result<Option<Outcome>> {
         let mut out = ::std::io::stdout();
         let line_str = String::from_utf8_lossy(line);
+        let line_str = line_str.trim_end_matches('\r');
         out.write(line_str.as_bytes())?;
         out.write(b"\n")?;
         out.flush()?;
-        if line_str.starts_with("[done]") {
-            process::exit(0);
-        } else if line_str.starts_with("[fail]") {
-            process::exit(1);
-        } else if line_str.contains("[exception]") {
-            process::exit(2);
-        } else if line_str.contains("[panic]") {
-            process::exit(3);
+        Ok(run.line(line_str))
+    }
+
+    /// Displays a defmt frame and matches the test tags and markers against its message.
+    fn handle_frame(&mut self, run: &mut TestRun, frame: &defmt::Frame) -> Result<Option<Outcome>> {
+        let mut out = StandardStream::stdout(ColorChoice::Always);
+        frame.write_color(&mut out)?;
+        out.flush()?;
+        for line in frame.text.lines() {
+            if let Some(outcome) = run.line(line) {
+                return Ok(Some(outcome))
+            }
         }
-        Ok(())
+        Ok(None)
+    }
+}
+
+/// Returns true if a read result means that the serial port has gone away. A read that returns
+/// no data after the port has been polled as readable means it was hung up.
+fn is_disconnect(result: &io::Result<usize>) -> bool {
+    match *result {
+        Ok(n) => n == 0,
+        Err(ref e) => !matches!(e.kind(),
+            io::ErrorKind::TimedOut | io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock),
+    }
+}
+
+/// Prints a local message on the console.
+fn notice(message: &str) {
+    let mut out = io::stdout();
+    let _ = write!(out, "\n*** {}\n", message);
+    let _ = out.flush();
+}
+
+fn help(escape_name: &str) -> String {
+    let mut text = format!("Commands (type {} first):", escape_name);
+    for &(keys, description) in terminal::COMMANDS {
+        text.push_str(&format!("\n***   {:<6} {}", keys, description));
+    }
+    text.push_str(&format!("\n***   {:<6} send {}", escape_name, escape_name));
+    text
+}
+
+/// Returns the next key typed, waiting for one if necessary, or None if stdin has been closed.
+fn next_key(keys_rx: &Receiver<Vec<u8>>, keys: &mut VecDeque<u8>) -> Option<u8> {
+    while keys.is_empty() {
+        keys.extend(keys_rx.recv().ok()?);
+    }
+    keys.pop_front()
+}
+
+/// Reads a line typed into the terminal, echoing it. Returns None if the user cancels with
+/// Ctrl-C or Escape, or stdin is closed.
+fn prompt(keys_rx: &Receiver<Vec<u8>>, keys: &mut VecDeque<u8>, label: &str) -> Option<String> {
+    let mut out = io::stdout();
+    let mut line = String::new();
+    let _ = write!(out, "\n*** {}", label);
+    let _ = out.flush();
+    loop {
+        match next_key(keys_rx, keys)? {
+            b'\r' | b'\n' => return Some(line),
+            0x03 | 0x1b => return None,
+            0x08 | 0x7f if line.pop().is_some() => {
+                let _ = write!(out, "\x08 \x08");
+            }
+            b if (0x20..0x7f).contains(&b) => {
+                line.push(b as char);
+                let _ = write!(out, "{}", b as char);
+            }
+            _ => {}
+        }
+        let _ = out.flush();
     }
 }
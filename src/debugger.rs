@@ -3,21 +3,35 @@ use config::Config;
 use printer::Printer;
 use device::Device;
 
-use std::process::Command;
+use std::process::{Command, Stdio};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::env;
 
+use regex::Regex;
 use tempfile;
 use Result;
 
 use blackmagic::blackmagic_scan;
+use mem::{self, Width};
+use console::Port;
+use rtt;
+use semihosting;
+
+#[cfg(feature = "stlink")]
+use console;
+#[cfg(feature = "stlink")]
+use stlink;
 
 pub fn debugger(debugger_type: &str) -> Option<Box<Control>> {
     match debugger_type.to_lowercase().as_ref() {
         "openocd" => Some(Box::new(OpenOcdDebugger {})),
         "jlink" => Some(Box::new(JLinkDebugger {})),
         "blackmagic" => Some(Box::new(BlackMagicDebugger {})),
+        #[cfg(feature = "stlink")]
+        "stlink" => Some(Box::new(StLinkDebugger {})),
+        #[cfg(not(feature = "stlink"))]
+        "stlink" => Some(Box::new(OpenOcdDebugger {})),
         _ => None,
     }
 }
@@ -71,6 +85,165 @@ pub trait Control {
         out: &mut Printer,
         device: &Device,
     ) -> Result<()>;
+    fn probe_info(
+        &self,
+        cfg: &Config,
+        args: &ArgMatches,
+        cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+    ) -> Result<ProbeInfo>;
+    /// Reads or writes target memory. Reads return the bytes read; writes return no data.
+    fn memory(
+        &self,
+        cfg: &Config,
+        args: &ArgMatches,
+        cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+        op: &mem::Op,
+    ) -> Result<Vec<u8>>;
+    /// Reads core registers of the halted target, numbered as in the DCRSR REGSEL field: r0-r12,
+    /// sp, lr and pc are 0-15, followed by xPSR, MSP and PSP.
+    fn registers(
+        &self,
+        cfg: &Config,
+        args: &ArgMatches,
+        cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+        regs: &[u32],
+    ) -> Result<Vec<u32>>;
+    /// Opens an RTT channel on the running target as a console port.
+    fn rtt(
+        &self,
+        cfg: &Config,
+        args: &ArgMatches,
+        cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+        rtt: &rtt::Settings,
+    ) -> Result<Box<Port>> {
+        bail!("RTT is not supported by this debugger")
+    }
+    /// Resets the target and runs it with semihosting enabled, returning its console port. The
+    /// port reports the exit code of the target program when it exits.
+    fn semihosting(
+        &self,
+        cfg: &Config,
+        args: &ArgMatches,
+        cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+    ) -> Result<Box<Port>> {
+        bail!("Semihosting is not supported by this debugger")
+    }
+}
+
+/// Probe and target details reported by `bobbin info --probe`. Fields are None when the probe or
+/// its tool doesn't report them.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ProbeInfo {
+    pub firmware: Option<String>,
+    pub api: Option<String>,
+    pub voltage: Option<f32>,
+    pub mode: Option<String>,
+    pub core_id: Option<u32>,
+    pub cpuid: Option<u32>,
+}
+
+impl ProbeInfo {
+    /// Decodes the CPUID part number, variant and revision, e.g. "Cortex-M4 r0p1".
+    pub fn core(&self) -> Option<String> {
+        let cpuid = self.cpuid?;
+        let part = match (cpuid >> 4) & 0xfff {
+            0xc20 => "Cortex-M0",
+            0xc60 => "Cortex-M0+",
+            0xc21 => "Cortex-M1",
+            0xc23 => "Cortex-M3",
+            0xc24 => "Cortex-M4",
+            0xc27 => "Cortex-M7",
+            0xd20 => "Cortex-M23",
+            0xd21 => "Cortex-M33",
+            0xd22 => "Cortex-M55",
+            0xd23 => "Cortex-M85",
+            _ => return None,
+        };
+        Some(format!("{} r{}p{}", part, (cpuid >> 20) & 0xf, cpuid & 0xf))
+    }
+}
+
+/// Returns the first capture group of `pattern` in a tool's output.
+fn capture(output: &str, pattern: &str) -> Option<String> {
+    Regex::new(pattern).ok()
+        .and_then(|re| re.captures(output))
+        .and_then(|c| c.get(1))
+        .map(|m| m.as_str().trim().to_string())
+}
+
+fn capture_hex(output: &str, pattern: &str) -> Option<u32> {
+    capture(output, pattern).and_then(|v| u32::from_str_radix(v.trim_start_matches("0x"), 16).ok())
+}
+
+/// Collects the values from memory dump lines matched by `pattern`, whose second capture group is
+/// a whitespace-separated list of hex values, and returns the first `len` bytes.
+fn parse_dump(tool: &str, output: &str, pattern: &str, len: usize, width: Width) -> Result<Vec<u8>> {
+    let re = Regex::new(pattern).unwrap();
+    let mut values = Vec::new();
+    for c in re.captures_iter(output) {
+        for v in c[2].split_whitespace() {
+            if let Ok(value) = u32::from_str_radix(v.trim_start_matches("0x"), 16) {
+                values.push(value);
+            }
+        }
+    }
+    let count = len / width.bytes();
+    if values.len() < count {
+        bail!("Unable to read memory with {}: expected {} values, found {}", tool, count, values.len());
+    }
+    values.truncate(count);
+    Ok(mem::to_bytes(&values, width))
+}
+
+/// Returns the name of a core register as OpenOCD names it, and its label in the output of
+/// J-Link Commander's `regs` command.
+fn register_names(reg: u32) -> Result<(String, String)> {
+    let (name, label) = match reg {
+        0..=12 => return Ok((format!("r{}", reg), format!("R{}", reg))),
+        13 => ("sp", "SP(R13)"),
+        14 => ("lr", "R14(LR)"),
+        15 => ("pc", "PC"),
+        16 => ("xPSR", "XPSR"),
+        17 => ("msp", "MSP"),
+        18 => ("psp", "PSP"),
+        _ => bail!("Unknown core register {}", reg),
+    };
+    Ok((String::from(name), String::from(label)))
+}
+
+/// Returns the value of each register in `regs` from a tool's output, where `pattern` returns
+/// the regular expression that captures a register's value in hex.
+fn parse_registers<F>(tool: &str, output: &str, regs: &[u32], pattern: F) -> Result<Vec<u32>>
+    where F: Fn(u32) -> Result<String>
+{
+    let mut values = Vec::new();
+    for reg in regs {
+        match capture_hex(output, &pattern(*reg)?) {
+            Some(value) => values.push(value),
+            None => bail!("Unable to read core register {} with {} (run with --verbose for details)", reg, tool),
+        }
+    }
+    Ok(values)
+}
+
+/// Returns the combined stdout and stderr of a tool.
+fn tool_output(out: &mut Printer, name: &str, cmd: &mut Command) -> Result<String> {
+    out.verbose(name, &format!("{:?}", cmd))?;
+    let output = cmd.output()?;
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    out.verbose(name, &text)?;
+    Ok(text)
 }
 
 pub struct OpenOcdDebugger {}
@@ -107,6 +280,29 @@ impl OpenOcdDebugger {
         None
     }
 
+    /// Returns an openocd command that runs `actions` after init and then exits.
+    fn openocd(&self, device: &Device, actions: &[&str]) -> Result<Command> {
+        let mut cmd = self.server(device, actions)?;
+        cmd.arg("--command").arg("exit");
+        Ok(cmd)
+    }
+
+    /// Returns an openocd command that runs `actions` after init and keeps running.
+    fn server(&self, device: &Device, actions: &[&str]) -> Result<Command> {
+        let mut cmd = Command::new("openocd");
+        if let Some(openocd_cfg) = self.find_config(device) {
+            cmd.arg("--file").arg(openocd_cfg);
+        } else {
+            bail!("No openocd.cfg file was found.");
+        }                
+        cmd.arg("--command").arg(device.openocd_serial().unwrap());
+        cmd.arg("--command").arg("init");
+        for action in actions {
+            cmd.arg("--command").arg(action);
+        }
+        Ok(cmd)
+    }
+
     pub fn command(
         &self,
         cfg: &Config,
@@ -116,16 +312,7 @@ impl OpenOcdDebugger {
         device: &Device,
         action: &str,
     ) -> Result<()> {
-        let mut cmd = Command::new("openocd");
-        if let Some(openocd_cfg) = self.find_config(device) {
-            cmd.arg("--file").arg(openocd_cfg);
-        } else {
-            bail!("No openocd.cfg file was found.");
-        }                
-        cmd.arg("--command").arg(&device.openocd_serial().unwrap());
-        cmd.arg("--command").arg("init");
-        cmd.arg("--command").arg(action);
-        cmd.arg("--command").arg("exit");
+        let mut cmd = self.openocd(device, &[action])?;
 
         out.verbose("openocd", &format!("{:?}", cmd))?;
 
@@ -236,20 +423,138 @@ impl Control for OpenOcdDebugger {
         )?;
         self.command(cfg, args, cmd_args, out, device, "reset init")
     }
+    fn probe_info(
+        &self,
+        cfg: &Config,
+        args: &ArgMatches,
+        cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+    ) -> Result<ProbeInfo> {
+        let mut cmd = self.openocd(device, &["mdw 0xe000ed00"])?;
+        let text = tool_output(out, "openocd", &mut cmd)?;
+
+        let firmware = capture(&text, r"STLINK (V\S+)")
+            .or_else(|| capture(&text, r"CMSIS-DAP: FW Version = (\S+)"))
+            .or_else(|| capture(&text, r"Info : (J-Link .*)"));
+        let mode = if text.contains("SWD DPIDR") || text.contains("SWD IDCODE") {
+            Some(String::from("SWD"))
+        } else if text.contains("JTAG tap:") {
+            Some(String::from("JTAG"))
+        } else {
+            None
+        };
+        Ok(ProbeInfo {
+            firmware,
+            api: capture(&text, r"\(API (v\d+)\)"),
+            voltage: capture(&text, r"Target voltage: ([0-9.]+)").and_then(|v| v.parse().ok()),
+            mode,
+            core_id: capture_hex(&text, r"(?:SWD DPIDR|SWD IDCODE|tap/device found:) (0x[0-9a-fA-F]+)"),
+            cpuid: capture_hex(&text, r"0xe000ed00: ([0-9a-fA-F]{8})"),
+        })
+    }
+    fn memory(
+        &self,
+        cfg: &Config,
+        args: &ArgMatches,
+        cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+        op: &mem::Op,
+    ) -> Result<Vec<u8>> {
+        let suffix = |width| match width {
+            Width::Byte => "b",
+            Width::Half => "h",
+            Width::Word => "w",
+        };
+        match *op {
+            mem::Op::Read { addr, len, width } => {
+                let action = format!("md{} 0x{:08x} {}", suffix(width), addr, len / width.bytes());
+                let mut cmd = self.openocd(device, &[&action])?;
+                let text = tool_output(out, "openocd", &mut cmd)?;
+                parse_dump("openocd", &text, r"(?m)^0x([0-9a-fA-F]+): ((?:[0-9a-fA-F]+ ?)+)", len, width)
+            }
+            mem::Op::Write { addr, value, width } => {
+                let action = format!("mw{} 0x{:08x} 0x{:x}", suffix(width), addr, value);
+                let mut cmd = self.openocd(device, &[&action])?;
+                let text = tool_output(out, "openocd", &mut cmd)?;
+                if text.contains("Error:") {
+                    bail!("Unable to write memory with openocd (run with --verbose for details)");
+                }
+                Ok(Vec::new())
+            }
+        }
+    }
+    fn registers(
+        &self,
+        cfg: &Config,
+        args: &ArgMatches,
+        cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+        regs: &[u32],
+    ) -> Result<Vec<u32>> {
+        let mut actions = Vec::new();
+        for reg in regs {
+            actions.push(format!("reg {}", register_names(*reg)?.0));
+        }
+        let actions: Vec<&str> = actions.iter().map(|a| a.as_str()).collect();
+        let mut cmd = self.openocd(device, &actions)?;
+        let text = tool_output(out, "openocd", &mut cmd)?;
+        // e.g. "pc (/32): 0x08000466"
+        parse_registers("openocd", &text, regs, |reg| {
+            Ok(format!(r"(?m)^{} \(/32\): 0x([0-9a-fA-F]+)", register_names(reg)?.0))
+        })
+    }
+    fn rtt(
+        &self,
+        cfg: &Config,
+        args: &ArgMatches,
+        cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+        rtt: &rtt::Settings,
+    ) -> Result<Box<Port>> {
+        // OpenOCD searches for the control block itself; a known address is searched on its own.
+        let setup = match rtt.address {
+            Some(addr) => format!("rtt setup 0x{:08x} {} \"SEGGER RTT\"", addr, rtt::CONTROL_BLOCK_ID.len()),
+            None => format!("rtt setup 0x{:08x} 0x{:x} \"SEGGER RTT\"", rtt.scan_start, rtt.scan_size),
+        };
+        let server = format!("rtt server start {} {}", rtt::TCP_PORT, rtt.channel);
+        let mut cmd = self.server(device, &[&setup, "rtt start", &server])?;
+        if !out.is_verbose() {
+            cmd.stdout(Stdio::null()).stderr(Stdio::null());
+        }
+        out.verbose("openocd", &format!("{:?}", cmd))?;
+        rtt::connect("openocd", cmd.spawn()?)
+    }
+    fn semihosting(
+        &self,
+        cfg: &Config,
+        args: &ArgMatches,
+        cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+    ) -> Result<Box<Port>> {
+        let mut cmd = self.server(device, &["reset halt", "arm semihosting enable", "resume"])?;
+        cmd.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+        out.verbose("openocd", &format!("{:?}", cmd))?;
+        semihosting::openocd(cmd.spawn()?, out.is_verbose())
+    }
 }
 
 
 pub struct JLinkDebugger {}
 impl JLinkDebugger {
-    fn command(
+    /// Returns a JLinkExe command that runs `actions` as a commander script. The script file is
+    /// removed when the returned temporary file is dropped.
+    fn script(
         &self,
         cfg: &Config,
-        args: &ArgMatches,
         cmd_args: &ArgMatches,
-        out: &mut Printer,
         device: &Device,
-        action: &str,
-    ) -> Result<()> {
+        actions: &[&str],
+    ) -> Result<(Command, tempfile::NamedTempFile)> {
 
         let jlink_dev = if let Some(jlink_dev) = cfg.jlink_device(cmd_args) {
             jlink_dev
@@ -259,7 +564,9 @@ impl JLinkDebugger {
 
         // Generate Script File
         let mut tmpfile: tempfile::NamedTempFile = tempfile::NamedTempFile::new().unwrap();
-        try!(writeln!(tmpfile, "{}", action));
+        for action in actions {
+            try!(writeln!(tmpfile, "{}", action));
+        }
         try!(writeln!(tmpfile, "exit"));
 
         // Execute Command
@@ -274,6 +581,19 @@ impl JLinkDebugger {
         );
         cmd.arg("-ExitOnError").arg("1");
         cmd.arg("-CommanderScript").arg(tmpfile.path());
+        Ok((cmd, tmpfile))
+    }
+
+    fn command(
+        &self,
+        cfg: &Config,
+        args: &ArgMatches,
+        cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+        action: &str,
+    ) -> Result<()> {
+        let (mut cmd, _tmpfile) = self.script(cfg, cmd_args, device, &[action])?;
 
         out.verbose("jlink", &format!("{:?}", cmd))?;
 
@@ -351,19 +671,121 @@ impl Control for JLinkDebugger {
         bail!("reset init is not supported for this debugger")
         //self.command(cfg, args, cmd_args, out, device, "r")
     }
+    fn probe_info(
+        &self,
+        cfg: &Config,
+        args: &ArgMatches,
+        cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+    ) -> Result<ProbeInfo> {
+        let (mut cmd, _tmpfile) = self.script(cfg, cmd_args, device, &["mem32 E000ED00 1"])?;
+        let text = tool_output(out, "jlink", &mut cmd)?;
+        let mode = if text.contains("Found SW-DP") {
+            Some(String::from("SWD"))
+        } else if text.contains("Found JTAG") {
+            Some(String::from("JTAG"))
+        } else {
+            None
+        };
+        Ok(ProbeInfo {
+            firmware: capture(&text, r"Firmware: (.*)"),
+            api: capture(&text, r"DLL version (V\S+)"),
+            voltage: capture(&text, r"VTref\s*=\s*([0-9.]+)").and_then(|v| v.parse().ok()),
+            mode,
+            core_id: capture_hex(&text, r"Found SW-DP with ID (0x[0-9A-Fa-f]+)"),
+            cpuid: capture_hex(&text, r"E000ED00 = ([0-9A-Fa-f]{8})"),
+        })
+    }
+    fn memory(
+        &self,
+        cfg: &Config,
+        args: &ArgMatches,
+        cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+        op: &mem::Op,
+    ) -> Result<Vec<u8>> {
+        match *op {
+            mem::Op::Read { addr, len, width } => {
+                // J-Link Commander reads numbers as hex.
+                let action = format!("mem{} 0x{:08X}, 0x{:X}", width.bits(), addr, len / width.bytes());
+                let (mut cmd, _tmpfile) = self.script(cfg, cmd_args, device, &[&action])?;
+                let text = tool_output(out, "jlink", &mut cmd)?;
+                parse_dump("JLinkExe", &text, r"(?m)^([0-9A-Fa-f]{8}) = ((?:[0-9A-Fa-f]+ ?)+)", len, width)
+            }
+            mem::Op::Write { addr, value, width } => {
+                let action = format!("w{} 0x{:08X}, 0x{:X}", width.bytes(), addr, value);
+                let (mut cmd, _tmpfile) = self.script(cfg, cmd_args, device, &[&action])?;
+                let text = tool_output(out, "jlink", &mut cmd)?;
+                if text.contains("ERROR") || text.contains("Could not write") {
+                    bail!("Unable to write memory with JLinkExe (run with --verbose for details)");
+                }
+                Ok(Vec::new())
+            }
+        }
+    }
+    fn registers(
+        &self,
+        cfg: &Config,
+        args: &ArgMatches,
+        cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+        regs: &[u32],
+    ) -> Result<Vec<u32>> {
+        let (mut cmd, _tmpfile) = self.script(cfg, cmd_args, device, &["regs"])?;
+        let text = tool_output(out, "jlink", &mut cmd)?;
+        // e.g. "SP(R13)= 20004FC8, MSP= 20004FC8, PSP= 00000000, R14(LR) = FFFFFFF9"
+        parse_registers("JLinkExe", &text, regs, |reg| {
+            Ok(format!(r"\b{}\s*=\s*([0-9A-Fa-f]{{8}})", ::regex::escape(&register_names(reg)?.1)))
+        })
+    }
+    fn rtt(
+        &self,
+        cfg: &Config,
+        args: &ArgMatches,
+        cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+        rtt: &rtt::Settings,
+    ) -> Result<Box<Port>> {
+        let jlink_dev = if let Some(jlink_dev) = cfg.jlink_device(cmd_args) {
+            jlink_dev
+        } else {
+            bail!("J-Link RTT requires that --jlink-device is specified");
+        };
+        // The J-Link software finds the control block itself, and serves channel 0 over telnet.
+        if rtt.channel != 0 {
+            bail!("J-Link RTT only supports channel 0");
+        }
+        let mut cmd = Command::new("JLinkGDBServer");
+        cmd.arg("-device").arg(jlink_dev);
+        cmd.arg("-if").arg("SWD");
+        cmd.arg("-speed").arg("4000");
+        cmd.arg("-select").arg(format!("USB={}", device.usb().serial_number));
+        cmd.arg("-nogui").arg("-nohalt").arg("-noir").arg("-silent");
+        cmd.arg("-RTTTelnetPort").arg(rtt::TCP_PORT.to_string());
+        if !out.is_verbose() {
+            cmd.stdout(Stdio::null()).stderr(Stdio::null());
+        }
+        out.verbose("jlink", &format!("{:?}", cmd))?;
+        rtt::connect("JLinkGDBServer", cmd.spawn()?)
+    }
 }
 
 pub struct BlackMagicDebugger {}
 impl BlackMagicDebugger {
-    fn command(
+    /// Returns a gdb command that attaches to the first target found by the probe and runs
+    /// `actions`.
+    fn gdb(
         &self,
         cfg: &Config,
         args: &ArgMatches,
         cmd_args: &ArgMatches,
-        out: &mut Printer,
         device: &Device,
-        action: &str,
-    ) -> Result<()> {
+        actions: &[&str],
+    ) -> Result<Command> {
         let blackmagic_scan = blackmagic_scan(cfg, args, cmd_args)?;
 
         let mut cmd = Command::new("arm-none-eabi-gdb");
@@ -374,8 +796,23 @@ impl BlackMagicDebugger {
             cmd.arg("-ex").arg(blackmagic_scan);
             cmd.arg("-ex").arg("attach 1");
         }
-        cmd.arg("-ex").arg(action);
+        for action in actions {
+            cmd.arg("-ex").arg(action);
+        }
         cmd.arg("-ex").arg("quit");
+        Ok(cmd)
+    }
+
+    fn command(
+        &self,
+        cfg: &Config,
+        args: &ArgMatches,
+        cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+        action: &str,
+    ) -> Result<()> {
+        let mut cmd = self.gdb(cfg, args, cmd_args, device, &[action])?;
         out.verbose("blackmagic", &format!("{:?}", cmd))?;
 
         if out.is_verbose() {
@@ -452,4 +889,345 @@ impl Control for BlackMagicDebugger {
         // self.command(cfg, args, cmd_args, out, device, "")
         bail!("reset init is not supported for this debugger")
     }
+    fn probe_info(
+        &self,
+        cfg: &Config,
+        args: &ArgMatches,
+        cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+    ) -> Result<ProbeInfo> {
+        let blackmagic_scan = blackmagic_scan(cfg, args, cmd_args)?;
+        let gdb_path = if let Some(gdb_path) = device.gdb_path() {
+            gdb_path
+        } else {
+            bail!("No GDB device found for this probe");
+        };
+
+        let mut cmd = Command::new("arm-none-eabi-gdb");
+        cmd.arg("-batch");
+        cmd.arg("-ex").arg("set confirm off");
+        cmd.arg("-ex").arg(format!("target extended-remote {}", gdb_path));
+        cmd.arg("-ex").arg("monitor version");
+        cmd.arg("-ex").arg(blackmagic_scan);
+        cmd.arg("-ex").arg("attach 1");
+        cmd.arg("-ex").arg("x/wx 0xe000ed00");
+        cmd.arg("-ex").arg("detach");
+        let text = tool_output(out, "blackmagic", &mut cmd)?;
+        let mode = if blackmagic_scan.contains("jtag") { "JTAG" } else { "SWD" };
+        Ok(ProbeInfo {
+            firmware: capture(&text, r"(Black Magic Probe.*)"),
+            api: None,
+            voltage: capture(&text, r"Target voltage: ([0-9.]+)").and_then(|v| v.parse().ok()),
+            mode: Some(String::from(mode)),
+            core_id: None,
+            cpuid: capture_hex(&text, r"0xe000ed00.*:\s+0x([0-9a-fA-F]{8})"),
+        })
+    }
+    fn memory(
+        &self,
+        cfg: &Config,
+        args: &ArgMatches,
+        cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+        op: &mem::Op,
+    ) -> Result<Vec<u8>> {
+        match *op {
+            mem::Op::Read { addr, len, width } => {
+                let unit = match width {
+                    Width::Byte => "b",
+                    Width::Half => "h",
+                    Width::Word => "w",
+                };
+                let action = format!("x/{}x{} 0x{:08x}", len / width.bytes(), unit, addr);
+                let mut cmd = self.gdb(cfg, args, cmd_args, device, &[&action])?;
+                cmd.arg("-batch");
+                let text = tool_output(out, "blackmagic", &mut cmd)?;
+                parse_dump("gdb", &text, r"(?m)^0x([0-9a-fA-F]+)[^:\n]*:((?:[ \t]+0x[0-9a-fA-F]+)+)", len, width)
+            }
+            mem::Op::Write { addr, value, width } => {
+                let ty = match width {
+                    Width::Byte => "unsigned char",
+                    Width::Half => "unsigned short",
+                    Width::Word => "unsigned int",
+                };
+                let action = format!("set {{{}}}0x{:08x} = 0x{:x}", ty, addr, value);
+                let mut cmd = self.gdb(cfg, args, cmd_args, device, &[&action])?;
+                cmd.arg("-batch");
+                let text = tool_output(out, "blackmagic", &mut cmd)?;
+                if text.contains("Cannot access memory") {
+                    bail!("Unable to write memory at 0x{:08x}", addr);
+                }
+                Ok(Vec::new())
+            }
+        }
+    }
+    fn registers(
+        &self,
+        cfg: &Config,
+        args: &ArgMatches,
+        cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+        regs: &[u32],
+    ) -> Result<Vec<u32>> {
+        let mut names = Vec::new();
+        for reg in regs {
+            names.push(register_names(*reg)?.0.to_lowercase());
+        }
+        let action = format!("info registers {}", names.join(" "));
+        let mut cmd = self.gdb(cfg, args, cmd_args, device, &[&action])?;
+        cmd.arg("-batch");
+        let text = tool_output(out, "blackmagic", &mut cmd)?;
+        // e.g. "pc             0x8000466           0x8000466 <HardFault>"
+        parse_registers("gdb", &text, regs, |reg| {
+            Ok(format!(r"(?m)^{}\s+0x([0-9a-fA-F]+)", register_names(reg)?.0.to_lowercase()))
+        })
+    }
+}
+
+/// Controls ST-Link probes directly over USB, without OpenOCD.
+#[cfg(feature = "stlink")]
+pub struct StLinkDebugger {}
+
+#[cfg(feature = "stlink")]
+impl StLinkDebugger {
+    fn command<T, F>(&self, out: &mut Printer, device: &Device, f: F) -> Result<T>
+        where F: FnOnce(&mut stlink::Debugger) -> Result<T>
+    {
+        out.verbose("stlink", &format!("Connecting to {:04x}:{:04x} {}",
+            device.usb().vendor_id, device.usb().product_id, device.usb().serial_number))?;
+        stlink::with_debugger(device, 0, 0, f)
+    }
+}
+
+/// Half-word transfers need ST-Link/V2 firmware J26 or later; older firmware rejects them with
+/// an unhelpful USB status.
+#[cfg(feature = "stlink")]
+fn check_half_word(d: &mut stlink::Debugger) -> Result<()> {
+    let version = d.version()?;
+    if version.stlink() == 2 && version.jtag() < 26 {
+        bail!("Half-word memory access requires ST-Link/V2 firmware J26 or later (found J{})", version.jtag());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "stlink")]
+impl Control for StLinkDebugger {
+    fn halt(
+        &self,
+        _cfg: &Config,
+        _args: &ArgMatches,
+        _cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+    ) -> Result<()> {
+        out.info("Halting", &format!("Halting Device"))?;
+        self.command(out, device, |d| d.halt())
+    }
+    fn resume(
+        &self,
+        _cfg: &Config,
+        _args: &ArgMatches,
+        _cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+    ) -> Result<()> {
+        out.info("Resuming", &format!("Resuming Device"))?;
+        self.command(out, device, |d| d.run())
+    }
+    fn reset(
+        &self,
+        _cfg: &Config,
+        _args: &ArgMatches,
+        _cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+    ) -> Result<()> {
+        out.info("Resetting", &format!("Resetting Device"))?;
+        self.command(out, device, |d| d.reset_run())
+    }
+    fn reset_halt(
+        &self,
+        _cfg: &Config,
+        _args: &ArgMatches,
+        _cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+    ) -> Result<()> {
+        out.info(
+            "Resetting",
+            &format!("Resetting and Halting Device"),
+        )?;
+        self.command(out, device, |d| d.reset_halt())
+    }
+    fn reset_run(
+        &self,
+        _cfg: &Config,
+        _args: &ArgMatches,
+        _cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+    ) -> Result<()> {
+        out.info(
+            "Resetting",
+            &format!("Resetting and Running Device"),
+        )?;
+        self.command(out, device, |d| d.reset_run())
+    }
+    fn reset_init(
+        &self,
+        _cfg: &Config,
+        _args: &ArgMatches,
+        _cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+    ) -> Result<()> {
+        // There are no target init scripts without OpenOCD; halting at the reset vector is the closest equivalent.
+        out.info(
+            "Resetting",
+            &format!("Resetting and Halting Device"),
+        )?;
+        self.command(out, device, |d| d.reset_halt())
+    }
+    fn probe_info(
+        &self,
+        _cfg: &Config,
+        _args: &ArgMatches,
+        _cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+    ) -> Result<ProbeInfo> {
+        out.verbose("stlink", &format!("Connecting to {:04x}:{:04x} {}",
+            device.usb().vendor_id, device.usb().product_id, device.usb().serial_number))?;
+        stlink::with_probe(device, 0, 0, |d| {
+            let version = d.version()?;
+            let mode = d.mode()?;
+            let voltage = d.voltage()?;
+            d.enter_debug_mode()?;
+            Ok(ProbeInfo {
+                firmware: Some(format!("V{}J{}S{}", version.stlink(), version.jtag(), version.swim())),
+                api: Some(format!("v{}", version.api())),
+                voltage: Some(voltage),
+                mode: Some(format!("{:?}", mode)),
+                core_id: Some(d.core_id()?),
+                cpuid: Some(d.read_32(stlink::SCS_CPUID)?),
+            })
+        })
+    }
+    fn memory(
+        &self,
+        _cfg: &Config,
+        _args: &ArgMatches,
+        _cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+        op: &mem::Op,
+    ) -> Result<Vec<u8>> {
+        match *op {
+            mem::Op::Read { addr, len, width } => {
+                self.command(out, device, |d| {
+                    if width == Width::Half {
+                        check_half_word(d)?;
+                    }
+                    let mut data = vec![0u8; len];
+                    // Byte reads are limited to 64 bytes per transfer.
+                    let chunk_size = if width == Width::Byte { 64 } else { 1024 };
+                    for (i, chunk) in data.chunks_mut(chunk_size).enumerate() {
+                        let a = addr + (i * chunk_size) as u32;
+                        match width {
+                            Width::Byte => d.read_mem8(a, chunk)?,
+                            Width::Half => d.read_mem16(a, chunk)?,
+                            Width::Word => {
+                                let mut words = vec![0u32; chunk.len() / 4];
+                                d.read_mem32(a, &mut words)?;
+                                chunk.copy_from_slice(&mem::to_bytes(&words, width));
+                            }
+                        }
+                    }
+                    Ok(data)
+                })
+            }
+            mem::Op::Write { addr, value, width } => {
+                self.command(out, device, |d| {
+                    match width {
+                        Width::Byte => d.write_mem8(addr, &[value as u8])?,
+                        Width::Half => {
+                            check_half_word(d)?;
+                            d.write_mem16(addr, &[value as u8, (value >> 8) as u8])?
+                        }
+                        Width::Word => d.write_mem32(addr, &[value])?,
+                    }
+                    Ok(Vec::new())
+                })
+            }
+        }
+    }
+    fn registers(
+        &self,
+        _cfg: &Config,
+        _args: &ArgMatches,
+        _cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+        regs: &[u32],
+    ) -> Result<Vec<u32>> {
+        self.command(out, device, |d| {
+            let mut values = Vec::new();
+            for reg in regs {
+                values.push(d.read_reg(*reg as u8)?);
+            }
+            Ok(values)
+        })
+    }
+    fn rtt(
+        &self,
+        _cfg: &Config,
+        _args: &ArgMatches,
+        _cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+        rtt: &rtt::Settings,
+    ) -> Result<Box<Port>> {
+        let ep = match device.stlink_endpoints() {
+            Some(ep) if ep.len() == 3 => ep.to_vec(),
+            _ => bail!("No ST-Link endpoints configured for {}", device.device_type().unwrap_or("this device")),
+        };
+        out.verbose("stlink", &format!("Connecting to {:04x}:{:04x} {}",
+            device.usb().vendor_id, device.usb().product_id, device.usb().serial_number))?;
+        let usb = device.usb().clone();
+        let settings = *rtt;
+        console::spawn_port(move |link| {
+            stlink::with_usb(&usb, &ep, 0, 0, |d| {
+                d.enter_debug_mode()?;
+                rtt::run(d, &settings, link)
+            })
+        })
+    }
+    fn semihosting(
+        &self,
+        _cfg: &Config,
+        _args: &ArgMatches,
+        _cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+    ) -> Result<Box<Port>> {
+        let ep = match device.stlink_endpoints() {
+            Some(ep) if ep.len() == 3 => ep.to_vec(),
+            _ => bail!("No ST-Link endpoints configured for {}", device.device_type().unwrap_or("this device")),
+        };
+        out.verbose("stlink", &format!("Connecting to {:04x}:{:04x} {}",
+            device.usb().vendor_id, device.usb().product_id, device.usb().serial_number))?;
+        let usb = device.usb().clone();
+        console::spawn_port(move |link| {
+            stlink::with_usb(&usb, &ep, 0, 0, |d| {
+                d.enter_debug_mode()?;
+                d.reset_halt()?;
+                link.ready();
+                d.run()?;
+                semihosting::run(d, link)
+            })
+        })
+    }
 }
@@ -0,0 +1,842 @@
+//! defmt log frame decoding.
+//!
+//! Firmware using `defmt` sends compact binary frames instead of text: a 16-bit index into the
+//! string table kept in the `.defmt` section of the ELF file, an optional timestamp and the
+//! encoded arguments of the format string. Each table entry is a symbol whose name is a JSON
+//! object holding its tag (the log level, or the kind of string) and format string, and whose
+//! value is its index. Frames are rzCOBS-encoded and separated by zero bytes, unless the firmware
+//! was built with the raw encoding.
+//!
+//! This decodes the defmt 0.3 wire format (versions 3 and 4). Source locations are not shown.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+use serde_json;
+use termcolor::{Color, ColorSpec, WriteColor};
+
+use elf::Elf;
+use Result;
+
+pub const SECTION: &str = ".defmt";
+
+const VERSION_PREFIX: &str = "_defmt_version_ = ";
+const ENCODING_PREFIX: &str = "_defmt_encoding_ = ";
+const SUPPORTED_VERSIONS: &[&str] = &["3", "4"];
+
+/// Limits how much undecodable data is buffered while waiting for the rest of a frame.
+const MAX_FRAME: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    fn from_tag(tag: &str) -> Option<Level> {
+        match tag {
+            "defmt_trace" => Some(Level::Trace),
+            "defmt_debug" => Some(Level::Debug),
+            "defmt_info" => Some(Level::Info),
+            "defmt_warn" => Some(Level::Warn),
+            "defmt_error" => Some(Level::Error),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Level::Trace => "TRACE",
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+        }
+    }
+
+    fn color(&self) -> Color {
+        match *self {
+            Level::Trace | Level::Debug => Color::White,
+            Level::Info => Color::Green,
+            Level::Warn => Color::Yellow,
+            Level::Error => Color::Red,
+        }
+    }
+}
+
+/// How frames are delimited in the byte stream.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Raw,
+    Rzcobs,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    tag: String,
+    format: String,
+}
+
+#[derive(Deserialize)]
+struct SymbolInfo {
+    tag: String,
+    data: String,
+}
+
+/// The string table of a defmt program.
+#[derive(Debug, Clone)]
+pub struct Table {
+    entries: BTreeMap<u16, Entry>,
+    timestamp: Option<String>,
+    encoding: Encoding,
+}
+
+impl Table {
+    /// Reads the table from an ELF file, returning None if the program doesn't use defmt.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Option<Table>> {
+        Table::parse(&Elf::open(path)?)
+    }
+
+    pub fn parse(elf: &Elf) -> Result<Option<Table>> {
+        let shndx = match elf.sections.iter().position(|s| s.name == SECTION) {
+            Some(i) => i as u16,
+            None => return Ok(None),
+        };
+        let mut table = Table { entries: BTreeMap::new(), timestamp: None, encoding: Encoding::Rzcobs };
+        for sym in elf.symbols()? {
+            if let Some(version) = sym.name.strip_prefix(VERSION_PREFIX) {
+                if !SUPPORTED_VERSIONS.contains(&version) {
+                    bail!("Unsupported defmt wire format version {}", version);
+                }
+            } else if let Some(encoding) = sym.name.strip_prefix(ENCODING_PREFIX) {
+                table.encoding = match encoding {
+                    "raw" => Encoding::Raw,
+                    "rzcobs" => Encoding::Rzcobs,
+                    _ => bail!("Unsupported defmt encoding {}", encoding),
+                };
+            } else if sym.shndx == shndx && sym.name.starts_with('{') {
+                let info: SymbolInfo = serde_json::from_str(&sym.name)?;
+                if info.tag == "defmt_timestamp" {
+                    table.timestamp = Some(info.data);
+                } else {
+                    table.entries.insert(sym.value as u16, Entry { tag: info.tag, format: info.data });
+                }
+            }
+        }
+        Ok(Some(table))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Decodes the frame at the start of `data`, returning it with the number of bytes used.
+    fn decode(&self, data: &[u8]) -> Decoded<(Frame, usize)> {
+        let mut r = Reader { data, pos: 0 };
+        let index = r.uint(2)? as u16;
+        let entry = self.entry(index)?;
+        let timestamp = match self.timestamp {
+            Some(ref format) => Some(self.format(format, &mut r)?),
+            None => None,
+        };
+        let text = self.format(&entry.format, &mut r)?;
+        let frame = Frame { level: Level::from_tag(&entry.tag), timestamp, text };
+        Ok((frame, r.pos))
+    }
+
+    fn entry(&self, index: u16) -> Decoded<&Entry> {
+        match self.entries.get(&index) {
+            Some(entry) => Ok(entry),
+            None => Err(Fail::Malformed(format!("unknown string index {}", index))),
+        }
+    }
+
+    /// Decodes the arguments of `format` and returns the formatted text. A format string with
+    /// `|`-separated variants (a derived enum) is preceded by the variant's discriminant.
+    fn format(&self, format: &str, r: &mut Reader) -> Decoded<String> {
+        let variants = split_variants(format);
+        let format = match variants.len() {
+            1 => format,
+            n => {
+                let discriminant = if n <= 256 { r.uint(1)? } else { r.uint(2)? } as usize;
+                match variants.get(discriminant) {
+                    Some(variant) => *variant,
+                    None => return Err(Fail::Malformed(format!("invalid enum discriminant {}", discriminant))),
+                }
+            }
+        };
+        let pieces = parse(format).map_err(Fail::Malformed)?;
+
+        // Arguments are encoded once each, in index order; bitfields of one argument share it, and
+        // only the bytes covering all of their ranges are sent.
+        let mut types: BTreeMap<usize, Type> = BTreeMap::new();
+        for piece in pieces.iter() {
+            if let Piece::Param(ref p) = *piece {
+                let ty = types.entry(p.index).or_insert(p.ty);
+                if let (&mut Type::BitField(ref mut start, ref mut end), Type::BitField(s, e)) = (ty, p.ty) {
+                    *start = (*start).min(s);
+                    *end = (*end).max(e);
+                }
+            }
+        }
+        let mut values = BTreeMap::new();
+        for (index, ty) in types {
+            values.insert(index, self.value(ty, r)?);
+        }
+
+        let mut text = String::new();
+        for piece in pieces.iter() {
+            match *piece {
+                Piece::Literal(ref s) => text.push_str(s),
+                Piece::Param(ref p) => text.push_str(&render(&values[&p.index], p)),
+            }
+        }
+        Ok(text)
+    }
+
+    /// Decodes a nested value: a string index followed by the arguments of that string.
+    fn nested(&self, r: &mut Reader) -> Decoded<String> {
+        let index = r.uint(2)? as u16;
+        let format = self.entry(index)?.format.clone();
+        self.format(&format, r)
+    }
+
+    fn value(&self, ty: Type, r: &mut Reader) -> Decoded<Value> {
+        Ok(match ty {
+            Type::Uint(n) => Value::Uint(r.uint(n)?),
+            Type::Int(n) => Value::Int(sign_extend(r.uint(n)?, n)),
+            Type::F32 => Value::F32(f32::from_bits(r.uint(4)? as u32)),
+            Type::F64 => Value::F64(f64::from_bits(r.uint(8)? as u64)),
+            Type::Bool => Value::Bool(r.uint(1)? != 0),
+            Type::Char => match ::std::char::from_u32(r.uint(4)? as u32) {
+                Some(c) => Value::Str(c.to_string()),
+                None => return Err(Fail::Malformed(String::from("invalid char"))),
+            },
+            Type::Str => {
+                let len = r.uint(4)? as usize;
+                Value::Str(String::from_utf8_lossy(r.take(len)?).into_owned())
+            }
+            Type::IStr => {
+                let index = r.uint(2)? as u16;
+                Value::Str(self.entry(index)?.format.clone())
+            }
+            Type::Bytes => {
+                let len = r.uint(4)? as usize;
+                Value::Bytes(r.take(len)?.to_vec())
+            }
+            Type::ByteArray(len) => Value::Bytes(r.take(len)?.to_vec()),
+            Type::BitField(start, end) => {
+                // The bytes below the lowest bitfield are not sent.
+                let lowest_byte = start as usize / 8;
+                let n = match (end as usize - 1) / 8 - lowest_byte + 1 {
+                    1 => 1,
+                    2 => 2,
+                    3..=4 => 4,
+                    5..=8 => 8,
+                    _ => 16,
+                };
+                Value::Uint(r.uint(n)? << (lowest_byte * 8))
+            }
+            Type::Format => Value::Str(self.nested(r)?),
+            Type::FormatSlice => {
+                let len = r.uint(4)? as usize;
+                self.list(len, r)?
+            }
+            Type::FormatArray(len) => self.list(len, r)?,
+            Type::FormatSequence => {
+                let mut text = String::new();
+                loop {
+                    let index = r.uint(2)? as u16;
+                    if index == 0 {
+                        break
+                    }
+                    let format = self.entry(index)?.format.clone();
+                    text.push_str(&self.format(&format, r)?);
+                }
+                Value::Str(text)
+            }
+        })
+    }
+
+    /// Decodes the elements of a slice or array of `Format` values: the string index of the
+    /// element type, sent once if there are any elements, followed by the arguments of each.
+    fn list(&self, len: usize, r: &mut Reader) -> Decoded<Value> {
+        let mut items = Vec::new();
+        if len > 0 {
+            let index = r.uint(2)? as u16;
+            let format = self.entry(index)?.format.clone();
+            for _ in 0..len {
+                items.push(self.format(&format, r)?);
+            }
+        }
+        Ok(Value::Str(format!("[{}]", items.join(", "))))
+    }
+}
+
+/// A decoded log frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// The log level, or None for `println!`.
+    pub level: Option<Level>,
+    pub timestamp: Option<String>,
+    pub text: String,
+}
+
+impl Frame {
+    /// Writes the frame as a line with the level in color.
+    pub fn write_color(&self, out: &mut WriteColor) -> io::Result<()> {
+        if let Some(ref timestamp) = self.timestamp {
+            write!(out, "{} ", timestamp)?;
+        }
+        if let Some(level) = self.level {
+            out.set_color(ColorSpec::new().set_bold(true).set_fg(Some(level.color())))?;
+            write!(out, "{:<5}", level.name())?;
+            out.reset()?;
+            write!(out, " ")?;
+        }
+        writeln!(out, "{}", self.text)
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref timestamp) = self.timestamp {
+            write!(f, "{} ", timestamp)?;
+        }
+        if let Some(level) = self.level {
+            write!(f, "{:<5} ", level.name())?;
+        }
+        write!(f, "{}", self.text)
+    }
+}
+
+/// Decodes frames from a stream of bytes. Frames without a firmware timestamp are stamped with
+/// the time since the decoder was created.
+pub struct Decoder {
+    table: Table,
+    buffer: Vec<u8>,
+    start: Instant,
+}
+
+impl Decoder {
+    pub fn new(table: Table) -> Self {
+        Decoder { table, buffer: Vec::new(), start: Instant::now() }
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    fn host_timestamp(&self, mut frame: Frame) -> Frame {
+        if frame.timestamp.is_none() {
+            let elapsed = self.start.elapsed();
+            frame.timestamp = Some(format!("{}.{:06}", elapsed.as_secs(), elapsed.subsec_micros()));
+        }
+        frame
+    }
+
+    fn malformed(&self, reason: &str) -> Frame {
+        self.host_timestamp(Frame { level: None, timestamp: None, text: format!("(malformed defmt frame: {})", reason) })
+    }
+
+    fn next_rzcobs(&mut self) -> Option<Frame> {
+        loop {
+            let end = self.buffer.iter().position(|b| *b == 0)?;
+            let encoded: Vec<u8> = self.buffer.drain(..=end).take(end).collect();
+            if encoded.is_empty() {
+                continue
+            }
+            let frame = match rzcobs_decode(&encoded) {
+                Some(data) => match self.table.decode(&data) {
+                    Ok((frame, _)) => self.host_timestamp(frame),
+                    Err(Fail::Incomplete) => self.malformed("truncated"),
+                    Err(Fail::Malformed(reason)) => self.malformed(&reason),
+                },
+                None => self.malformed("invalid rzCOBS encoding"),
+            };
+            return Some(frame)
+        }
+    }
+
+    fn next_raw(&mut self) -> Option<Frame> {
+        if self.buffer.is_empty() {
+            return None
+        }
+        match self.table.decode(&self.buffer) {
+            Ok((frame, n)) => {
+                self.buffer.drain(..n);
+                Some(self.host_timestamp(frame))
+            }
+            Err(Fail::Incomplete) if self.buffer.len() < MAX_FRAME => None,
+            Err(Fail::Incomplete) => {
+                self.buffer.clear();
+                Some(self.malformed("too long"))
+            }
+            // Raw frames can't be resynchronized, so the rest of the data is dropped.
+            Err(Fail::Malformed(reason)) => {
+                self.buffer.clear();
+                Some(self.malformed(&reason))
+            }
+        }
+    }
+}
+
+impl Iterator for Decoder {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        match self.table.encoding {
+            Encoding::Rzcobs => self.next_rzcobs(),
+            Encoding::Raw => self.next_raw(),
+        }
+    }
+}
+
+/// Decodes an rzCOBS (reverse zero-compressing COBS) frame, without its zero delimiter. The
+/// result may have trailing zeros, which the frame decoder ignores.
+fn rzcobs_decode(data: &[u8]) -> Option<Vec<u8>> {
+    let mut res = Vec::with_capacity(data.len() * 2);
+    let mut bytes = data.iter().rev().cloned();
+    while let Some(x) = bytes.next() {
+        match x {
+            0 => return None,
+            0x01..=0x7f => {
+                for i in 0..7 {
+                    if x & (1 << (6 - i)) == 0 {
+                        res.push(bytes.next()?);
+                    } else {
+                        res.push(0);
+                    }
+                }
+            }
+            0x80..=0xfe => {
+                res.push(0);
+                for _ in 0..(x & 0x7f) as usize + 7 {
+                    res.push(bytes.next()?);
+                }
+            }
+            0xff => {
+                for _ in 0..134 {
+                    res.push(bytes.next()?);
+                }
+            }
+        }
+    }
+    res.reverse();
+    Some(res)
+}
+
+enum Fail {
+    /// The data ends before the frame does.
+    Incomplete,
+    Malformed(String),
+}
+
+type Decoded<T> = ::std::result::Result<T, Fail>;
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Decoded<&'a [u8]> {
+        if self.data.len() - self.pos < n {
+            return Err(Fail::Incomplete)
+        }
+        let bytes = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    /// Reads an `n`-byte little-endian unsigned integer.
+    fn uint(&mut self, n: usize) -> Decoded<u128> {
+        Ok(self.take(n)?.iter().rev().fold(0, |v, b| v << 8 | *b as u128))
+    }
+}
+
+fn sign_extend(value: u128, bytes: usize) -> i128 {
+    let shift = 128 - bytes * 8;
+    ((value << shift) as i128) >> shift
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    /// An unsigned integer of the given size in bytes; `usize` is 4 bytes on the wire.
+    Uint(usize),
+    Int(usize),
+    F32,
+    F64,
+    Bool,
+    Char,
+    Str,
+    /// An interned string: the index of a table entry.
+    IStr,
+    Bytes,
+    ByteArray(usize),
+    /// Bits `start..end` of an unsigned integer.
+    BitField(u8, u8),
+    /// A value implementing `Format`.
+    Format,
+    FormatSlice,
+    FormatArray(usize),
+    FormatSequence,
+}
+
+impl Type {
+    fn parse(s: &str) -> ::std::result::Result<Type, String> {
+        Ok(match s {
+            "" | "?" => Type::Format,
+            "u8" => Type::Uint(1),
+            "u16" => Type::Uint(2),
+            "u32" | "usize" => Type::Uint(4),
+            "u64" => Type::Uint(8),
+            "u128" => Type::Uint(16),
+            "i8" => Type::Int(1),
+            "i16" => Type::Int(2),
+            "i32" | "isize" => Type::Int(4),
+            "i64" => Type::Int(8),
+            "i128" => Type::Int(16),
+            "f32" => Type::F32,
+            "f64" => Type::F64,
+            "bool" => Type::Bool,
+            "char" => Type::Char,
+            "str" | "__internal_Display" | "__internal_Debug" => Type::Str,
+            "istr" => Type::IStr,
+            "[u8]" => Type::Bytes,
+            "[?]" => Type::FormatSlice,
+            "__internal_FormatSequence" => Type::FormatSequence,
+            _ => {
+                if let Some((start, end)) = split_pair(s, "..") {
+                    match (start.parse::<u8>(), end.parse::<u8>()) {
+                        (Ok(start), Ok(end)) if start < end && end <= 128 => Type::BitField(start, end),
+                        _ => return Err(format!("invalid bitfield {}", s)),
+                    }
+                } else if let Some(inner) = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+                    let (item, len) = match split_pair(inner, ";") {
+                        Some((item, len)) => (item.trim(), len.trim()),
+                        None => return Err(format!("unsupported type {}", s)),
+                    };
+                    let len = match len.parse::<usize>() {
+                        Ok(len) => len,
+                        Err(_) => return Err(format!("invalid array length in {}", s)),
+                    };
+                    match item {
+                        "u8" => Type::ByteArray(len),
+                        "?" => Type::FormatArray(len),
+                        _ => return Err(format!("unsupported array type {}", s)),
+                    }
+                } else {
+                    return Err(format!("unsupported type {}", s))
+                }
+            }
+        })
+    }
+}
+
+fn split_pair<'a>(s: &'a str, sep: &str) -> Option<(&'a str, &'a str)> {
+    s.find(sep).map(|i| (&s[..i], &s[i + sep.len()..]))
+}
+
+/// A display hint, such as `x`, `#010x` or `us`.
+#[derive(Debug, Clone, PartialEq, Default)]
+struct Hint {
+    alternate: bool,
+    width: usize,
+    kind: String,
+}
+
+impl Hint {
+    fn parse(s: &str) -> Hint {
+        let alternate = s.starts_with('#');
+        let s = s.trim_start_matches('#');
+        let digits = s.chars().take_while(|c| c.is_ascii_digit()).count();
+        let width = s[..digits].parse::<usize>().unwrap_or(0);
+        Hint { alternate, width, kind: String::from(&s[digits..]) }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Param {
+    index: usize,
+    ty: Type,
+    hint: Hint,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Piece {
+    Literal(String),
+    Param(Param),
+}
+
+/// Splits a derived enum's format string into its variants at top-level `|` characters.
+fn split_variants(format: &str) -> Vec<&str> {
+    let mut variants = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in format.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            '|' if depth == 0 => {
+                variants.push(&format[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    variants.push(&format[start..]);
+    variants
+}
+
+/// Parses a format string: `{{` and `}}` are literal braces, and a parameter is
+/// `{[index][=type][:hint]}`. Parameters without an index take the next one.
+fn parse(format: &str) -> ::std::result::Result<Vec<Piece>, String> {
+    let mut pieces = Vec::new();
+    let mut literal = String::new();
+    let mut next_index = 0;
+    let mut chars = format.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '{' if chars.peek().map(|p| p.1) == Some('{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek().map(|p| p.1) == Some('}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let end = match format[i..].find('}') {
+                    Some(n) => i + n,
+                    None => return Err(format!("unterminated parameter in \"{}\"", format)),
+                };
+                let spec = &format[i + 1..end];
+                while chars.peek().is_some_and(|p| p.0 <= end) {
+                    chars.next();
+                }
+                let (spec, hint) = match split_pair(spec, ":") {
+                    Some((spec, hint)) => (spec, Hint::parse(hint)),
+                    None => (spec, Hint::default()),
+                };
+                let (index, ty) = match split_pair(spec, "=") {
+                    Some((index, ty)) => (index, Type::parse(ty)?),
+                    None => (spec, Type::Format),
+                };
+                let index = if index.is_empty() {
+                    next_index += 1;
+                    next_index - 1
+                } else {
+                    match index.parse::<usize>() {
+                        Ok(index) => index,
+                        Err(_) => return Err(format!("invalid parameter index in \"{}\"", format)),
+                    }
+                };
+                if !literal.is_empty() {
+                    pieces.push(Piece::Literal(literal.clone()));
+                    literal.clear();
+                }
+                pieces.push(Piece::Param(Param { index, ty, hint }));
+            }
+            _ => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        pieces.push(Piece::Literal(literal));
+    }
+    Ok(pieces)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Uint(u128),
+    Int(i128),
+    F32(f32),
+    F64(f64),
+    Bool(bool),
+    Str(String),
+    Bytes(Vec<u8>),
+}
+
+/// Formats an integer with a hint's radix, `#` prefix and zero padding.
+fn integer(value: u128, negative: bool, hint: &Hint) -> String {
+    let (digits, prefix) = match &hint.kind[..] {
+        "x" => (format!("{:x}", value), "0x"),
+        "X" => (format!("{:X}", value), "0x"),
+        "b" => (format!("{:b}", value), "0b"),
+        "o" => (format!("{:o}", value), "0o"),
+        _ => (format!("{}", value), ""),
+    };
+    let prefix = if hint.alternate { prefix } else { "" };
+    let sign = if negative { "-" } else { "" };
+    let pad = hint.width.saturating_sub(sign.len() + prefix.len() + digits.len());
+    format!("{}{}{}{}", sign, prefix, "0".repeat(pad), digits)
+}
+
+fn ascii(data: &[u8]) -> String {
+    let mut s = String::from("b\"");
+    for b in data {
+        match *b {
+            b'"' => s.push_str("\\\""),
+            b'\\' => s.push_str("\\\\"),
+            0x20..=0x7e => s.push(*b as char),
+            _ => s.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    s.push('"');
+    s
+}
+
+fn render(value: &Value, param: &Param) -> String {
+    let hint = &param.hint;
+    match *value {
+        Value::Uint(v) => {
+            if let Type::BitField(start, end) = param.ty {
+                let bits = (v >> start) & (u128::MAX >> (128 - (end - start) as u32));
+                if hint.kind.is_empty() {
+                    return format!("0b{:b}", bits)
+                }
+                return integer(bits, false, hint)
+            }
+            match &hint.kind[..] {
+                "us" => format!("{}.{:06}", v / 1_000_000, v % 1_000_000),
+                "ms" => format!("{}.{:03}", v / 1_000, v % 1_000),
+                _ => integer(v, false, hint),
+            }
+        }
+        Value::Int(v) => integer(v.unsigned_abs(), v < 0, hint),
+        Value::F32(v) => format!("{}", v),
+        Value::F64(v) => format!("{}", v),
+        Value::Bool(v) => format!("{}", v),
+        Value::Str(ref s) => if hint.kind == "?" { format!("{:?}", s) } else { s.clone() },
+        Value::Bytes(ref data) => {
+            if hint.kind == "a" {
+                return ascii(data)
+            }
+            let items: Vec<String> = data.iter().map(|b| integer(*b as u128, false, hint)).collect();
+            format!("[{}]", items.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a table from (index, tag, format string) entries.
+    fn table(entries: &[(u16, &str, &str)], encoding: Encoding) -> Table {
+        let entries = entries.iter()
+            .map(|&(index, tag, format)| (index, Entry { tag: String::from(tag), format: String::from(format) }))
+            .collect();
+        Table { entries, timestamp: None, encoding }
+    }
+
+    fn decode(table: &Table, data: &[u8]) -> String {
+        match table.decode(data) {
+            Ok((frame, n)) => {
+                assert_eq!(n, data.len(), "frame length");
+                frame.text
+            }
+            Err(Fail::Incomplete) => panic!("incomplete frame"),
+            Err(Fail::Malformed(reason)) => panic!("malformed frame: {}", reason),
+        }
+    }
+
+    /// Encodes with rzCOBS using only the 7-byte group form, padding with zeros.
+    fn rzcobs_encode(data: &[u8]) -> Vec<u8> {
+        let mut data = data.to_vec();
+        let padding = (7 - data.len() % 7) % 7;
+        data.resize(data.len() + padding, 0);
+        let mut out = Vec::new();
+        for group in data.chunks(7) {
+            let code = (0..7).filter(|k| group[*k] == 0).fold(0u8, |code, k| code | 1 << k);
+            assert!(code != 0, "every 7 bytes of test data need a zero");
+            out.extend(group.iter().filter(|b| **b != 0));
+            out.push(code);
+        }
+        out
+    }
+
+    #[test]
+    fn bitfield_sends_only_covered_bytes() {
+        let t = table(&[(1, "defmt_info", "{0=8..16} {1=u8}")], Encoding::Raw);
+        // Only byte 1 of the u16 argument is sent.
+        assert_eq!(decode(&t, &[1, 0, 0xab, 5]), "0b10101011 5");
+    }
+
+    #[test]
+    fn bitfields_of_one_argument_are_merged() {
+        let t = table(&[(1, "defmt_info", "{0=4..8} {0=8..12} {1=u8}")], Encoding::Raw);
+        assert_eq!(decode(&t, &[1, 0, 0xa5, 0x03, 7]), "0b1010 0b11 7");
+        let t = table(&[(1, "defmt_info", "{0=16..20:x} {0=8..10}")], Encoding::Raw);
+        assert_eq!(decode(&t, &[1, 0, 0x02, 0x0c]), "c 0b10");
+    }
+
+    #[test]
+    fn format_slice_sends_element_type_once() {
+        let t = table(&[
+            (1, "defmt_info", "{=[?]} {=u8}"),
+            (2, "defmt_derived", "P({=u8})"),
+        ], Encoding::Raw);
+        assert_eq!(decode(&t, &[1, 0, 2, 0, 0, 0, 2, 0, 7, 9, 1]), "[P(7), P(9)] 1");
+        // An empty slice has no element type.
+        assert_eq!(decode(&t, &[1, 0, 0, 0, 0, 0, 1]), "[] 1");
+    }
+
+    #[test]
+    fn format_array() {
+        let t = table(&[
+            (1, "defmt_info", "{=[?;3]}"),
+            (2, "defmt_derived", "{=i8}"),
+        ], Encoding::Raw);
+        assert_eq!(decode(&t, &[1, 0, 2, 0, 1, 0xff, 3]), "[1, -1, 3]");
+    }
+
+    #[test]
+    fn enums() {
+        let t = table(&[
+            (1, "defmt_info", "state {=?}"),
+            (2, "defmt_derived", "Idle|Busy({=u8})|Done"),
+            (3, "defmt_info", "states {=[?]}"),
+        ], Encoding::Raw);
+        assert_eq!(decode(&t, &[1, 0, 2, 0, 1, 42]), "state Busy(42)");
+        assert_eq!(decode(&t, &[1, 0, 2, 0, 2]), "state Done");
+        assert_eq!(decode(&t, &[3, 0, 2, 0, 0, 0, 2, 0, 0, 1, 5]), "states [Idle, Busy(5)]");
+    }
+
+    #[test]
+    fn rzcobs_frames() {
+        let t = table(&[(1, "defmt_warn", "x={=u32} y={=u8}")], Encoding::Rzcobs);
+        let mut stream = rzcobs_encode(&[1, 0, 0x10, 0x27, 0, 0, 3]);
+        stream.push(0);
+        stream.extend(rzcobs_encode(&[1, 0, 1, 0, 0, 0, 0]));
+        stream.push(0);
+        let mut decoder = Decoder::new(t);
+        decoder.push(&stream);
+        let frames: Vec<Frame> = decoder.collect();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].level, Some(Level::Warn));
+        assert_eq!(frames[0].text, "x=10000 y=3");
+        assert_eq!(frames[1].text, "x=1 y=0");
+    }
+
+    #[test]
+    fn rzcobs_run_forms() {
+        // Seven non-zero bytes followed by a zero.
+        assert_eq!(rzcobs_decode(&[1, 2, 3, 4, 5, 6, 7, 0x80]), Some(vec![1, 2, 3, 4, 5, 6, 7, 0]));
+        // A zero, then non-zero bytes.
+        assert_eq!(rzcobs_decode(&[1, 2, 3, 4, 5, 6, 0x01]), Some(vec![0, 1, 2, 3, 4, 5, 6]));
+        assert_eq!(rzcobs_decode(&[0x81]), None);
+    }
+}
@@ -4,16 +4,21 @@ use ioreg;
 #[cfg(target_os = "linux")]
 use sysfs;
 use clap::ArgMatches;
-use std::path::{Path, PathBuf};
+#[cfg(target_os = "macos")]
+use std::path::Path;
+use std::path::PathBuf;
 use std::fs;
 use std::io::Read;
 use std::fmt::Write;
 use config::Config;
+use bobbin_config::{DeviceConfig, DevicesConfig};
+use itm::TraceConfig;
+use toml;
 #[cfg(feature = "stlink")]
 use stlink;
 use Result;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct UsbDevice {
     pub vendor_id: u16,
     pub product_id: u16,
@@ -75,365 +80,135 @@ pub trait Device {
         None
     }
 
-    fn can_trace_itm(&self) -> bool {
-        false
-    }
-    fn trace_itm(&self, target_clk: u32, trace_clk: u32) -> Result<()> {
-        unimplemented!()
+    fn console_speed(&self) -> Option<u32> {
+        None
     }
-}
-
-pub struct UnknownDevice {
-    usb: UsbDevice,
-}
-
-impl Device for UnknownDevice {
-    fn usb(&self) -> &UsbDevice {
-        &self.usb
+    fn console_format(&self) -> Option<&str> {
+        None
     }
-}
-
-pub struct JLinkDevice {
-    usb: UsbDevice,
-}
-
-impl Device for JLinkDevice {
-    fn usb(&self) -> &UsbDevice {
-        &self.usb
+    fn console_flow_control(&self) -> Option<&str> {
+        None
     }
 
-    fn device_type(&self) -> Option<&str> {
-        Some("JLink")
+    /// The ST-Link send, receive and trace bulk endpoints, if this is an ST-Link probe.
+    fn stlink_endpoints(&self) -> Option<&[u8]> {
+        None
     }
 
-    fn loader_type(&self) -> Option<&str> {
-        Some("JLink")
+    fn can_trace_itm(&self) -> bool {
+        false
     }
-
-    fn debugger_type(&self) -> Option<&str> {
-        Some("JLink")
+    /// Starts SWO trace and passes the raw trace data to `sink` until it returns false or an
+    /// error occurs.
+    fn trace_itm(&self, _trace: &TraceConfig, _sink: &mut FnMut(&[u8]) -> Result<bool>) -> Result<()> {
+        bail!("Currently selected device does not support ITM trace")
     }
 
-    #[cfg(target_os = "macos")]
-    fn cdc_path(&self) -> Option<String> {
-        let path = format!("/dev/cu.usbmodem{}{}",
-            &format!("{:x}", self.usb.location_id.unwrap_or(0))[..4],
-            1,
-        );
-        if Path::new(&path).exists() {
-            Some(path)
-        } else {
-            None
-        }
-    }
-
-    #[cfg(target_os = "linux")]
-    fn cdc_path(&self) -> Option<String> {
-        if let Some(ref path) = self.usb().path {
-            if let Some(cdc_path) = sysfs::cdc_path(path, "1.0") {
-                if Path::new(&cdc_path).exists() {
-                    Some(cdc_path)
-                } else {
-                    None
-                }
-            } else {
-                None
-            }
-        } else {
-            None
-        }
-    }
-
-
-    fn openocd_serial(&self) -> Option<String> {
-        Some(format!("jlink_serial {}", self.usb.serial_number))
+    /// Returns the core clock of the target in Hz and how it was found.
+    fn detect_target_clock(&self) -> Result<(u32, &'static str)> {
+        bail!("Currently selected device does not support target clock detection")
     }
 }
 
-pub struct StLinkV2Device {
+pub struct UnknownDevice {
     usb: UsbDevice,
 }
 
-impl Device for StLinkV2Device {
+impl Device for UnknownDevice {
     fn usb(&self) -> &UsbDevice {
         &self.usb
     }
-
-    fn device_type(&self) -> Option<&str> {
-        Some("STLinkV2")
-    }
-
-    fn loader_type(&self) -> Option<&str> {
-        Some("OpenOCD")
-    }
-
-    fn debugger_type(&self) -> Option<&str> {
-        Some("OpenOCD")
-    }
-
-    fn openocd_serial(&self) -> Option<String> {
-        // see https://armprojects.wordpress.com/2016/08/21/debugging-multiple-stm32-in-eclipse-with-st-link-v2-and-openocd/
-        // This assumes serial number is an 8-bit ASCII string that has been directly encoded as UTF-8
-        // Additionally, assume that OpenOCD will replace non-ASCII characters with a question mark.
-
-        let serial = self.usb.serial_number.clone().into_bytes();
-        let mut out = String::from("hla_serial \"");
-
-        for c in self.usb.serial_number.chars() {
-            let c = c as u8;
-            let b = if c > 0x7f { 0x3f } else { c };
-            write!(out, "\\x{:02X}", b).unwrap();
-        }
-        write!(out, "\"").unwrap();
-        Some(out)
-    }
-
-    #[cfg(feature = "stlink")]
-    fn can_trace_itm(&self) -> bool {
-        true
-    }
-
-    #[allow(unreachable_code)]
-    #[cfg(feature = "stlink")]
-    fn trace_itm(&self, target_clk: u32, trace_clk: u32) -> Result<()> {
-
-        let mut ctx = stlink::context()?;
-        let cfg = stlink::Config::new(
-            self.usb.vendor_id,
-            self.usb.product_id,
-            0x2,
-            0x81,
-            0x83,
-            target_clk,
-            trace_clk,
-            &self.usb.serial_number,
-        );
-        if let Some(mut d) = ctx.connect(cfg)? {
-            println!("configure");
-            d.configure(false)?;
-            println!("run trace");
-            d.run_trace()?;
-
-        } else {
-            bail!("No device found");
-        }
-        unreachable!()
-    }
 }
 
-pub struct StLinkV21Device {
+pub struct ProbeDevice {
     usb: UsbDevice,
+    entry: DeviceConfig,
 }
 
-impl Device for StLinkV21Device {
-    fn usb(&self) -> &UsbDevice {
-        &self.usb
-    }
-
-    fn device_type(&self) -> Option<&str> {
-        Some("STLinkV21")
-    }
-
-    fn loader_type(&self) -> Option<&str> {
-        Some("OpenOCD")
-    }
-
-    fn debugger_type(&self) -> Option<&str> {
-        Some("OpenOCD")
-    }
-
+impl ProbeDevice {
     #[cfg(target_os = "macos")]
-    fn cdc_path(&self) -> Option<String> {       
-        Some(format!("/dev/cu.usbmodem{}{}",
-            &format!("{:x}", self.usb.location_id.unwrap_or(0))[..4],
-            3,
-        ))            
+    fn macos_path(&self, current: &Option<String>, legacy: &Option<String>) -> Option<String> {
+        let template = if legacy.is_some() && !os_version_match("10.14") {
+            legacy
+        } else {
+            current
+        };
+        template.as_ref().map(|t| expand(t, &self.usb))
     }
 
     #[cfg(target_os = "linux")]
-    fn cdc_path(&self) -> Option<String> {
-        if let Some(ref path) = self.usb().path {
-            sysfs::cdc_path(path, "1.2")
+    fn sysfs_path(&self, interface: &Option<String>) -> Option<String> {
+        if let (Some(path), Some(interface)) = (self.usb.path.as_ref(), interface.as_ref()) {
+            sysfs::cdc_path(path, interface)
         } else {
             None
         }
     }
-
-    fn openocd_serial(&self) -> Option<String> {
-        Some(format!("hla_serial {}", self.usb.serial_number))
-    }
-
-    #[cfg(feature = "stlink")]
-    fn can_trace_itm(&self) -> bool {
-        true
-    }
-
-    #[cfg(feature = "stlink")]
-    #[allow(unreachable_code)]
-    fn trace_itm(&self, target_clk: u32, trace_clk: u32) -> Result<()> {
-        let mut ctx = stlink::context()?;
-        let cfg = stlink::Config::new(
-            self.usb.vendor_id,
-            self.usb.product_id,
-            0x1,
-            0x81,
-            0x82,
-            target_clk,
-            trace_clk,
-            &self.usb.serial_number,
-        );
-        if let Some(mut d) = ctx.connect(cfg)? {
-            d.configure(false)?;
-            d.run_trace()?;
-
-        } else {
-            bail!("No device found");
-        }
-        unreachable!()
-    }
-}
-
-pub struct TiIcdiDevice {
-    usb: UsbDevice,
 }
 
-impl Device for TiIcdiDevice {
+impl Device for ProbeDevice {
     fn usb(&self) -> &UsbDevice {
         &self.usb
     }
 
     fn device_type(&self) -> Option<&str> {
-        Some("TI-ICDI")
+        Some(&self.entry.device_type)
     }
 
     fn loader_type(&self) -> Option<&str> {
-        Some("OpenOCD")
+        self.entry.loader.as_deref()
     }
 
     fn debugger_type(&self) -> Option<&str> {
-        Some("OpenOCD")
+        self.entry.debugger.as_deref()
     }
 
     #[cfg(target_os = "macos")]
     fn cdc_path(&self) -> Option<String> {
-        Some(format!(
-            "/dev/cu.usbmodem{}{}",
-            &self.usb.serial_number[..7],
-            1
-        ))
+        self.macos_path(&self.entry.macos_cdc_path, &self.entry.macos_legacy_cdc_path)
+            .and_then(|path| if Path::new(&path).exists() { Some(path) } else { None })
     }
 
     #[cfg(target_os = "linux")]
     fn cdc_path(&self) -> Option<String> {
-        if let Some(ref path) = self.usb().path {
-            sysfs::cdc_path(path, "1.0")
-        } else {
-            None
-        }
-    }
-
-    fn openocd_serial(&self) -> Option<String> {
-        Some(format!("hla_serial {}", self.usb.serial_number))
-    }
-}
-
-pub struct CmsisDapDevice {
-    usb: UsbDevice,
-}
-
-impl Device for CmsisDapDevice {
-    fn usb(&self) -> &UsbDevice {
-        &self.usb
-    }
-
-    fn device_type(&self) -> Option<&str> {
-        Some("DAPLink")
-    }
-
-    fn loader_type(&self) -> Option<&str> {
-        Some("OpenOCD")
-    }
-
-    fn debugger_type(&self) -> Option<&str> {
-        Some("OpenOCD")
+        self.sysfs_path(&self.entry.cdc_interface)
     }
 
     #[cfg(target_os = "macos")]
-    fn cdc_path(&self) -> Option<String> {
-        Some(format!("/dev/cu.usbmodem{}{}",
-            &format!("{:x}", self.usb.location_id.unwrap_or(0))[..4],
-            2,
-        ))
+    fn gdb_path(&self) -> Option<String> {
+        self.macos_path(&self.entry.macos_gdb_path, &self.entry.macos_legacy_gdb_path)
     }
 
     #[cfg(target_os = "linux")]
-    fn cdc_path(&self) -> Option<String> {
-        if let Some(ref path) = self.usb().path {
-            sysfs::cdc_path(path, "1.1")
-        } else {
-            None
-        }
-    }
-
-
-    fn openocd_serial(&self) -> Option<String> {
-        Some(format!("cmsis_dap_serial {}", self.usb.serial_number))
-    }
-}
-
-pub struct DapLinkDevice {
-    usb: UsbDevice,
-}
-
-impl Device for DapLinkDevice {
-    fn usb(&self) -> &UsbDevice {
-        &self.usb
-    }
-
-    fn device_type(&self) -> Option<&str> {
-        Some("DAPLink")
-    }
-
-    fn loader_type(&self) -> Option<&str> {
-        Some("OpenOCD")
-    }
-
-    fn debugger_type(&self) -> Option<&str> {
-        Some("OpenOCD")
+    fn gdb_path(&self) -> Option<String> {
+        self.sysfs_path(&self.entry.gdb_interface)
     }
 
     #[cfg(target_os = "macos")]
-    fn cdc_path(&self) -> Option<String> {
-        Some(format!("/dev/cu.usbmodem{}{}",
-            &format!("{:x}", self.usb.location_id.unwrap_or(0))[..4],
-            2,
-        ))
-    }
-
-    #[cfg(target_os = "linux")]
-    fn cdc_path(&self) -> Option<String> {
-        if let Some(ref path) = self.usb().path {
-            sysfs::cdc_path(path, "1.1")
-        } else {
-            None
-        }
+    fn bossa_path(&self) -> Option<String> {
+        self.macos_path(&self.entry.macos_bossa_path, &self.entry.macos_legacy_bossa_path)
     }
 
     fn msd_path(&self) -> Option<PathBuf> {
-        // Look in /Volumes/DAPLINK*/ for DETAILS.TXT
+        // Look in /Volumes/<msd-volume>*/ for DETAILS.TXT
         // Look for Unique ID line == serial number
+        let prefix = if let Some(ref volume) = self.entry.msd_volume {
+            format!("/Volumes/{}", volume)
+        } else {
+            return None
+        };
         if let Ok(volumes) = fs::read_dir("/Volumes/") {
             for volume in volumes {
                 if let Ok(volume) = volume {
-                    //println!("checking {:?} {}", volume.path(), volume.path().to_string_lossy().starts_with("/Volumes/DAPLINK") );
-                    if volume.path().to_string_lossy().starts_with(
-                        "/Volumes/DAPLINK",
-                    )
-                    {
+                    if volume.path().to_string_lossy().starts_with(&prefix) {
                         let details = volume.path().join("DETAILS.TXT");
-                        let mut f = fs::File::open(details).expect("Error opening DETAILS.TXT");
                         let mut s = String::new();
-                        f.read_to_string(&mut s).expect("Error reading details");
+                        match fs::File::open(details) {
+                            Ok(mut f) => if f.read_to_string(&mut s).is_err() {
+                                continue;
+                            },
+                            Err(_) => continue,
+                        }
                         if s.contains(&self.usb.serial_number) {
                             return Some(volume.path());
                         }
@@ -445,225 +220,99 @@ impl Device for DapLinkDevice {
     }
 
     fn openocd_serial(&self) -> Option<String> {
-        Some(format!("cmsis_dap_serial {}", self.usb.serial_number))
+        self.entry.openocd_serial.as_ref().map(|t| expand(t, &self.usb))
     }
-}
 
-pub struct FeatherDevice {
-    usb: UsbDevice,
-}
-
-impl Device for FeatherDevice {
-    fn usb(&self) -> &UsbDevice {
-        &self.usb
-    }
-
-    fn device_type(&self) -> Option<&str> {
-        Some("Feather")
-    }
-
-    fn loader_type(&self) -> Option<&str> {
-        Some("Bossa")
-    }
-
-    #[cfg(target_os = "macos")]
-    fn bossa_path(&self) -> Option<String> {
-        // eprintln!("location id: 0x{:0x}", self.usb.location_id.unwrap());
-        let loc = format!("{:x}", self.usb.location_id.unwrap_or(0));
-        let loc = loc.split("0").next().unwrap();
-        if os_version_match("10.14") {
-            Some(format!("/dev/cu.usbmodem{}01", loc))
-        } else {
-            Some(format!("/dev/cu.usbmodem{}{}",
-                &format!("{:x}", self.usb.location_id.unwrap_or(0))[..4],
-                1,
-            ))
-        }
+    fn console_speed(&self) -> Option<u32> {
+        self.entry.console_speed
     }
-}
-
-pub struct TeensyDevice {
-    usb: UsbDevice,
-}
 
-fn os_version_match(required_version: &str) -> bool {
-    let os = os_type::current_platform();
-    let os_version = semver::Version::parse(&os.version).unwrap();
-
-    let ver_str = ">= ".to_string() + required_version;
-    let r = semver::VersionReq::parse(&ver_str).unwrap();
-
-    r.matches(&os_version)
-}
-
-impl Device for TeensyDevice {
-    fn usb(&self) -> &UsbDevice {
-        &self.usb
+    fn console_format(&self) -> Option<&str> {
+        self.entry.console_format.as_deref()
     }
 
-    fn device_type(&self) -> Option<&str> {
-        Some("Teensy")
+    fn console_flow_control(&self) -> Option<&str> {
+        self.entry.console_flow_control.as_deref()
     }
 
-    fn loader_type(&self) -> Option<&str> {
-        Some("Teensy")
+    fn stlink_endpoints(&self) -> Option<&[u8]> {
+        self.entry.trace_endpoints.as_deref()
     }
-}
 
-pub struct Stm32Device {
-    usb: UsbDevice,
-}
-
-impl Device for Stm32Device {
-    fn usb(&self) -> &UsbDevice {
-        &self.usb
-    }
-
-    fn device_type(&self) -> Option<&str> {
-        Some("STM32")
-    }
-
-    fn loader_type(&self) -> Option<&str> {
-        Some("dfu-util")
-    }
-}
-
-pub struct BlackMagicDevice {
-    usb: UsbDevice,
-}
-
-impl Device for BlackMagicDevice {
-    fn usb(&self) -> &UsbDevice {
-        &self.usb
-    }
-
-    fn device_type(&self) -> Option<&str> {
-        Some("BlackMagicProbe")
-    }
-
-    fn loader_type(&self) -> Option<&str> {
-        Some("blackmagic")
-    }
-
-    fn debugger_type(&self) -> Option<&str> {
-        Some("blackmagic")
-    }
-
-    #[cfg(target_os = "macos")]
-    fn cdc_path(&self) -> Option<String> {
-
-        // Since Macos 10.14 the usbmodem serial number behaviour has changed
-        // instead of replacing the last character with 1 or 3, it is actually
-        // added after the last character
-        if os_version_match("10.14") {
-            Some(format!("/dev/cu.usbmodem{}3", &self.usb.serial_number))
-        } else {
-            let serial_len = self.usb.serial_number.len();
-            Some(format!("/dev/cu.usbmodem{}3", &self.usb.serial_number[..serial_len  - 1]))
-        }
-    }
-
-    #[cfg(target_os = "linux")]
-    fn cdc_path(&self) -> Option<String> {
-        if let Some(ref path) = self.usb().path {
-            sysfs::cdc_path(path, "1.2")
-        } else {
-            None
-        }
+    #[cfg(feature = "stlink")]
+    fn can_trace_itm(&self) -> bool {
+        self.stlink_endpoints().map(|ep| ep.len() == 3).unwrap_or(false)
     }
 
-    #[cfg(target_os = "macos")]
-    fn gdb_path(&self) -> Option<String> {
-
-        // Since Macos 10.14 the cu.usbmodem serial number behaviour has changed
-        // instead of replacing the last character with 1 or 3, it is actually
-        // added after the last character
-        if os_version_match("10.14") {
-            Some(format!("/dev/cu.usbmodem{}1", &self.usb.serial_number))
-        } else {
-            let serial_len = self.usb.serial_number.len();
-            Some(format!("/dev/cu.usbmodem{}1", &self.usb.serial_number[..serial_len  - 1]))
-        }
+    #[cfg(feature = "stlink")]
+    fn trace_itm(&self, trace: &TraceConfig, sink: &mut FnMut(&[u8]) -> Result<bool>) -> Result<()> {
+        stlink::with_debugger(self, trace.target_clk, trace.trace_clk, |d| d.run_trace(trace, sink))
     }
 
-    #[cfg(target_os = "linux")]
-    fn gdb_path(&self) -> Option<String> {
-        if let Some(ref path) = self.usb().path {
-            sysfs::cdc_path(path, "1.0")
-        } else {
-            None
-        }
+    #[cfg(feature = "stlink")]
+    fn detect_target_clock(&self) -> Result<(u32, &'static str)> {
+        stlink::with_debugger(self, 0, 0, |d| stlink::clock::detect(d))
     }
 }
 
-pub struct Xds110Device {
-    usb: UsbDevice,
-}
-
-impl Device for Xds110Device {
-    fn usb(&self) -> &UsbDevice {
-        &self.usb
-    }
-
-    fn device_type(&self) -> Option<&str> {
-        Some("XDS110")
-    }
-
-    fn loader_type(&self) -> Option<&str> {
-        Some("OpenOCD")
-    }
-
-    fn debugger_type(&self) -> Option<&str> {
-        Some("OpenOCD")
-    }
-
-    #[cfg(target_os = "macos")]
-    fn cdc_path(&self) -> Option<String> {
-        Some(format!(
-            "/dev/cu.usbmodem{}{}",
-            &self.usb.serial_number[..7],
-            4
-        ))
-    }
-
-    #[cfg(target_os = "linux")]
-    fn cdc_path(&self) -> Option<String> {
-        if let Some(ref path) = self.usb().path {
-            sysfs::cdc_path(path, "1.0")
+/// Expands a device table path or command template for a specific USB device.
+pub fn expand(template: &str, usb: &UsbDevice) -> String {
+    let location = format!("{:x}", usb.location_id.unwrap_or(0));
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = if let Some(end) = rest[start..].find('}') {
+            start + end
         } else {
-            None
+            break;
+        };
+        out.push_str(&rest[..start]);
+        let mut parts = rest[start + 1..end].splitn(2, ':');
+        let name = parts.next().unwrap_or("");
+        let arg = parts.next();
+        match (name, arg) {
+            ("serial", Some("escaped")) => {
+                for c in usb.serial_number.chars() {
+                    let c = c as u32;
+                    let b = if c > 0x7f { 0x3f } else { c };
+                    write!(out, "\\x{:02X}", b).unwrap();
+                }
+            }
+            ("serial", arg) => out.push_str(truncate(&usb.serial_number, arg)),
+            ("location", arg) => out.push_str(truncate(&location, arg)),
+            ("location-prefix", None) => {
+                out.push_str(location.split('0').next().unwrap_or(""))
+            }
+            _ => out.push_str(&rest[start..end + 1]),
         }
+        rest = &rest[end + 1..];
     }
-
-    fn openocd_serial(&self) -> Option<String> {
-        Some(format!("cmsis_dap_serial {}", self.usb.serial_number))
-    }
+    out.push_str(rest);
+    out
 }
 
-pub struct OlimexDevice {
-    usb: UsbDevice,
+// Applies a {name:N} (first N characters) or {name:-N} (drop last N characters) argument.
+fn truncate<'a>(value: &'a str, arg: Option<&str>) -> &'a str {
+    let n = if let Some(n) = arg.and_then(|a| a.parse::<isize>().ok()) {
+        n
+    } else {
+        return value
+    };
+    let len = value.len() as isize;
+    let end = if n < 0 { len + n } else { n };
+    let end = if end < 0 { 0 } else if end > len { len } else { end };
+    value.get(..end as usize).unwrap_or(value)
 }
 
-impl Device for OlimexDevice {
-    fn usb(&self) -> &UsbDevice {
-        &self.usb
-    }
-
-    fn device_type(&self) -> Option<&str> {
-        Some("Olimex")
-    }
 
-    fn loader_type(&self) -> Option<&str> {
-        Some("OpenOCD")
-    }
+fn os_version_match(required_version: &str) -> bool {
+    let os = os_type::current_platform();
+    let os_version = semver::Version::parse(&os.version).unwrap();
 
-    fn debugger_type(&self) -> Option<&str> {
-        Some("OpenOCD")
-    }
+    let ver_str = ">= ".to_string() + required_version;
+    let r = semver::VersionReq::parse(&ver_str).unwrap();
 
-    fn openocd_serial(&self) -> Option<String> {
-        Some(format!("ftdi_serial {}", self.usb.serial_number))
-    }
+    r.matches(&os_version)
 }
 
 pub struct DeviceFilter {
@@ -695,40 +344,69 @@ pub fn filter(cfg: &Config, args: &ArgMatches, cmd_args: &ArgMatches) -> DeviceF
     }
 }
 
-pub fn lookup(usb: UsbDevice) -> Box<Device> {
-    match (usb.vendor_id, usb.product_id) {
-        (0x0d28, 0x0204) => Box::new(DapLinkDevice { usb: usb }),
-        (0x03eb, 0x2157) => Box::new(CmsisDapDevice { usb: usb }),
-        (0x0483, 0x3748) => Box::new(StLinkV2Device { usb: usb }),
-        (0x0483, 0x374b) => Box::new(StLinkV21Device { usb: usb }),
-        (0x1366, 0x0101) => Box::new(JLinkDevice { usb: usb }),
-        (0x1366, 0x0105) => Box::new(JLinkDevice { usb: usb }),
-        (0x1cbe, 0x00fd) => Box::new(TiIcdiDevice { usb: usb }),
-        (0x0451, 0xbef3) => Box::new(Xds110Device { usb: usb }),
-        (0x16c0, 0x0486) => Box::new(TeensyDevice { usb: usb }),
-        (0x16c0, 0x0478) => Box::new(TeensyDevice { usb: usb }),
-        (0x0483, 0xdf11) => Box::new(Stm32Device { usb: usb }),
-        (0x1d50, 0x6018) => Box::new(BlackMagicDevice { usb: usb }),
-        (0x15ba, 0x002a) => Box::new(OlimexDevice { usb: usb }),
-        // Vendor Prefix Only
-        (0x1366, _) => Box::new(JLinkDevice { usb: usb }),        
-        // Assume all Adafruit devices are FeatherDevices, which use
-        // the BOSSA loader.
-        (0x239a, _) => Box::new(FeatherDevice { usb: usb }),
-        _ => Box::new(UnknownDevice { usb: usb }),
+const BUILTIN_DEVICES: &str = include_str!("devices.toml");
+
+pub fn builtin_devices() -> Vec<DeviceConfig> {
+    let table: DevicesConfig = toml::from_str(BUILTIN_DEVICES).expect("Invalid built-in device table");
+    table.device.unwrap_or_default()
+}
+
+/// Returns the device table, with user entries ahead of the built-in defaults.
+pub fn table(cfg: &Config) -> Vec<DeviceConfig> {
+    let mut items = cfg.cfg_devices();
+    items.extend(builtin_devices());
+    items
+}
+
+fn matches_id(id: &str, usb: &UsbDevice) -> Option<bool> {
+    let mut parts = id.splitn(2, ':');
+    let vid = parts.next().and_then(|v| u16::from_str_radix(v.trim(), 16).ok());
+    if vid != Some(usb.vendor_id) {
+        return None;
+    }
+    match parts.next().map(str::trim) {
+        Some("*") | None => Some(false),
+        Some(pid) => {
+            if u16::from_str_radix(pid, 16).ok() == Some(usb.product_id) {
+                Some(true)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+pub fn lookup(table: &[DeviceConfig], usb: UsbDevice) -> Box<Device> {
+    let mut wildcard: Option<&DeviceConfig> = None;
+    for entry in table.iter() {
+        match matches_id(&entry.id, &usb) {
+            Some(true) => return Box::new(ProbeDevice { usb: usb, entry: entry.clone() }),
+            Some(false) if wildcard.is_none() => wildcard = Some(entry),
+            _ => {}
+        }
+    }
+    if let Some(entry) = wildcard {
+        Box::new(ProbeDevice { usb: usb, entry: entry.clone() })
+    } else {
+        Box::new(UnknownDevice { usb: usb })
     }
 }
 
 
-pub fn enumerate() -> Result<Vec<Box<Device>>> {
-    #[cfg(target_os = "macos")] return Ok(ioreg::enumerate()?.into_iter().map(lookup).collect());
+pub fn enumerate(cfg: &Config) -> Result<Vec<Box<Device>>> {
+    enumerate_table(&table(cfg))
+}
+
+/// Enumerates the connected devices, looking them up in `table`.
+pub fn enumerate_table(table: &[DeviceConfig]) -> Result<Vec<Box<Device>>> {
+    #[cfg(target_os = "macos")] return Ok(ioreg::enumerate()?.into_iter().map(|usb| lookup(table, usb)).collect());
 
-    #[cfg(target_os = "linux")] return Ok(sysfs::enumerate()?.into_iter().map(lookup).collect());
+    #[cfg(target_os = "linux")] return Ok(sysfs::enumerate()?.into_iter().map(|usb| lookup(table, usb)).collect());
 }
 
-pub fn search(filter: &DeviceFilter) -> Result<Vec<Box<Device>>> {
+pub fn search(cfg: &Config, filter: &DeviceFilter) -> Result<Vec<Box<Device>>> {
     Ok(
-        enumerate()?
+        enumerate(cfg)?
             .into_iter()
             .filter(|d| {
                 if !filter.all {
@@ -0,0 +1,533 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use Result;
+
+pub const PT_LOAD: u32 = 1;
+
+pub const SHT_PROGBITS: u32 = 1;
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_NOBITS: u32 = 8;
+
+pub const SHF_WRITE: u64 = 0x1;
+pub const SHF_ALLOC: u64 = 0x2;
+pub const SHF_EXECINSTR: u64 = 0x4;
+
+pub const STT_OBJECT: u8 = 1;
+pub const STT_FUNC: u8 = 2;
+pub const STT_SECTION: u8 = 3;
+pub const STT_FILE: u8 = 4;
+
+pub const EM_386: u16 = 3;
+pub const EM_ARM: u16 = 40;
+pub const EM_X86_64: u16 = 62;
+pub const EM_AARCH64: u16 = 183;
+pub const EM_RISCV: u16 = 243;
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: String,
+    pub kind: u32,
+    pub flags: u64,
+    pub addr: u64,
+    pub offset: u64,
+    pub size: u64,
+    pub link: u32,
+    pub entsize: u64,
+}
+
+impl Section {
+    pub fn is_alloc(&self) -> bool {
+        self.flags & SHF_ALLOC != 0
+    }
+
+    pub fn flag_string(&self) -> String {
+        let mut s = String::new();
+        if self.flags & SHF_ALLOC != 0 { s.push('A') }
+        if self.flags & SHF_WRITE != 0 { s.push('W') }
+        if self.flags & SHF_EXECINSTR != 0 { s.push('X') }
+        s
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub paddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub value: u64,
+    pub size: u64,
+    pub kind: u8,
+    pub bind: u8,
+    pub shndx: u16,
+}
+
+impl Symbol {
+    pub fn kind_str(&self) -> &'static str {
+        match self.kind {
+            STT_OBJECT => "OBJECT",
+            STT_FUNC => "FUNC",
+            STT_SECTION => "SECTION",
+            STT_FILE => "FILE",
+            _ => "NOTYPE",
+        }
+    }
+
+    /// Returns the symbol address with the Thumb bit cleared for ARM functions.
+    pub fn addr(&self) -> u64 {
+        if self.kind == STT_FUNC { self.value & !1 } else { self.value }
+    }
+}
+
+pub struct Elf {
+    data: Vec<u8>,
+    pub is_64: bool,
+    pub is_little: bool,
+    pub machine: u16,
+    pub entry: u64,
+    pub sections: Vec<Section>,
+    pub segments: Vec<Segment>,
+}
+
+impl Elf {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Elf> {
+        let path = path.as_ref();
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        match Elf::parse(data) {
+            Ok(elf) => Ok(elf),
+            Err(e) => bail!("{}: {}", path.display(), e),
+        }
+    }
+
+    pub fn parse(data: Vec<u8>) -> Result<Elf> {
+        if data.len() < 16 || &data[..4] != b"\x7fELF" {
+            bail!("not an ELF file");
+        }
+        let is_64 = match data[4] {
+            1 => false,
+            2 => true,
+            c => bail!("invalid ELF class {}", c),
+        };
+        let is_little = match data[5] {
+            1 => true,
+            2 => false,
+            e => bail!("invalid ELF data encoding {}", e),
+        };
+        let mut elf = Elf {
+            data,
+            is_64,
+            is_little,
+            machine: 0,
+            entry: 0,
+            sections: Vec::new(),
+            segments: Vec::new(),
+        };
+        elf.machine = elf.u16_at(0x12)?;
+        let (phoff, shoff, rest) = if is_64 {
+            (elf.u64_at(0x20)?, elf.u64_at(0x28)?, 0x34)
+        } else {
+            (elf.u32_at(0x1c)? as u64, elf.u32_at(0x20)? as u64, 0x28)
+        };
+        elf.entry = if is_64 { elf.u64_at(0x18)? } else { elf.u32_at(0x18)? as u64 };
+        let phentsize = elf.u16_at(rest + 2)? as u64;
+        let phnum = elf.u16_at(rest + 4)? as u64;
+        let shentsize = elf.u16_at(rest + 6)? as u64;
+        let shnum = elf.u16_at(rest + 8)? as u64;
+        let shstrndx = elf.u16_at(rest + 10)? as u64;
+
+        for i in 0..phnum {
+            let base = elf.entry_pos("program header table", phoff, i, phentsize)?;
+            let segment = if is_64 {
+                Segment {
+                    kind: elf.u32_at(base)?,
+                    flags: elf.u32_at(base + 4)?,
+                    offset: elf.u64_at(base + 8)?,
+                    vaddr: elf.u64_at(base + 16)?,
+                    paddr: elf.u64_at(base + 24)?,
+                    filesz: elf.u64_at(base + 32)?,
+                    memsz: elf.u64_at(base + 40)?,
+                }
+            } else {
+                Segment {
+                    kind: elf.u32_at(base)?,
+                    offset: elf.u32_at(base + 4)? as u64,
+                    vaddr: elf.u32_at(base + 8)? as u64,
+                    paddr: elf.u32_at(base + 12)? as u64,
+                    filesz: elf.u32_at(base + 16)? as u64,
+                    memsz: elf.u32_at(base + 20)? as u64,
+                    flags: elf.u32_at(base + 24)?,
+                }
+            };
+            elf.segments.push(segment);
+        }
+
+        let mut names: Vec<u32> = Vec::new();
+        for i in 0..shnum {
+            let base = elf.entry_pos("section header table", shoff, i, shentsize)?;
+            names.push(elf.u32_at(base)?);
+            let section = if is_64 {
+                Section {
+                    name: String::new(),
+                    kind: elf.u32_at(base + 4)?,
+                    flags: elf.u64_at(base + 8)?,
+                    addr: elf.u64_at(base + 16)?,
+                    offset: elf.u64_at(base + 24)?,
+                    size: elf.u64_at(base + 32)?,
+                    link: elf.u32_at(base + 40)?,
+                    entsize: elf.u64_at(base + 56)?,
+                }
+            } else {
+                Section {
+                    name: String::new(),
+                    kind: elf.u32_at(base + 4)?,
+                    flags: elf.u32_at(base + 8)? as u64,
+                    addr: elf.u32_at(base + 12)? as u64,
+                    offset: elf.u32_at(base + 16)? as u64,
+                    size: elf.u32_at(base + 20)? as u64,
+                    link: elf.u32_at(base + 24)?,
+                    entsize: elf.u32_at(base + 36)? as u64,
+                }
+            };
+            elf.sections.push(section);
+        }
+
+        if shstrndx < shnum {
+            let strtab = elf.sections[shstrndx as usize].clone();
+            for (section, name) in elf.sections.iter_mut().zip(names.iter()) {
+                section.name = str_at(&elf.data, &strtab, *name as u64)?;
+            }
+        }
+
+        Ok(elf)
+    }
+
+    pub fn machine_name(&self) -> &'static str {
+        match self.machine {
+            EM_386 => "x86",
+            EM_ARM => "ARM",
+            EM_X86_64 => "x86-64",
+            EM_AARCH64 => "AArch64",
+            EM_RISCV => "RISC-V",
+            _ => "Unknown",
+        }
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+
+    pub fn section_data(&self, section: &Section) -> Result<&[u8]> {
+        if section.kind == SHT_NOBITS {
+            return Ok(&[]);
+        }
+        self.slice(section.offset, section.size)
+    }
+
+    pub fn segment_data(&self, segment: &Segment) -> Result<&[u8]> {
+        self.slice(segment.offset, segment.filesz)
+    }
+
+    /// Returns the PT_LOAD segments that have data in the file.
+    pub fn load_segments(&self) -> Vec<&Segment> {
+        self.segments.iter().filter(|s| s.kind == PT_LOAD && s.filesz > 0).collect()
+    }
+
+    /// Returns the file data of each PT_LOAD segment at its physical (load) address, sorted by
+    /// address. Fails if the file has no loadable data, if a segment extends past the end of the
+    /// address space or if two segments overlap.
+    pub fn load_image(&self) -> Result<Vec<(u64, &[u8])>> {
+        let mut items: Vec<(u64, &[u8])> = Vec::new();
+        for (i, segment) in self.segments.iter().enumerate() {
+            if segment.kind != PT_LOAD || segment.filesz == 0 {
+                continue;
+            }
+            let data = self.segment_data(segment)?;
+            if segment.paddr.checked_add(data.len() as u64).is_none() {
+                bail!("malformed ELF file: segment {} at 0x{:x} extends past the end of the address space",
+                    i, segment.paddr);
+            }
+            items.push((segment.paddr, data));
+        }
+        if items.is_empty() {
+            bail!("no loadable segments");
+        }
+        items.sort_by_key(|&(addr, _)| addr);
+        for pair in items.windows(2) {
+            let (a_addr, a_data) = pair[0];
+            let (b_addr, _) = pair[1];
+            if a_addr + a_data.len() as u64 > b_addr {
+                bail!("overlapping segments at 0x{:08x} and 0x{:08x}", a_addr, b_addr);
+            }
+        }
+        Ok(items)
+    }
+
+    pub fn symbols(&self) -> Result<Vec<Symbol>> {
+        let mut items: Vec<Symbol> = Vec::new();
+        let symtab = if let Some(symtab) = self.sections.iter().find(|s| s.kind == SHT_SYMTAB) {
+            symtab
+        } else {
+            return Ok(items);
+        };
+        let strtab = if let Some(strtab) = self.sections.get(symtab.link as usize) {
+            strtab
+        } else {
+            bail!("symbol table has no string table");
+        };
+        let entsize = if symtab.entsize > 0 {
+            symtab.entsize
+        } else if self.is_64 {
+            24
+        } else {
+            16
+        };
+        for i in 1..(symtab.size / entsize) {
+            let base = self.entry_pos("symbol table", symtab.offset, i, entsize)?;
+            let (name, value, size, info, shndx) = if self.is_64 {
+                (
+                    self.u32_at(base)?,
+                    self.u64_at(base + 8)?,
+                    self.u64_at(base + 16)?,
+                    self.u8_at(base + 4)?,
+                    self.u16_at(base + 6)?,
+                )
+            } else {
+                (
+                    self.u32_at(base)?,
+                    self.u32_at(base + 4)? as u64,
+                    self.u32_at(base + 8)? as u64,
+                    self.u8_at(base + 12)?,
+                    self.u16_at(base + 14)?,
+                )
+            };
+            items.push(Symbol {
+                name: str_at(&self.data, strtab, name as u64)?,
+                value,
+                size,
+                kind: info & 0xf,
+                bind: info >> 4,
+                shndx,
+            });
+        }
+        Ok(items)
+    }
+
+    pub fn symbol(&self, name: &str) -> Result<Option<Symbol>> {
+        Ok(self.symbols()?.into_iter().find(|s| s.name == name))
+    }
+
+    /// Reads a word from the loaded image at a virtual address.
+    pub fn read_u32(&self, addr: u64) -> Option<u32> {
+        for section in self.sections.iter() {
+            let end = match section.addr.checked_add(section.size) {
+                Some(end) => end,
+                None => continue,
+            };
+            if section.is_alloc() && section.kind != SHT_NOBITS &&
+                addr >= section.addr && addr < end && end - addr >= 4
+            {
+                let pos = section.offset.checked_add(addr - section.addr)?;
+                return self.u32_at(pos as usize).ok();
+            }
+        }
+        None
+    }
+
+    /// Returns the file position of entry `index` in a table at `offset`.
+    fn entry_pos(&self, table: &str, offset: u64, index: u64, entsize: u64) -> Result<usize> {
+        match index.checked_mul(entsize).and_then(|pos| offset.checked_add(pos)) {
+            Some(pos) if pos <= self.data.len() as u64 => Ok(pos as usize),
+            _ => bail!("malformed ELF file: {} at offset 0x{:x} is out of range", table, offset),
+        }
+    }
+
+    fn slice(&self, offset: u64, size: u64) -> Result<&[u8]> {
+        let start = offset as usize;
+        let end = start.saturating_add(size as usize);
+        if end > self.data.len() {
+            bail!("truncated ELF file (0x{:x} bytes at offset 0x{:x})", size, offset);
+        }
+        Ok(&self.data[start..end])
+    }
+
+    fn u8_at(&self, pos: usize) -> Result<u8> {
+        Ok(self.slice(pos as u64, 1)?[0])
+    }
+
+    fn u16_at(&self, pos: usize) -> Result<u16> {
+        let b = self.slice(pos as u64, 2)?;
+        Ok(if self.is_little {
+            (b[0] as u16) | (b[1] as u16) << 8
+        } else {
+            (b[1] as u16) | (b[0] as u16) << 8
+        })
+    }
+
+    fn u32_at(&self, pos: usize) -> Result<u32> {
+        let lo = self.u16_at(pos)? as u32;
+        let hi = self.u16_at(pos + 2)? as u32;
+        Ok(if self.is_little { lo | hi << 16 } else { hi | lo << 16 })
+    }
+
+    fn u64_at(&self, pos: usize) -> Result<u64> {
+        let lo = self.u32_at(pos)? as u64;
+        let hi = self.u32_at(pos + 4)? as u64;
+        Ok(if self.is_little { lo | hi << 32 } else { hi | lo << 32 })
+    }
+}
+
+/// Flattens a load image into a single binary starting at the lowest load address, filling
+/// gaps between segments with `fill`. Returns the base address and the data.
+pub fn to_binary(image: &[(u64, &[u8])], fill: u8) -> Result<(u64, Vec<u8>)> {
+    const MAX_BINARY_SIZE: u64 = 256 << 20;
+
+    let base = if let Some(&(addr, _)) = image.first() { addr } else { bail!("no loadable segments") };
+    let mut end = base;
+    for (i, &(addr, data)) in image.iter().enumerate() {
+        match addr.checked_add(data.len() as u64) {
+            Some(seg_end) if addr >= base => end = end.max(seg_end),
+            _ => bail!("malformed ELF file: segment {} at 0x{:x} is outside the load image", i, addr),
+        }
+    }
+    if end - base > MAX_BINARY_SIZE {
+        bail!("load segments span 0x{:08x} to 0x{:08x}, which is too large for a binary image", base, end);
+    }
+    let mut buf = vec![fill; (end - base) as usize];
+    for &(addr, data) in image.iter() {
+        let start = (addr - base) as usize;
+        buf[start..start + data.len()].copy_from_slice(data);
+    }
+    Ok((base, buf))
+}
+
+/// Encodes a load image as Intel HEX, with a start address record for `entry`.
+pub fn to_ihex(image: &[(u64, &[u8])], entry: u64) -> Result<String> {
+    use std::fmt::Write;
+
+    fn record(out: &mut String, kind: u8, addr: u16, data: &[u8]) {
+        let mut sum = (data.len() as u8)
+            .wrapping_add((addr >> 8) as u8)
+            .wrapping_add(addr as u8)
+            .wrapping_add(kind);
+        write!(out, ":{:02X}{:04X}{:02X}", data.len(), addr, kind).unwrap();
+        for b in data.iter() {
+            write!(out, "{:02X}", b).unwrap();
+            sum = sum.wrapping_add(*b);
+        }
+        writeln!(out, "{:02X}", sum.wrapping_neg()).unwrap();
+    }
+
+    let mut out = String::new();
+    let mut upper: Option<u64> = None;
+    for (i, &(addr, data)) in image.iter().enumerate() {
+        match addr.checked_add(data.len() as u64) {
+            Some(end) if end <= 0x1_0000_0000 => {}
+            _ => bail!("segment {} at 0x{:x} is outside the 32-bit Intel HEX address space", i, addr),
+        }
+        let mut pos = 0;
+        while pos < data.len() {
+            let a = addr + pos as u64;
+            // Records may not cross a 64K boundary
+            let n = (data.len() - pos).min(16).min((0x1_0000 - (a & 0xffff)) as usize);
+            if upper != Some(a >> 16) {
+                upper = Some(a >> 16);
+                record(&mut out, 0x04, 0, &[(a >> 24) as u8, (a >> 16) as u8]);
+            }
+            record(&mut out, 0x00, a as u16, &data[pos..pos + n]);
+            pos += n;
+        }
+    }
+    if entry <= 0xffff_ffff {
+        record(&mut out, 0x05, 0, &[(entry >> 24) as u8, (entry >> 16) as u8, (entry >> 8) as u8, entry as u8]);
+    }
+    record(&mut out, 0x01, 0, &[]);
+    Ok(out)
+}
+
+/// Finds the function or object symbol containing an address, returning it with the offset.
+pub fn symbolize(symbols: &[Symbol], addr: u64) -> Option<(&Symbol, u64)> {
+    let mut best: Option<&Symbol> = None;
+    for sym in symbols.iter() {
+        if sym.kind != STT_FUNC && sym.kind != STT_OBJECT || sym.addr() > addr {
+            continue;
+        }
+        if sym.size > 0 && addr >= sym.addr() + sym.size {
+            continue;
+        }
+        if best.map(|b| sym.addr() > b.addr() || b.size == 0 && sym.size > 0).unwrap_or(true) {
+            best = Some(sym);
+        }
+    }
+    best.map(|sym| (sym, addr - sym.addr()))
+}
+
+fn str_at(data: &[u8], strtab: &Section, pos: u64) -> Result<String> {
+    let (start, end) = match (strtab.offset.checked_add(pos), strtab.offset.checked_add(strtab.size)) {
+        (Some(start), Some(end)) => (start as usize, end as usize),
+        _ => bail!("malformed ELF file: string table at offset 0x{:x} is out of range", strtab.offset),
+    };
+    if start > end || end > data.len() {
+        bail!("string table index 0x{:x} out of range", pos);
+    }
+    let s = &data[start..end];
+    let len = s.iter().position(|b| *b == 0).unwrap_or(s.len());
+    Ok(String::from_utf8_lossy(&s[..len]).into_owned())
+}
+
+/// Demangles legacy Rust (and simple C++) symbol names, dropping the trailing hash.
+pub fn demangle(name: &str) -> String {
+    let mut rest = if let Some(rest) = name.strip_prefix("_ZN").or_else(|| name.strip_prefix("__ZN")) {
+        rest
+    } else {
+        return String::from(name);
+    };
+    let mut parts: Vec<String> = Vec::new();
+    while !rest.starts_with('E') {
+        let digits = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+        let len = if let Ok(len) = rest[..digits].parse::<usize>() {
+            len
+        } else {
+            return String::from(name);
+        };
+        let part = match digits.checked_add(len).and_then(|end| rest.get(digits..end)) {
+            Some(part) => part,
+            None => return String::from(name),
+        };
+        parts.push(unescape(part));
+        rest = &rest[digits + part.len()..];
+    }
+    if let Some(last) = parts.last().cloned() {
+        if last.len() == 17 && last.starts_with('h') && last[1..].chars().all(|c| c.is_ascii_hexdigit()) {
+            parts.pop();
+        }
+    }
+    parts.join("::")
+}
+
+fn unescape(part: &str) -> String {
+    let part = if part.starts_with("_$") { &part[1..] } else { part };
+    part.replace("$LT$", "<")
+        .replace("$GT$", ">")
+        .replace("$RF$", "&")
+        .replace("$BP$", "*")
+        .replace("$u20$", " ")
+        .replace("$u27$", "'")
+        .replace("$u5b$", "[")
+        .replace("$u5d$", "]")
+        .replace("$u7b$", "{")
+        .replace("$u7d$", "}")
+        .replace("$u7e$", "~")
+        .replace("$C$", ",")
+        .replace("..", "::")
+}
@@ -0,0 +1,294 @@
+//! Cortex-M fault post-mortem analysis.
+//!
+//! Everything is read through `debugger::Control::memory` and `debugger::Control::registers`, so
+//! any debugger backend can be used. The exception frame is found from the EXC_RETURN value in LR,
+//! or by scanning the main stack for one if the handler has since called other functions.
+
+use std::io::Write;
+
+use clap::ArgMatches;
+use config::Config;
+use debugger::Control;
+use device::Device;
+use elf::{self, Symbol};
+use itm;
+use mem::{self, Width};
+use printer::Printer;
+use Result;
+
+const SCB_CPUID: u32 = 0xE000_ED00;
+const SCB_CFSR: u32 = 0xE000_ED28;
+
+const REG_LR: u32 = 14;
+const REG_PC: u32 = 15;
+const REG_XPSR: u32 = 16;
+const REG_MSP: u32 = 17;
+const REG_PSP: u32 = 18;
+
+/// Number of stack words above the exception frame searched for return addresses.
+const STACK_SCAN_WORDS: usize = 64;
+const BACKTRACE_DEPTH: usize = 16;
+
+const CFSR_BITS: &[(u32, &str, &str)] = &[
+    (0, "IACCVIOL", "Instruction access violation"),
+    (1, "DACCVIOL", "Data access violation"),
+    (3, "MUNSTKERR", "MemManage fault on unstacking for an exception return"),
+    (4, "MSTKERR", "MemManage fault on stacking for exception entry"),
+    (5, "MLSPERR", "MemManage fault during floating-point lazy state preservation"),
+    (8, "IBUSERR", "Instruction bus error"),
+    (9, "PRECISERR", "Precise data bus error"),
+    (10, "IMPRECISERR", "Imprecise data bus error"),
+    (11, "UNSTKERR", "Bus fault on unstacking for an exception return"),
+    (12, "STKERR", "Bus fault on stacking for exception entry"),
+    (13, "LSPERR", "Bus fault during floating-point lazy state preservation"),
+    (16, "UNDEFINSTR", "Undefined instruction"),
+    (17, "INVSTATE", "Invalid state (executing with the Thumb bit clear)"),
+    (18, "INVPC", "Invalid PC load on exception return"),
+    (19, "NOCP", "No coprocessor (FPU access while disabled)"),
+    (20, "STKOF", "Stack overflow"),
+    (24, "UNALIGNED", "Unaligned access"),
+    (25, "DIVBYZERO", "Divide by zero"),
+];
+
+const CFSR_MMARVALID: u32 = 1 << 7;
+const CFSR_BFARVALID: u32 = 1 << 15;
+
+const HFSR_BITS: &[(u32, &str, &str)] = &[
+    (1, "VECTTBL", "Bus fault on vector table read"),
+    (30, "FORCED", "Escalated from a configurable fault"),
+    (31, "DEBUGEVT", "Debug event"),
+];
+
+/// The SCB fault status and address registers.
+#[derive(Debug, Clone, Copy)]
+pub struct FaultStatus {
+    pub cfsr: u32,
+    pub hfsr: u32,
+    pub mmfar: u32,
+    pub bfar: u32,
+}
+
+/// The registers stacked on exception entry.
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub addr: u32,
+    pub from_psp: bool,
+    pub r0: u32,
+    pub r1: u32,
+    pub r2: u32,
+    pub r3: u32,
+    pub r12: u32,
+    pub lr: u32,
+    pub pc: u32,
+    pub xpsr: u32,
+}
+
+#[derive(Debug)]
+pub struct Fault {
+    /// None on ARMv6-M cores, which have no configurable fault status registers.
+    pub status: Option<FaultStatus>,
+    pub pc: u32,
+    pub lr: u32,
+    pub xpsr: u32,
+    pub msp: u32,
+    pub psp: u32,
+    pub frame: Option<Frame>,
+    /// The stack above the exception frame, used to find earlier return addresses.
+    pub stack: Vec<u32>,
+}
+
+fn is_exc_return(value: u32) -> bool {
+    value & 0xffff_ff00 == 0xffff_ff00
+}
+
+fn words(data: &[u8]) -> Vec<u32> {
+    data.chunks(4)
+        .map(|w| w.iter().enumerate().fold(0, |v, (i, b)| v | (*b as u32) << (i * 8)))
+        .collect()
+}
+
+struct Target<'a> {
+    dbg: &'a Control,
+    cfg: &'a Config,
+    args: &'a ArgMatches<'a>,
+    cmd_args: &'a ArgMatches<'a>,
+    device: &'a Device,
+}
+
+impl<'a> Target<'a> {
+    fn read(&self, out: &mut Printer, addr: u32, count: usize) -> Result<Vec<u32>> {
+        let op = mem::Op::Read { addr, len: count * 4, width: Width::Word };
+        Ok(words(&self.dbg.memory(self.cfg, self.args, self.cmd_args, out, self.device, &op)?))
+    }
+
+    fn registers(&self, out: &mut Printer, regs: &[u32]) -> Result<Vec<u32>> {
+        self.dbg.registers(self.cfg, self.args, self.cmd_args, out, self.device, regs)
+    }
+}
+
+/// Halts the core and reads the fault status, core registers and exception frame.
+pub fn analyze(
+    dbg: &Control,
+    cfg: &Config,
+    args: &ArgMatches,
+    cmd_args: &ArgMatches,
+    out: &mut Printer,
+    device: &Device,
+) -> Result<Fault> {
+    if let Err(e) = dbg.halt(cfg, args, cmd_args, out, device) {
+        out.info("Fault", &format!("Unable to halt the core ({}); continuing", e))?;
+    }
+    let t = Target { dbg, cfg, args, cmd_args, device };
+
+    // The fault status registers are reserved on ARMv6-M (and ARMv8-M Baseline) cores, whose
+    // CPUID architecture field is 0xC, so they are not read there.
+    let armv6m = match t.read(out, SCB_CPUID, 1) {
+        Ok(ref r) if r.len() == 1 => (r[0] >> 16) & 0xf == 0xc,
+        _ => false,
+    };
+    let status = if armv6m {
+        None
+    } else {
+        match t.read(out, SCB_CFSR, 5) {
+            Ok(ref r) if r.len() == 5 => Some(FaultStatus { cfsr: r[0], hfsr: r[1], mmfar: r[3], bfar: r[4] }),
+            _ => None,
+        }
+    };
+    let regs = t.registers(out, &[REG_LR, REG_PC, REG_XPSR, REG_MSP, REG_PSP])?;
+    let (lr, pc, xpsr, msp, psp) = (regs[0], regs[1], regs[2], regs[3], regs[4]);
+
+    let mut fault = Fault { status, pc, lr, xpsr, msp, psp, frame: None, stack: Vec::new() };
+    if xpsr & 0x1ff == 0 {
+        return Ok(fault)
+    }
+
+    // Find the EXC_RETURN value: in LR if the handler hasn't called anything yet, otherwise
+    // pushed by the handler's prologue just below the frame on the main stack.
+    let (exc_return, sp) = if is_exc_return(lr) {
+        (lr, if lr & 0x4 != 0 { psp } else { msp })
+    } else {
+        let stack = t.read(out, msp, STACK_SCAN_WORDS)?;
+        match stack.iter().position(|v| is_exc_return(*v)) {
+            Some(i) => {
+                let exc_return = stack[i];
+                let sp = if exc_return & 0x4 != 0 { psp } else { msp + (i as u32 + 1) * 4 };
+                (exc_return, sp)
+            }
+            None => return Ok(fault),
+        }
+    };
+
+    // An extended frame (EXC_RETURN bit 4 clear) also holds S0-S15, FPSCR and a reserved word.
+    let frame_words = if exc_return & 0x10 == 0 { 26 } else { 8 };
+    let data = t.read(out, sp, frame_words + STACK_SCAN_WORDS)?;
+    if data.len() < frame_words {
+        bail!("Unable to read the exception frame at 0x{:08x}", sp);
+    }
+    let frame = Frame {
+        addr: sp,
+        from_psp: exc_return & 0x4 != 0,
+        r0: data[0],
+        r1: data[1],
+        r2: data[2],
+        r3: data[3],
+        r12: data[4],
+        lr: data[5],
+        pc: data[6],
+        xpsr: data[7],
+    };
+    // xPSR bit 9 is set if a padding word was added to align the frame.
+    let skip = frame_words + if frame.xpsr & (1 << 9) != 0 { 1 } else { 0 };
+    fault.stack = data.iter().skip(skip).cloned().collect();
+    fault.frame = Some(frame);
+    Ok(fault)
+}
+
+/// Returns the names and descriptions of the fault status bits that are set.
+pub fn reasons(status: &FaultStatus) -> Vec<(&'static str, &'static str)> {
+    let cfsr = CFSR_BITS.iter().filter(|b| status.cfsr & (1 << b.0) != 0);
+    let hfsr = HFSR_BITS.iter().filter(|b| status.hfsr & (1 << b.0) != 0);
+    cfsr.chain(hfsr).map(|b| (b.1, b.2)).collect()
+}
+
+fn symbol(symbols: &[Symbol], addr: u32) -> String {
+    match elf::symbolize(symbols, addr as u64 & !1) {
+        Some((sym, offset)) => format!("{} + 0x{:x}", elf::demangle(&sym.name), offset),
+        None => String::from("<unknown>"),
+    }
+}
+
+/// Returns the faulting PC, the caller from the stacked LR and any return addresses into
+/// functions found on the stack above the frame. The stack entries are a heuristic.
+pub fn backtrace(fault: &Fault, symbols: &[Symbol]) -> Vec<(u32, &'static str)> {
+    let mut trace = Vec::new();
+    let frame = if let Some(ref frame) = fault.frame {
+        frame
+    } else {
+        trace.push((fault.pc, "pc"));
+        return trace
+    };
+    trace.push((frame.pc, "pc"));
+    if !is_exc_return(frame.lr) {
+        trace.push((frame.lr & !1, "lr"));
+    }
+    for &value in fault.stack.iter() {
+        if trace.len() >= BACKTRACE_DEPTH {
+            break;
+        }
+        if value & 1 == 0 {
+            continue;
+        }
+        let is_code = match elf::symbolize(symbols, value as u64 & !1) {
+            Some((sym, _)) => sym.kind == elf::STT_FUNC,
+            None => false,
+        };
+        if is_code && trace.last().map(|t| t.0) != Some(value & !1) {
+            trace.push((value & !1, "stack"));
+        }
+    }
+    trace
+}
+
+pub fn write<W: Write>(out: &mut W, fault: &Fault, symbols: &[Symbol]) -> Result<()> {
+    match fault.status {
+        Some(ref status) => {
+            writeln!(out, "{:16} {:08x}", "CFSR", status.cfsr)?;
+            writeln!(out, "{:16} {:08x}", "HFSR", status.hfsr)?;
+            if status.cfsr & CFSR_MMARVALID != 0 {
+                writeln!(out, "{:16} {:08x}", "MMFAR", status.mmfar)?;
+            }
+            if status.cfsr & CFSR_BFARVALID != 0 {
+                writeln!(out, "{:16} {:08x}", "BFAR", status.bfar)?;
+            }
+            for (name, description) in reasons(status) {
+                writeln!(out, "{:16} {}", name, description)?;
+            }
+        }
+        None => writeln!(out, "{:16} Not available (ARMv6-M)", "Fault Status")?,
+    }
+    let exception = (fault.xpsr & 0x1ff) as u16;
+    writeln!(out, "{:16} {} ({})", "Exception", itm::exception_name(exception), exception)?;
+    writeln!(out, "{:16} {:08x} {}", "PC", fault.pc, symbol(symbols, fault.pc))?;
+    writeln!(out, "{:16} {:08x}", "LR", fault.lr)?;
+    writeln!(out, "{:16} {:08x}", "MSP", fault.msp)?;
+    writeln!(out, "{:16} {:08x}", "PSP", fault.psp)?;
+
+    match fault.frame {
+        Some(ref f) => {
+            writeln!(out, "{:16} {:08x} ({})", "Stack Frame", f.addr, if f.from_psp { "PSP" } else { "MSP" })?;
+            writeln!(out, "{:16} {:08x} {:08x} {:08x} {:08x}", "R0-R3", f.r0, f.r1, f.r2, f.r3)?;
+            writeln!(out, "{:16} {:08x}", "R12", f.r12)?;
+            writeln!(out, "{:16} {:08x} {}", "Stacked LR", f.lr, symbol(symbols, f.lr))?;
+            writeln!(out, "{:16} {:08x} {}", "Stacked PC", f.pc, symbol(symbols, f.pc))?;
+            writeln!(out, "{:16} {:08x}", "Stacked xPSR", f.xpsr)?;
+        }
+        None if exception == 0 => writeln!(out, "{:16} None (the core is not handling an exception)", "Stack Frame")?,
+        None => writeln!(out, "{:16} Not found", "Stack Frame")?,
+    }
+
+    writeln!(out, "Backtrace:")?;
+    for (i, (addr, source)) in backtrace(fault, symbols).iter().enumerate() {
+        writeln!(out, "  #{:<2} {:08x} {:5} {}", i, addr, source, symbol(symbols, *addr))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,762 @@
+//! ARMv7-M ITM and DWT trace packet decoding and output.
+
+use clap::ArgMatches;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::path::Path;
+use std::process::Command;
+
+use config::Config;
+use defmt;
+use device::Device;
+use printer::Printer;
+use Result;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExceptionFunction {
+    Enter,
+    Exit,
+    Return,
+    Unknown,
+}
+
+impl ExceptionFunction {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ExceptionFunction::Enter => "entered",
+            ExceptionFunction::Exit => "exited",
+            ExceptionFunction::Return => "returned to",
+            ExceptionFunction::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Packet {
+    Sync,
+    Overflow,
+    /// Cycles since the previous local timestamp. `tc` is the TC field: 0 if the timestamp is
+    /// synchronous to the data, otherwise the packet or timestamp was delayed.
+    LocalTimestamp { delta: u32, tc: u8 },
+    /// Bits [25:0] of the global timestamp.
+    GlobalTimestamp1 { value: u32, wrap: bool, clock_change: bool },
+    /// Bits [63:26] of the global timestamp.
+    GlobalTimestamp2 { value: u64 },
+    /// Stimulus port page (`hardware` false) or a source-defined extension.
+    Extension { hardware: bool, value: u32 },
+    Instrumentation { port: u16, data: Vec<u8> },
+    /// DWT event counter wraparound flags: CPI, EXC, SLEEP, LSU, FOLD and POSTCNT (bits 0-5).
+    EventCounter { flags: u8 },
+    Exception { number: u16, function: ExceptionFunction },
+    /// A sampled PC, or None if the core was sleeping.
+    PcSample { pc: Option<u32> },
+    DataTracePc { comparator: u8, pc: u32 },
+    DataTraceAddress { comparator: u8, offset: u16 },
+    DataTraceValue { comparator: u8, write: bool, value: u32, size: u8 },
+    /// A hardware source packet with an unknown discriminator.
+    Hardware { id: u8, data: Vec<u8> },
+    Unknown(u8),
+}
+
+/// Streaming decoder for a raw (unformatted) ITM/DWT trace.
+pub struct Decoder {
+    buf: Vec<u8>,
+    pos: usize,
+    page: u16,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder::new()
+    }
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Decoder { buf: Vec::new(), pos: 0, page: 0 }
+    }
+
+    /// Adds trace data to be decoded. Incomplete packets are kept until more data arrives.
+    pub fn push(&mut self, data: &[u8]) {
+        if self.pos > 0 {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+        self.buf.extend_from_slice(data);
+    }
+}
+
+impl Iterator for Decoder {
+    type Item = Packet;
+
+    /// Returns the next complete packet, if any.
+    fn next(&mut self) -> Option<Packet> {
+        let (packet, len) = decode(&self.buf[self.pos..], self.page)?;
+        self.pos += len;
+        if let Packet::Extension { hardware: false, value } = packet {
+            self.page = value as u16;
+        }
+        Some(packet)
+    }
+}
+
+/// Reads a payload of up to `max` bytes, where bit 7 of each byte but the last is a
+/// continuation flag. Returns the payload length, or None if the payload is incomplete.
+fn continued(buf: &[u8], max: usize) -> Option<usize> {
+    for (i, b) in buf.iter().take(max).enumerate() {
+        if b & 0x80 == 0 || i + 1 == max {
+            return Some(i + 1)
+        }
+    }
+    None
+}
+
+fn le(data: &[u8]) -> u32 {
+    data.iter().rev().fold(0, |acc, &b| (acc << 8) | b as u32)
+}
+
+fn decode(buf: &[u8], page: u16) -> Option<(Packet, usize)> {
+    let header = *buf.first()?;
+    match header {
+        0x00 => {
+            // A synchronization packet is at least 47 zero bits followed by a one bit.
+            let zeros = buf.iter().take_while(|&&b| b == 0).count();
+            if zeros == buf.len() {
+                return None
+            }
+            if zeros >= 5 && buf[zeros] == 0x80 {
+                Some((Packet::Sync, zeros + 1))
+            } else {
+                Some((Packet::Unknown(0), 1))
+            }
+        }
+        0x70 => Some((Packet::Overflow, 1)),
+        0x94 => {
+            let n = continued(&buf[1..], 4)?;
+            let p = &buf[1..1 + n];
+            let mut value = 0;
+            for (i, &b) in p.iter().enumerate() {
+                if i < 3 {
+                    value |= ((b & 0x7f) as u32) << (7 * i);
+                } else {
+                    value |= ((b & 0x1f) as u32) << 21;
+                }
+            }
+            let (wrap, clock_change) = if n == 4 {
+                (p[3] & 0x40 != 0, p[3] & 0x20 != 0)
+            } else {
+                (false, false)
+            };
+            Some((Packet::GlobalTimestamp1 { value, wrap, clock_change }, 1 + n))
+        }
+        0xb4 => {
+            let n = continued(&buf[1..], 6)?;
+            let value = buf[1..1 + n].iter().enumerate()
+                .fold(0u64, |acc, (i, &b)| acc | (((b & 0x7f) as u64) << (7 * i)));
+            Some((Packet::GlobalTimestamp2 { value }, 1 + n))
+        }
+        _ if header & 0xcf == 0xc0 => {
+            let n = continued(&buf[1..], 4)?;
+            let delta = buf[1..1 + n].iter().enumerate()
+                .fold(0u32, |acc, (i, &b)| acc | (((b & 0x7f) as u32) << (7 * i)));
+            Some((Packet::LocalTimestamp { delta, tc: (header >> 4) & 0x3 }, 1 + n))
+        }
+        _ if header & 0x8f == 0x00 => {
+            Some((Packet::LocalTimestamp { delta: ((header >> 4) & 0x7) as u32, tc: 0 }, 1))
+        }
+        _ if header & 0x0b == 0x08 => {
+            let hardware = header & 0x04 != 0;
+            let mut value = ((header >> 4) & 0x7) as u32;
+            let mut len = 1;
+            if header & 0x80 != 0 {
+                let n = continued(&buf[1..], 4)?;
+                for (i, &b) in buf[1..1 + n].iter().enumerate() {
+                    let bits = if i == 3 { b } else { b & 0x7f };
+                    value |= (bits as u32) << (3 + 7 * i);
+                }
+                len += n;
+            }
+            Some((Packet::Extension { hardware, value }, len))
+        }
+        _ if header & 0x03 != 0 => {
+            let size = match header & 0x03 {
+                1 => 1,
+                2 => 2,
+                _ => 4,
+            };
+            if buf.len() < 1 + size {
+                return None
+            }
+            let data = &buf[1..1 + size];
+            let id = header >> 3;
+            let packet = if header & 0x04 == 0 {
+                let port = page.wrapping_mul(32).wrapping_add(id as u16);
+                Packet::Instrumentation { port, data: data.to_vec() }
+            } else {
+                hardware_packet(id, data)
+            };
+            Some((packet, 1 + size))
+        }
+        _ => Some((Packet::Unknown(header), 1)),
+    }
+}
+
+fn hardware_packet(id: u8, data: &[u8]) -> Packet {
+    let comparator = (id >> 1) & 0x3;
+    match id {
+        0 if data.len() == 1 => Packet::EventCounter { flags: data[0] },
+        1 if data.len() == 2 => {
+            let function = match (data[1] >> 4) & 0x3 {
+                1 => ExceptionFunction::Enter,
+                2 => ExceptionFunction::Exit,
+                3 => ExceptionFunction::Return,
+                _ => ExceptionFunction::Unknown,
+            };
+            Packet::Exception { number: le(data) as u16 & 0x1ff, function }
+        }
+        2 if data.len() == 1 => Packet::PcSample { pc: None },
+        2 if data.len() == 4 => Packet::PcSample { pc: Some(le(data)) },
+        8..=15 if id & 1 == 0 && data.len() == 4 => Packet::DataTracePc { comparator, pc: le(data) },
+        8..=15 if id & 1 == 1 && data.len() == 2 => {
+            Packet::DataTraceAddress { comparator, offset: le(data) as u16 }
+        }
+        16..=23 => Packet::DataTraceValue {
+            comparator,
+            write: id & 1 == 1,
+            value: le(data),
+            size: data.len() as u8,
+        },
+        _ => Packet::Hardware { id, data: data.to_vec() },
+    }
+}
+
+pub fn exception_name(number: u16) -> String {
+    match number {
+        0 => String::from("Thread"),
+        1 => String::from("Reset"),
+        2 => String::from("NMI"),
+        3 => String::from("HardFault"),
+        4 => String::from("MemManage"),
+        5 => String::from("BusFault"),
+        6 => String::from("UsageFault"),
+        11 => String::from("SVCall"),
+        12 => String::from("DebugMonitor"),
+        14 => String::from("PendSV"),
+        15 => String::from("SysTick"),
+        n if n >= 16 => format!("IRQ{}", n - 16),
+        n => format!("Exception{}", n),
+    }
+}
+
+const EVENT_COUNTERS: [&str; 6] = ["CPI", "EXC", "SLEEP", "LSU", "FOLD", "CYC"];
+
+/// Formats a packet as a line of text, or returns None for packets that are only used for
+/// framing and timing.
+pub fn describe(packet: &Packet) -> Option<String> {
+    match *packet {
+        Packet::Sync | Packet::LocalTimestamp { .. } | Packet::GlobalTimestamp1 { .. } |
+        Packet::GlobalTimestamp2 { .. } | Packet::Extension { hardware: false, .. } => None,
+        Packet::Overflow => Some(String::from("overflow")),
+        Packet::Extension { hardware: true, value } => Some(format!("extension 0x{:08x}", value)),
+        Packet::Instrumentation { port, ref data } => Some(format!("[{}] {:?}", port, data)),
+        Packet::EventCounter { flags } => {
+            let names: Vec<&str> = EVENT_COUNTERS.iter().enumerate()
+                .filter(|&(i, _)| flags & (1 << i) != 0)
+                .map(|(_, name)| *name)
+                .collect();
+            Some(format!("event counter wrapped: {}", names.join(" ")))
+        }
+        Packet::Exception { number, function } => {
+            Some(format!("exception {} {} ({})", function.name(), exception_name(number), number))
+        }
+        Packet::PcSample { pc: Some(pc) } => Some(format!("pc 0x{:08x}", pc)),
+        Packet::PcSample { pc: None } => Some(String::from("pc sleeping")),
+        Packet::DataTracePc { comparator, pc } => {
+            Some(format!("dwt{} match pc 0x{:08x}", comparator, pc))
+        }
+        Packet::DataTraceAddress { comparator, offset } => {
+            Some(format!("dwt{} match address offset 0x{:04x}", comparator, offset))
+        }
+        Packet::DataTraceValue { comparator, write, value, size } => {
+            Some(format!("dwt{} {} 0x{:0width$x}", comparator, if write { "write" } else { "read" },
+                value, width = size as usize * 2))
+        }
+        Packet::Hardware { id, ref data } => Some(format!("hardware source {} {:?}", id, data)),
+        Packet::Unknown(header) => Some(format!("unknown header 0x{:02x}", header)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Write the data of the selected stimulus ports as-is.
+    Ports,
+    /// Write one timestamped line per stimulus port line or hardware packet.
+    Text,
+    /// Write the undecoded trace.
+    Raw,
+}
+
+impl Mode {
+    pub fn from_name(name: &str) -> Result<Mode> {
+        match name {
+            "ports" => Ok(Mode::Ports),
+            "text" => Ok(Mode::Text),
+            "raw" => Ok(Mode::Raw),
+            _ => bail!("Unknown ITM output mode: {}", name),
+        }
+    }
+}
+
+/// Trace setup for the debug probe.
+#[derive(Debug, Clone)]
+pub struct TraceConfig {
+    pub target_clk: u32,
+    pub trace_clk: u32,
+    /// Enable local timestamps and DWT exception trace.
+    pub timestamps: bool,
+    /// ITM_TER: one bit per stimulus port.
+    pub enable_mask: u32,
+    /// ITM_TPR: one bit per eight stimulus ports that only privileged code may write.
+    pub privilege_mask: u32,
+    /// Enable DWT PC sampling.
+    pub pc_sampling: Option<PcSampling>,
+}
+
+/// DWT PC sampling period: a sample is taken every (`postpreset` + 1) * 64 cycles, or * 1024
+/// cycles if `cyctap` is set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PcSampling {
+    pub cyctap: bool,
+    pub postpreset: u8,
+}
+
+impl PcSampling {
+    /// Returns the sampling period closest to `rate` samples per second at `target_clk`.
+    pub fn with_rate(target_clk: u32, rate: u32) -> PcSampling {
+        let period = (target_clk / rate.max(1)).max(64);
+        let (cyctap, tap) = if period >= 16 * 64 { (true, 1024) } else { (false, 64) };
+        let postpreset = ((period + tap / 2) / tap).clamp(1, 16) - 1;
+        PcSampling { cyctap, postpreset: postpreset as u8 }
+    }
+
+    pub fn period(&self) -> u32 {
+        (self.postpreset as u32 + 1) * if self.cyctap { 1024 } else { 64 }
+    }
+}
+
+/// Parses a decimal or 0x-prefixed hexadecimal mask.
+fn parse_mask(name: &str, value: &str) -> Result<u32> {
+    let v = value.trim();
+    let parsed = if v.starts_with("0x") || v.starts_with("0X") {
+        u32::from_str_radix(&v[2..], 16)
+    } else {
+        v.parse::<u32>()
+    };
+    match parsed {
+        Ok(mask) => Ok(mask),
+        Err(_) => bail!("Invalid {}: {}", name, value),
+    }
+}
+
+fn parse_clock(name: &str, value: &str) -> Result<u32> {
+    match value.trim().parse::<u32>() {
+        Ok(hz) => Ok(hz),
+        Err(_) => bail!("Invalid {}: {}", name, value),
+    }
+}
+
+/// Returns the TPIU_ACPR prescaler that divides `target_clk` down to the SWO `trace_clk`.
+///
+/// The SWO receiver tolerates a small baud rate error, so the nearest divisor is used as long as
+/// the resulting rate is within 3% of `trace_clk`.
+pub fn prescaler(target_clk: u32, trace_clk: u32) -> Result<u32> {
+    if trace_clk == 0 {
+        bail!("The ITM trace clock must be greater than zero");
+    }
+    if trace_clk > target_clk {
+        bail!("The ITM trace clock ({} Hz) is faster than the target clock ({} Hz)", trace_clk, target_clk);
+    }
+    let divisor = (target_clk as u64 + trace_clk as u64 / 2) / trace_clk as u64;
+    if divisor > ACPR_MAX_DIVISOR as u64 {
+        bail!(
+            "The ITM trace clock ({} Hz) is too slow for a {} Hz target clock; the minimum is {} Hz",
+            trace_clk, target_clk, target_clk.div_ceil(ACPR_MAX_DIVISOR),
+        );
+    }
+    let actual = target_clk as u64 / divisor;
+    if (actual as i64 - trace_clk as i64).abs() * 100 > trace_clk as i64 * 3 {
+        bail!(
+            "The ITM trace clock ({} Hz) can't be derived from a {} Hz target clock; the nearest is {} Hz",
+            trace_clk, target_clk, actual,
+        );
+    }
+    Ok(divisor as u32 - 1)
+}
+
+/// The TPIU_ACPR prescaler is 13 bits wide.
+const ACPR_MAX_DIVISOR: u32 = 0x2000;
+
+/// The default SWO trace clock; the fastest rate supported by the ST-Link/V2.
+pub const DEFAULT_TRACE_CLK: u32 = 2_000_000;
+
+/// Returns the trace setup from the command line and .bobbin/config.
+///
+/// With `--itm-target-clock auto` (or `target-clock = "auto"`) the core clock is read from the
+/// device before tracing starts.
+pub fn trace_config(
+    cfg: &Config,
+    cmd_args: &ArgMatches,
+    mode: Mode,
+    device: &Device,
+    out: &mut Printer,
+) -> Result<TraceConfig> {
+    let target_clk = match cfg.itm_target_clock(cmd_args) {
+        Some(ref v) if v == "auto" => {
+            let (hz, method) = device.detect_target_clock()?;
+            out.info("ITM", &format!("Detected target clock {} Hz ({})", hz, method))?;
+            hz
+        }
+        Some(v) => parse_clock("ITM target clock", &v)?,
+        None => bail!("itm-target-clock is required for ITM trace (use \"auto\" to detect it)."),
+    };
+    let trace_clk = match cfg.itm_trace_clock(cmd_args) {
+        Some(v) => parse_clock("ITM trace clock", &v)?,
+        None => DEFAULT_TRACE_CLK,
+    };
+    prescaler(target_clk, trace_clk)?;
+    let enable_mask = match cfg.itm_enable_mask(cmd_args) {
+        Some(v) => parse_mask("ITM enable mask", &v)?,
+        None => 0xffffffff,
+    };
+    let privilege_mask = match cfg.itm_privilege_mask(cmd_args) {
+        Some(v) => parse_mask("ITM privilege mask", &v)?,
+        None => 0xffffffff,
+    };
+    Ok(TraceConfig {
+        target_clk,
+        trace_clk,
+        timestamps: mode != Mode::Ports,
+        enable_mask,
+        privilege_mask,
+        pc_sampling: None,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Framing {
+    /// Write the port data as-is.
+    Text,
+    /// Write each stimulus port write as a line of hex bytes.
+    Hex,
+    /// Decode the port data as defmt frames, writing one line per frame.
+    Defmt,
+}
+
+impl Framing {
+    pub fn from_name(name: &str) -> Result<Framing> {
+        match name {
+            "text" => Ok(Framing::Text),
+            "hex" => Ok(Framing::Hex),
+            "defmt" => Ok(Framing::Defmt),
+            _ => bail!("Unknown ITM port framing: {}", name),
+        }
+    }
+}
+
+/// A destination for stimulus port data.
+pub enum Sink {
+    Stdout(io::Stdout),
+    File(File),
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+impl Sink {
+    /// Opens `stdout`, `file:<path>`, `pipe:<path>`, `udp:<host>:<port>` or
+    /// `tcp:<host>:<port>`. A named pipe is created if it does not exist, and opening it blocks
+    /// until a reader opens the other end.
+    pub fn open(spec: &str) -> Result<Sink> {
+        let (kind, target) = match spec.find(':') {
+            Some(i) => (&spec[..i], &spec[i + 1..]),
+            None => (spec, ""),
+        };
+        match kind {
+            "stdout" => Ok(Sink::Stdout(io::stdout())),
+            "file" => Ok(Sink::File(File::create(target)?)),
+            "pipe" => {
+                if !Path::new(target).exists() {
+                    let status = Command::new("mkfifo").arg(target).status()?;
+                    if !status.success() {
+                        bail!("Unable to create named pipe {}", target);
+                    }
+                }
+                Ok(Sink::File(OpenOptions::new().write(true).open(target)?))
+            }
+            "udp" => {
+                let socket = UdpSocket::bind("0.0.0.0:0")?;
+                socket.connect(target)?;
+                Ok(Sink::Udp(socket))
+            }
+            "tcp" => Ok(Sink::Tcp(TcpStream::connect(target)?)),
+            _ => bail!("Unknown ITM sink: {} (expected stdout, file:, pipe:, udp: or tcp:)", spec),
+        }
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Sink::Stdout(ref mut w) => w.write(buf),
+            Sink::File(ref mut w) => w.write(buf),
+            Sink::Udp(ref socket) => socket.send(buf),
+            Sink::Tcp(ref mut w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Sink::Stdout(ref mut w) => w.flush(),
+            Sink::File(ref mut w) => w.flush(),
+            Sink::Udp(_) => Ok(()),
+            Sink::Tcp(ref mut w) => w.flush(),
+        }
+    }
+}
+
+/// Where the data of a stimulus port is written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub port: u16,
+    pub framing: Framing,
+    pub sink: String,
+}
+
+impl Route {
+    /// Parses `<port>[:<framing>][=<sink>]`, e.g. `0`, `1=file:metrics.log` or
+    /// `2:hex=udp:127.0.0.1:9000`.
+    pub fn parse(spec: &str) -> Result<Route> {
+        let (head, sink) = match spec.find('=') {
+            Some(i) => (&spec[..i], &spec[i + 1..]),
+            None => (spec, "stdout"),
+        };
+        let (port, framing) = match head.find(':') {
+            Some(i) => (&head[..i], Framing::from_name(&head[i + 1..])?),
+            None => (head, Framing::Text),
+        };
+        let port = match port.parse::<u16>() {
+            Ok(port) => port,
+            Err(_) => bail!("Invalid ITM port: {}", spec),
+        };
+        Ok(Route { port, framing, sink: String::from(sink) })
+    }
+}
+
+/// Returns the stimulus port routes from --itm-port, or from the [[itm.port]] sections of
+/// .bobbin/config. Defaults to port 0 on stdout.
+pub fn routes(cfg: &Config, cmd_args: &ArgMatches) -> Result<Vec<Route>> {
+    let mut routes = Vec::new();
+    if let Some(values) = cmd_args.values_of("itm-port") {
+        for v in values {
+            routes.push(Route::parse(v)?);
+        }
+    } else {
+        for port in cfg.cfg_itm_ports() {
+            let framing = Framing::from_name(port.framing.as_deref().unwrap_or("text"))?;
+            let sink = port.sink.unwrap_or_else(|| String::from("stdout"));
+            routes.push(Route { port: port.port, framing, sink });
+        }
+    }
+    if routes.is_empty() {
+        routes.push(Route { port: 0, framing: Framing::Text, sink: String::from("stdout") });
+    }
+    Ok(routes)
+}
+
+/// Decodes a trace stream and writes it in the selected mode.
+pub struct Output {
+    mode: Mode,
+    // Port, framing and index into `sinks`.
+    routes: Vec<(u16, Framing, usize)>,
+    // Sinks with the output pending for each; sink 0 is stdout.
+    sinks: Vec<(Sink, Vec<u8>)>,
+    decoder: Decoder,
+    capture: Option<File>,
+    lines: BTreeMap<u16, Vec<u8>>,
+    timestamp: u64,
+    defmt_table: Option<defmt::Table>,
+    defmt: BTreeMap<u16, defmt::Decoder>,
+}
+
+/// Returns the output for the --itm-output, --itm-port and --itm-capture options.
+pub fn output(cfg: &Config, cmd_args: &ArgMatches) -> Result<Output> {
+    let mode = Mode::from_name(&cfg.itm_output(cmd_args).unwrap_or_else(|| String::from("ports")))?;
+    let capture = if let Some(path) = cmd_args.value_of("itm-capture") {
+        Some(File::create(path)?)
+    } else {
+        None
+    };
+    Output::new(mode, &routes(cfg, cmd_args)?, capture)
+}
+
+impl Output {
+    /// Creates an output, opening the sinks of `routes`. Routes with the same sink share it.
+    pub fn new(mode: Mode, routes: &[Route], capture: Option<File>) -> Result<Self> {
+        let mut names = vec![String::from("stdout")];
+        let mut sinks = vec![(Sink::Stdout(io::stdout()), Vec::new())];
+        let mut indexes = Vec::new();
+        for route in routes.iter() {
+            let index = if let Some(i) = names.iter().position(|n| *n == route.sink) {
+                i
+            } else {
+                sinks.push((Sink::open(&route.sink)?, Vec::new()));
+                names.push(route.sink.clone());
+                names.len() - 1
+            };
+            indexes.push((route.port, route.framing, index));
+        }
+        Ok(Output {
+            mode,
+            routes: indexes,
+            sinks,
+            decoder: Decoder::new(),
+            capture,
+            lines: BTreeMap::new(),
+            timestamp: 0,
+            defmt_table: None,
+            defmt: BTreeMap::new(),
+        })
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Sets the defmt table used to decode ports with the defmt framing.
+    pub fn set_defmt(&mut self, table: defmt::Table) {
+        self.defmt_table = Some(table);
+    }
+
+    /// Returns true if any port is routed with the defmt framing.
+    pub fn uses_defmt(&self) -> bool {
+        self.routes.iter().any(|r| r.1 == Framing::Defmt)
+    }
+
+    /// Decodes and writes a chunk of raw trace data.
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        if let Some(ref mut capture) = self.capture {
+            capture.write_all(data)?;
+        }
+        if self.mode == Mode::Raw {
+            self.sinks[0].1.extend_from_slice(data);
+        } else {
+            self.decoder.push(data);
+            while let Some(packet) = self.decoder.next() {
+                self.packet(packet)?;
+            }
+        }
+        self.flush()
+    }
+
+    /// Writes any partial stimulus port lines.
+    pub fn finish(&mut self) -> Result<()> {
+        let ports: Vec<u16> = self.lines.keys().cloned().collect();
+        for port in ports {
+            self.line(port)?;
+        }
+        self.flush()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        for &mut (ref mut sink, ref mut pending) in self.sinks.iter_mut() {
+            if !pending.is_empty() {
+                sink.write_all(pending)?;
+                sink.flush()?;
+                pending.clear();
+            }
+        }
+        Ok(())
+    }
+
+    fn route(&self, port: u16) -> Option<(Framing, usize)> {
+        self.routes.iter().find(|r| r.0 == port).map(|r| (r.1, r.2))
+    }
+
+    fn packet(&mut self, packet: Packet) -> Result<()> {
+        match packet {
+            Packet::LocalTimestamp { delta, .. } => {
+                self.timestamp += delta as u64;
+            }
+            Packet::Instrumentation { port, ref data } => {
+                let (framing, sink) = match self.route(port) {
+                    Some(route) => route,
+                    None => return Ok(()),
+                };
+                if framing == Framing::Defmt {
+                    return self.defmt_frames(port, sink, data)
+                }
+                let pending = &mut self.sinks[sink].1;
+                match (self.mode, framing) {
+                    (Mode::Text, Framing::Text) => {
+                        for &b in data.iter() {
+                            if b == b'\n' {
+                                self.line(port)?;
+                            } else if b != b'\r' {
+                                self.lines.entry(port).or_default().push(b);
+                            }
+                        }
+                    }
+                    (Mode::Text, Framing::Hex) => {
+                        write!(pending, "{:>12} [{}] ", self.timestamp, port)?;
+                        writeln!(pending, "{}", hex(data))?;
+                    }
+                    (_, Framing::Text) => pending.extend_from_slice(data),
+                    (_, Framing::Hex) => writeln!(pending, "{}", hex(data))?,
+                    (_, Framing::Defmt) => {}
+                }
+            }
+            _ => {
+                if self.mode == Mode::Text {
+                    if let Some(text) = describe(&packet) {
+                        writeln!(self.sinks[0].1, "{:>12} {}", self.timestamp, text)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn defmt_frames(&mut self, port: u16, sink: usize, data: &[u8]) -> Result<()> {
+        if !self.defmt.contains_key(&port) {
+            let table = match self.defmt_table {
+                Some(ref table) => table.clone(),
+                None => bail!("ITM port {} uses the defmt framing, but no defmt table was loaded", port),
+            };
+            self.defmt.insert(port, defmt::Decoder::new(table));
+        }
+        let decoder = self.defmt.get_mut(&port).unwrap();
+        decoder.push(data);
+        for frame in decoder {
+            if self.mode == Mode::Text {
+                writeln!(self.sinks[sink].1, "{:>12} [{}] {}", self.timestamp, port, frame)?;
+            } else {
+                writeln!(self.sinks[sink].1, "{}", frame)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn line(&mut self, port: u16) -> Result<()> {
+        if let Some(line) = self.lines.remove(&port) {
+            let sink = self.route(port).map(|r| r.1).unwrap_or(0);
+            writeln!(self.sinks[sink].1, "{:>12} [{}] {}", self.timestamp, port, String::from_utf8_lossy(&line))?;
+        }
+        Ok(())
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    let bytes: Vec<String> = data.iter().map(|b| format!("{:02x}", b)).collect();
+    bytes.join(" ")
+}
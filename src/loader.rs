@@ -1,6 +1,7 @@
 use clap::ArgMatches;
 use std::io::Write;
-use std::process::{Command, ExitStatus};
+use std::process::Command;
+use std::fs::File;
 use std::env;
 use config::Config;
 use printer::Printer;
@@ -12,6 +13,10 @@ use tempfile;
 use Result;
 
 use blackmagic::blackmagic_scan;
+use elf::{self, Elf};
+
+#[cfg(feature = "stlink")]
+use stlink;
 
 pub trait Load {
     fn load(
@@ -33,6 +38,10 @@ pub fn loader(loader_type: &str) -> Option<Box<Load>> {
         "teensy" => Some(Box::new(TeensyLoader {})),
         "dfu-util" => Some(Box::new(DfuUtilLoader {})),
         "blackmagic" => Some(Box::new(BlackMagicLoader {})),
+        #[cfg(feature = "stlink")]
+        "stlink" => Some(Box::new(StLinkLoader {})),
+        #[cfg(not(feature = "stlink"))]
+        "stlink" => Some(Box::new(OpenOcdLoader {})),
         _ => None,
     }
 }
@@ -307,7 +316,7 @@ impl Load for DfuUtilLoader {
     ) -> Result<()> {
         let mut dst = PathBuf::from(target);
         dst.set_extension("bin");
-        objcopy("binary", target, &dst)?;
+        let base = objcopy("binary", target, &dst)?;
 
         // Execute Command
 
@@ -316,7 +325,7 @@ impl Load for DfuUtilLoader {
         let mut cmd = Command::new("dfu-util");
         cmd.arg("-d").arg(format!("{:04x}:{:04x}", device.usb().vendor_id, device.usb().product_id));
         cmd.arg("-a").arg("0");
-        cmd.arg("-s").arg("0x08000000");
+        cmd.arg("-s").arg(format!("0x{:08x}", base));
         cmd.arg("-D").arg(dst);
 
         let status = if out.is_verbose() {
@@ -386,10 +395,103 @@ impl Load for BlackMagicLoader {
     }
 }
 
-pub fn objcopy(output: &str, src: &Path, dst: &Path) -> Result<ExitStatus> {
-    let mut cmd = Command::new("arm-none-eabi-objcopy");
-    cmd.arg("-O").arg(output).arg(src).arg(dst);
-    Ok(cmd.status()?)
+/// Programs STM32 flash directly over an ST-Link probe, without OpenOCD.
+#[cfg(feature = "stlink")]
+pub struct StLinkLoader {}
+
+#[cfg(feature = "stlink")]
+impl Load for StLinkLoader {
+    fn load(
+        &self,
+        _cfg: &Config,
+        args: &ArgMatches,
+        _cmd_args: &ArgMatches,
+        out: &mut Printer,
+        device: &Device,
+        target: &Path,
+    ) -> Result<()> {
+        use stlink::flash::{self, Flash};
+
+        let elf = Elf::open(target)?;
+        let image = match elf.load_image() {
+            Ok(image) => image,
+            Err(e) => bail!("{}: {}", target.display(), e),
+        };
+        let (base, data) = elf::to_binary(&image, 0xff)?;
+
+        out.info("Loading", &format!("{}", target.display()))?;
+        stlink::with_debugger(device, 0, 0, |d| {
+            d.reset_halt()?;
+            let chip = flash::identify(d)?;
+            out.verbose("stlink", &format!("{} (device id 0x{:03x}), {}K flash",
+                chip.name, chip.dev_id, chip.flash_size >> 10))?;
+            let mut flash = Flash::new(d, chip)?;
+
+            // Pad the image out to whole flash writes.
+            let unit = flash.write_unit() as u64;
+            let start = base & !(unit - 1);
+            let mut buf = vec![0xff; (base - start) as usize];
+            buf.extend_from_slice(&data);
+            while buf.len() as u64 % unit != 0 {
+                buf.push(0xff);
+            }
+            if start > u64::from(u32::MAX) - buf.len() as u64 {
+                bail!("{} is not loaded into flash", target.display());
+            }
+            let start = start as u32;
+
+            let sectors = flash.sectors_for(start, buf.len())?;
+            flash.unlock()?;
+            // Lock the flash again even if erasing or programming fails.
+            let written = (|| -> Result<()> {
+                out.verbose("stlink", &format!("Erasing {} sectors", sectors.len()))?;
+                for sector in sectors.iter() {
+                    flash.erase_sector(sector)?;
+                }
+                out.verbose("stlink", &format!("Programming {} bytes at 0x{:08x}", buf.len(), start))?;
+                flash.program(start, &buf)
+            })();
+            let locked = flash.lock();
+            written?;
+            locked?;
+            out.verbose("stlink", "Verifying")?;
+            flash.verify(start, &buf)?;
+            drop(flash);
+
+            if args.is_present("run") || args.is_present("test") {
+                d.reset_run()?;
+            }
+            Ok(())
+        })?;
+        out.info(
+            "Complete",
+            &format!("Successfully flashed device"),
+        )?;
+        Ok(())
+    }
+}
+
+/// Converts an ELF file to a raw binary ("binary") or Intel HEX ("ihex") file, returning the
+/// load address of the first byte. Gaps between segments are filled with 0xff.
+pub fn objcopy(output: &str, src: &Path, dst: &Path) -> Result<u64> {
+    let elf = Elf::open(src)?;
+    let image = match elf.load_image() {
+        Ok(image) => image,
+        Err(e) => bail!("{}: {}", src.display(), e),
+    };
+    let mut file = File::create(dst)?;
+    match output {
+        "binary" => {
+            let (base, data) = elf::to_binary(&image, 0xff)?;
+            file.write_all(&data)?;
+            Ok(base)
+        }
+        "ihex" => {
+            file.write_all(elf::to_ihex(&image, elf.entry)?.as_bytes())?;
+            Ok(image[0].0)
+        }
+        _ => bail!("Unknown objcopy output format: {}", output),
+    }
 }
 
 // pub struct RemoteLoader {}
@@ -0,0 +1,254 @@
+//! Console session logs.
+//!
+//! Each line received from the device is written with the host time it started arriving, both
+//! as a UTC date and as the number of seconds since the device was last reset (or the console
+//! was opened). Lines typed into the console can be logged too, prefixed with "> ".
+
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use clap::ArgMatches;
+use config::Config;
+use device::Device;
+use Result;
+
+/// The number of rotated logs kept by default.
+const DEFAULT_KEEP: u32 = 5;
+
+/// Where and how to write the session log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    /// The log file, or the directory in which a file named after the device and the time is
+    /// created.
+    pub target: Target,
+    /// Also log the lines sent to the device.
+    pub input: bool,
+    /// Rotate the log when it grows past this size.
+    pub max_size: Option<u64>,
+    /// The number of rotated logs (`<file>.1` to `<file>.<keep>`) to keep.
+    pub keep: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    File(PathBuf),
+    Dir(PathBuf),
+}
+
+/// Returns the log settings from --log, --log-dir, --log-input and --log-max-size (or the
+/// [console] section of .bobbin/config), or None if the session is not logged.
+pub fn settings(cfg: &Config, cmd_args: &ArgMatches) -> Result<Option<Settings>> {
+    let target = if let Some(path) = cmd_args.value_of("log") {
+        Target::File(PathBuf::from(path))
+    } else if let Some(dir) = cfg.console_log_dir(cmd_args) {
+        Target::Dir(PathBuf::from(dir))
+    } else {
+        return Ok(None)
+    };
+    let max_size = match cfg.console_log_max_size(cmd_args) {
+        Some(value) => Some(parse_size(&value)?),
+        None => None,
+    };
+    Ok(Some(Settings {
+        target,
+        input: cfg.console_log_input(cmd_args),
+        max_size,
+        keep: cfg.cfg_console_log_keep().unwrap_or(DEFAULT_KEEP),
+    }))
+}
+
+/// Parses a size in bytes, with an optional K, M or G suffix.
+pub fn parse_size(value: &str) -> Result<u64> {
+    let v = value.trim();
+    let (digits, scale) = match v.chars().last() {
+        Some('k') | Some('K') => (&v[..v.len() - 1], 1 << 10),
+        Some('m') | Some('M') => (&v[..v.len() - 1], 1 << 20),
+        Some('g') | Some('G') => (&v[..v.len() - 1], 1 << 30),
+        _ => (v, 1),
+    };
+    match digits.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(n * scale),
+        _ => bail!("Invalid log size: {} (expected e.g. 512K or 10M)", value),
+    }
+}
+
+/// Formats a time as a UTC date with milliseconds, e.g. "2017-06-01T12:34:56.789Z".
+pub fn utc(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0));
+    let secs = since.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let s = secs % 86_400;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, s / 3600, s / 60 % 60, s % 60, since.subsec_millis())
+}
+
+/// Converts days since 1970-01-01 to a (year, month, day) date in the proleptic Gregorian
+/// calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// A console session log.
+pub struct Log {
+    file: File,
+    path: PathBuf,
+    size: u64,
+    settings: Settings,
+    reset_time: Instant,
+    // The partial line received so far and the time its first byte arrived.
+    line: Vec<u8>,
+    line_time: Option<(SystemTime, Instant)>,
+}
+
+impl Log {
+    /// Opens the log, appending to an existing log file. `console` describes the console in the
+    /// session header.
+    pub fn open(settings: &Settings, device: Option<&Device>, console: &str) -> Result<Log> {
+        let path = match settings.target {
+            Target::File(ref path) => path.clone(),
+            Target::Dir(ref dir) => {
+                fs::create_dir_all(dir)?;
+                let id = device.map(|d| String::from(&d.hash()[..8])).unwrap_or_else(|| String::from("console"));
+                let stamp: String = utc(SystemTime::now()).chars()
+                    .take(19)
+                    .filter(|c| c.is_ascii_digit() || *c == 'T')
+                    .map(|c| if c == 'T' { '-' } else { c })
+                    .collect();
+                dir.join(format!("{}-{}.log", id, stamp))
+            }
+        };
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        let mut log = Log {
+            file,
+            path,
+            size,
+            settings: settings.clone(),
+            reset_time: Instant::now(),
+            line: Vec::new(),
+            line_time: None,
+        };
+        let mut header = format!("# {} bobbin console session on {}", utc(SystemTime::now()), console);
+        if let Some(device) = device {
+            header.push_str(&format!(" (device {})", &device.hash()[..8]));
+        }
+        log.write_line(&header)?;
+        Ok(log)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Restarts the relative timestamps, after the device has been reset or reloaded.
+    pub fn reset(&mut self) -> Result<()> {
+        self.flush_line()?;
+        self.reset_time = Instant::now();
+        self.note("reset")
+    }
+
+    /// Writes a session event, such as the console disconnecting, as a "# <time> <text>" line.
+    pub fn note(&mut self, text: &str) -> Result<()> {
+        self.flush_line()?;
+        let line = format!("# {} {}", utc(SystemTime::now()), text);
+        self.write_line(&line)
+    }
+
+    /// Logs data received from the device; lines are written once they are complete.
+    pub fn received(&mut self, data: &[u8]) -> Result<()> {
+        for b in data {
+            if self.line_time.is_none() {
+                self.line_time = Some((SystemTime::now(), Instant::now()));
+            }
+            if *b == b'\n' {
+                self.flush_line()?;
+            } else {
+                self.line.push(*b);
+            }
+        }
+        Ok(())
+    }
+
+    /// Logs a complete line received from the device, such as a decoded defmt frame.
+    pub fn received_line(&mut self, line: &str) -> Result<()> {
+        self.flush_line()?;
+        let line = self.stamp(SystemTime::now(), Instant::now(), line);
+        self.write_line(&line)
+    }
+
+    /// Logs data sent to the device, if input logging is enabled.
+    pub fn sent(&mut self, data: &[u8]) -> Result<()> {
+        if !self.settings.input {
+            return Ok(())
+        }
+        let text = String::from_utf8_lossy(data);
+        for line in text.trim_end_matches('\n').split('\n') {
+            let line = self.stamp(SystemTime::now(), Instant::now(), &format!("> {}", line.trim_end_matches('\r')));
+            self.write_line(&line)?;
+        }
+        Ok(())
+    }
+
+    /// Writes any partial line.
+    pub fn flush_line(&mut self) -> Result<()> {
+        if let Some((time, instant)) = self.line_time.take() {
+            let text = String::from_utf8_lossy(&self.line).into_owned();
+            let line = self.stamp(time, instant, text.trim_end_matches('\r'));
+            self.line.clear();
+            self.write_line(&line)?;
+        }
+        Ok(())
+    }
+
+    fn stamp(&self, time: SystemTime, instant: Instant, text: &str) -> String {
+        let since = instant.saturating_duration_since(self.reset_time);
+        format!("{} +{}.{:06} {}", utc(time), since.as_secs(), since.subsec_micros(), text)
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        if self.settings.max_size.is_some_and(|max| self.size + line.len() as u64 + 1 > max) && self.size > 0 {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.file.flush()?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    /// Renames the log to `<file>.1`, shifting older logs up and removing the oldest, and starts
+    /// a new log.
+    fn rotate(&mut self) -> Result<()> {
+        let rotated = |n: u32| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        if self.settings.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(rotated(self.settings.keep));
+            for n in (1..self.settings.keep).rev() {
+                if rotated(n).exists() {
+                    fs::rename(rotated(n), rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated(1))?;
+        }
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Drop for Log {
+    fn drop(&mut self) {
+        let _ = self.flush_line();
+    }
+}
@@ -7,6 +7,8 @@ extern crate error_chain;
 extern crate clap;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde;
+extern crate serde_json;
 extern crate toml;
 extern crate sha1;
 extern crate plist;
@@ -16,6 +18,7 @@ extern crate tempfile;
 extern crate regex;
 extern crate os_type;
 extern crate semver;
+extern crate termios;
 
 #[cfg(feature = "stlink")]
 extern crate byteorder;
@@ -35,6 +38,20 @@ mod printer;
 mod console;
 mod check;
 mod blackmagic;
+mod elf;
+mod record;
+mod report;
+mod watch;
+mod itm;
+mod profile;
+mod mem;
+mod fault;
+mod rtt;
+mod semihosting;
+mod defmt;
+mod logfile;
+mod terminal;
+mod view;
 
 #[cfg(feature = "stlink")]
 mod stlink;
@@ -57,6 +74,7 @@ mod errors {
             PList(::plist::Error);
             Toml(::toml::de::Error);
             Serial(::serial::Error);
+            Json(::serde_json::Error);
             LibUsb(::libusb::Error) #[cfg(feature="stlink")];
         }
     }
@@ -121,6 +139,14 @@ fn run() -> Result<()> {
         cmd::screen(&cfg, &args, cmd_args, &mut out)
     } else if let Some(cmd_args) = args.subcommand_matches("itm") {
         cmd::itm(&cfg, &args, cmd_args, &mut out)
+    } else if let Some(cmd_args) = args.subcommand_matches("objdump") {
+        cmd::objdump(&cfg, &args, cmd_args, &mut out)
+    } else if let Some(cmd_args) = args.subcommand_matches("profile") {
+        cmd::profile(&cfg, &args, cmd_args, &mut out)
+    } else if let Some(cmd_args) = args.subcommand_matches("mem") {
+        cmd::mem(&cfg, &args, cmd_args, &mut out)
+    } else if let Some(cmd_args) = args.subcommand_matches("fault") {
+        cmd::fault(&cfg, &args, cmd_args, &mut out)
     } else {
         println!("{}", args.usage());
         Ok(())
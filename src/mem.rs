@@ -0,0 +1,126 @@
+//! Target memory access helpers.
+
+use std::io::Write;
+
+use Result;
+
+/// The access width used to read or write target memory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Width {
+    Byte,
+    Half,
+    Word,
+}
+
+impl Width {
+    pub fn from_name(name: &str) -> Result<Width> {
+        match name {
+            "8" => Ok(Width::Byte),
+            "16" => Ok(Width::Half),
+            "32" => Ok(Width::Word),
+            _ => bail!("Unknown memory access width: {} (expected 8, 16 or 32)", name),
+        }
+    }
+
+    pub fn bytes(&self) -> usize {
+        match *self {
+            Width::Byte => 1,
+            Width::Half => 2,
+            Width::Word => 4,
+        }
+    }
+
+    pub fn bits(&self) -> usize {
+        self.bytes() * 8
+    }
+
+    /// Returns the largest value that fits in this width.
+    pub fn max(&self) -> u32 {
+        match *self {
+            Width::Byte => 0xff,
+            Width::Half => 0xffff,
+            Width::Word => 0xffff_ffff,
+        }
+    }
+}
+
+/// Direct target memory access, for debuggers that hold a connection to the target.
+pub trait Memory {
+    fn read(&mut self, addr: u32, data: &mut [u8]) -> Result<()>;
+    fn write(&mut self, addr: u32, data: &[u8]) -> Result<()>;
+}
+
+/// A memory access performed through `debugger::Control::memory`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    /// Read `len` bytes at `addr` using `width` accesses.
+    Read { addr: u32, len: usize, width: Width },
+    /// Write `value` at `addr` using a single `width` access.
+    Write { addr: u32, value: u32, width: Width },
+}
+
+/// Parses a decimal or 0x-prefixed hexadecimal number.
+pub fn parse_number(name: &str, value: &str) -> Result<u32> {
+    let v = value.trim();
+    let parsed = if v.starts_with("0x") || v.starts_with("0X") {
+        u32::from_str_radix(&v[2..], 16)
+    } else {
+        v.parse::<u32>()
+    };
+    match parsed {
+        Ok(n) => Ok(n),
+        Err(_) => bail!("Invalid {}: {}", name, value),
+    }
+}
+
+/// Checks that `addr` and `len` are aligned to `width` and that the range fits in the address
+/// space.
+pub fn check_range(addr: u32, len: usize, width: Width) -> Result<()> {
+    if !(addr as usize).is_multiple_of(width.bytes()) {
+        bail!("Address 0x{:08x} is not aligned to the {}-bit access width", addr, width.bits());
+    }
+    if !len.is_multiple_of(width.bytes()) {
+        bail!("Length {} is not a multiple of the {}-bit access width", len, width.bits());
+    }
+    if addr as u64 + len as u64 > 0x1_0000_0000 {
+        bail!("Range 0x{:08x} + {} is outside the 32-bit address space", addr, len);
+    }
+    Ok(())
+}
+
+/// Converts values read at `width` into little-endian bytes.
+pub fn to_bytes(values: &[u32], width: Width) -> Vec<u8> {
+    let mut data = Vec::with_capacity(values.len() * width.bytes());
+    for value in values {
+        for i in 0..width.bytes() {
+            data.push((value >> (i * 8)) as u8);
+        }
+    }
+    data
+}
+
+/// Writes `data` as a hexdump: sixteen bytes per line, grouped into little-endian values of
+/// `width`, followed by the printable ASCII characters.
+pub fn hexdump<W: Write>(out: &mut W, addr: u32, data: &[u8], width: Width) -> Result<()> {
+    let n = width.bytes();
+    for (i, line) in data.chunks(16).enumerate() {
+        write!(out, "{:08x}:", addr as usize + i * 16)?;
+        let mut column = 0;
+        for unit in line.chunks(n) {
+            let mut value = 0u32;
+            for (j, b) in unit.iter().enumerate() {
+                value |= (*b as u32) << (j * 8);
+            }
+            write!(out, " {:01$x}", value, n * 2)?;
+            column += n * 2 + 1;
+        }
+        let full = 16 / n * (n * 2 + 1);
+        write!(out, "{:1$}  ", "", full - column)?;
+        for b in line {
+            let c = *b as char;
+            write!(out, "{}", if c.is_ascii_graphic() || c == ' ' { c } else { '.' })?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
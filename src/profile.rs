@@ -0,0 +1,82 @@
+//! Statistical profiling from DWT PC samples.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use elf::{self, Symbol};
+use itm::{Decoder, Packet};
+use Result;
+
+/// Collects PC samples from a raw ITM/DWT trace.
+#[derive(Default)]
+pub struct Profile {
+    decoder: Decoder,
+    samples: BTreeMap<u32, u64>,
+    sleeping: u64,
+    overflows: u64,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Profile::default()
+    }
+
+    /// Decodes a chunk of raw trace data.
+    pub fn add(&mut self, data: &[u8]) {
+        self.decoder.push(data);
+        for packet in &mut self.decoder {
+            match packet {
+                Packet::PcSample { pc: Some(pc) } => *self.samples.entry(pc).or_insert(0) += 1,
+                Packet::PcSample { pc: None } => self.sleeping += 1,
+                Packet::Overflow => self.overflows += 1,
+                _ => {}
+            }
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.samples.values().sum::<u64>() + self.sleeping
+    }
+
+    pub fn overflows(&self) -> u64 {
+        self.overflows
+    }
+
+    /// Returns the number of samples per function, most frequent first. Sleep samples are
+    /// counted as `<sleep>` and addresses without a symbol as `<unknown>`.
+    pub fn functions(&self, symbols: &[Symbol]) -> Vec<(String, u64)> {
+        let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+        for (&pc, &count) in self.samples.iter() {
+            let name = match elf::symbolize(symbols, pc as u64 & !1) {
+                Some((sym, _)) => elf::demangle(&sym.name),
+                None => String::from("<unknown>"),
+            };
+            *counts.entry(name).or_insert(0) += count;
+        }
+        if self.sleeping > 0 {
+            counts.insert(String::from("<sleep>"), self.sleeping);
+        }
+        let mut functions: Vec<(String, u64)> = counts.into_iter().collect();
+        functions.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        functions
+    }
+
+    /// Writes a flat profile of the `limit` most frequently sampled functions.
+    pub fn write_flat<W: Write>(&self, out: &mut W, symbols: &[Symbol], limit: usize) -> Result<()> {
+        let total = self.total().max(1);
+        writeln!(out, "{:>10} {:>7} Function", "Samples", "Percent")?;
+        for (name, count) in self.functions(symbols).iter().take(limit) {
+            writeln!(out, "{:>10} {:>6.2}% {}", count, *count as f64 * 100.0 / total as f64, name)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the samples in folded-stack format, one `function count` line per function, for
+    /// flame graph tools. PC samples carry no call stack, so each stack has a single frame.
+    pub fn write_folded<W: Write>(&self, out: &mut W, symbols: &[Symbol]) -> Result<()> {
+        for (name, count) in self.functions(symbols).iter() {
+            writeln!(out, "{} {}", name.replace(' ', "_").replace(';', ":"), count)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,198 @@
+//! Machine-readable records for the list, info and check subcommands.
+
+use clap::ArgMatches;
+use serde::Serialize;
+use serde_json;
+use std::io::Write;
+
+use debugger::ProbeInfo;
+use device::Device;
+use Result;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+    Tsv,
+}
+
+/// Returns the output format selected with --format, which may be given before or after the subcommand.
+pub fn format(args: &ArgMatches, cmd_args: &ArgMatches) -> Result<Format> {
+    match cmd_args.value_of("format").or_else(|| args.value_of("format")) {
+        None | Some("text") => Ok(Format::Text),
+        Some("json") => Ok(Format::Json),
+        Some("tsv") => Ok(Format::Tsv),
+        Some(other) => bail!("Unknown output format: {}", other),
+    }
+}
+
+pub trait Record {
+    fn columns() -> &'static [&'static str];
+    fn values(&self) -> Vec<String>;
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceRecord {
+    pub id: String,
+    pub vendor_id: String,
+    pub product_id: String,
+    pub vendor: String,
+    pub product: String,
+    pub serial_number: String,
+    pub location_id: Option<i64>,
+    pub usb_path: Option<String>,
+    pub device_type: Option<String>,
+    pub loader_type: Option<String>,
+    pub debugger_type: Option<String>,
+    pub bossa_path: Option<String>,
+    pub cdc_path: Option<String>,
+    pub msd_path: Option<String>,
+    pub gdb_path: Option<String>,
+    pub openocd_serial: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub probe: Option<ProbeRecord>,
+}
+
+/// The fields reported by `bobbin info --probe`.
+#[derive(Debug, Default, Serialize)]
+pub struct ProbeRecord {
+    pub firmware: Option<String>,
+    pub api: Option<String>,
+    pub target_voltage: Option<f32>,
+    pub mode: Option<String>,
+    pub core_id: Option<String>,
+    pub cpuid: Option<String>,
+    pub core: Option<String>,
+    pub error: Option<String>,
+}
+
+impl ProbeRecord {
+    pub fn new(probe: &Result<ProbeInfo>) -> Self {
+        match *probe {
+            Ok(ref info) => ProbeRecord {
+                firmware: info.firmware.clone(),
+                api: info.api.clone(),
+                target_voltage: info.voltage,
+                mode: info.mode.clone(),
+                core_id: info.core_id.map(|v| format!("{:08x}", v)),
+                cpuid: info.cpuid.map(|v| format!("{:08x}", v)),
+                core: info.core(),
+                error: None,
+            },
+            Err(ref e) => ProbeRecord {
+                error: Some(e.to_string()),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl DeviceRecord {
+    pub fn new(d: &Device) -> Self {
+        let u = d.usb();
+        DeviceRecord {
+            id: d.hash(),
+            vendor_id: format!("{:04x}", u.vendor_id),
+            product_id: format!("{:04x}", u.product_id),
+            vendor: u.vendor_string.clone(),
+            product: u.product_string.clone(),
+            serial_number: u.serial_number.clone(),
+            location_id: u.location_id,
+            usb_path: u.path.as_ref().map(|p| p.display().to_string()),
+            device_type: d.device_type().map(String::from),
+            loader_type: d.loader_type().map(String::from),
+            debugger_type: d.debugger_type().map(String::from),
+            bossa_path: d.bossa_path(),
+            cdc_path: d.cdc_path(),
+            msd_path: d.msd_path().map(|p| p.display().to_string()),
+            gdb_path: d.gdb_path(),
+            openocd_serial: d.openocd_serial(),
+            probe: None,
+        }
+    }
+}
+
+impl Record for DeviceRecord {
+    fn columns() -> &'static [&'static str] {
+        &[
+            "id", "vendor_id", "product_id", "vendor", "product", "serial_number", "location_id",
+            "usb_path", "device_type", "loader_type", "debugger_type", "bossa_path", "cdc_path",
+            "msd_path", "gdb_path", "openocd_serial", "probe_firmware", "probe_api", "target_voltage",
+            "probe_mode", "core_id", "cpuid", "core", "probe_error",
+        ]
+    }
+
+    fn values(&self) -> Vec<String> {
+        let mut values = vec![
+            self.id.clone(),
+            self.vendor_id.clone(),
+            self.product_id.clone(),
+            self.vendor.clone(),
+            self.product.clone(),
+            self.serial_number.clone(),
+            self.location_id.map(|v| format!("{:08x}", v)).unwrap_or_default(),
+            self.usb_path.clone().unwrap_or_default(),
+            self.device_type.clone().unwrap_or_default(),
+            self.loader_type.clone().unwrap_or_default(),
+            self.debugger_type.clone().unwrap_or_default(),
+            self.bossa_path.clone().unwrap_or_default(),
+            self.cdc_path.clone().unwrap_or_default(),
+            self.msd_path.clone().unwrap_or_default(),
+            self.gdb_path.clone().unwrap_or_default(),
+            self.openocd_serial.clone().unwrap_or_default(),
+        ];
+        let probe = self.probe.as_ref();
+        values.extend(vec![
+            probe.and_then(|p| p.firmware.clone()).unwrap_or_default(),
+            probe.and_then(|p| p.api.clone()).unwrap_or_default(),
+            probe.and_then(|p| p.target_voltage).map(|v| format!("{:.2}", v)).unwrap_or_default(),
+            probe.and_then(|p| p.mode.clone()).unwrap_or_default(),
+            probe.and_then(|p| p.core_id.clone()).unwrap_or_default(),
+            probe.and_then(|p| p.cpuid.clone()).unwrap_or_default(),
+            probe.and_then(|p| p.core.clone()).unwrap_or_default(),
+            probe.and_then(|p| p.error.clone()).unwrap_or_default(),
+        ]);
+        values
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ToolRecord {
+    pub name: String,
+    pub version: Option<String>,
+    pub path: Option<String>,
+}
+
+impl Record for ToolRecord {
+    fn columns() -> &'static [&'static str] {
+        &["name", "version", "path"]
+    }
+
+    fn values(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.version.clone().unwrap_or_default(),
+            self.path.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+/// Writes the records as a JSON array.
+pub fn write_json<W: Write, R: Serialize>(out: &mut W, records: &[R]) -> Result<()> {
+    serde_json::to_writer_pretty(&mut *out, records)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Writes the records as tab-separated values with a header row. Tabs and line breaks within
+/// values are replaced with spaces.
+pub fn write_tsv<W: Write, R: Record>(out: &mut W, records: &[R]) -> Result<()> {
+    writeln!(out, "{}", R::columns().join("\t"))?;
+    for r in records {
+        let values: Vec<String> = r.values().iter()
+            .map(|v| v.replace(&['\t', '\n', '\r'][..], " "))
+            .collect();
+        writeln!(out, "{}", values.join("\t"))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,402 @@
+//! Test result collection and JUnit XML / TAP reporting for `bobbin test`.
+//!
+//! The firmware reports progress with tags at the start of a line:
+//!
+//! - `[start] text`: the test run has started.
+//! - `[test] name`: a test case has started.
+//! - `[pass] name`: the current test case passed. Without a preceding `[test]`, this records
+//!   a passing test case named by the text after the tag.
+//! - `[fail] reason`: the current test case failed. Without a preceding `[test]`, this ends
+//!   the run as a failure.
+//! - `[done] text`: the test run has completed.
+//! - `[exception] text` / `[panic] text`: the test run has crashed. These may appear anywhere
+//!   in a line.
+//!
+//! User-defined markers from the [test] section of .bobbin/config and the --pass-marker,
+//! --fail-marker and --exit-marker options are checked before these tags. All other lines are
+//! captured as output of the current test case, or of the run if no test case is in progress.
+
+use clap::ArgMatches;
+use regex::Regex;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use config::Config;
+use Result;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Done,
+    Fail,
+    Exception,
+    Panic,
+    Exit(i32),
+    BootTimeout,
+    LineTimeout,
+    TestTimeout,
+}
+
+impl Outcome {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Outcome::Done => "done",
+            Outcome::Fail => "fail",
+            Outcome::Exception => "exception",
+            Outcome::Panic => "panic",
+            Outcome::Exit(_) => "exit",
+            Outcome::BootTimeout => "timeout:boot",
+            Outcome::LineTimeout => "timeout:line",
+            Outcome::TestTimeout => "timeout:test",
+        }
+    }
+}
+
+/// A regular expression that ends the test run with the given outcome when it matches a line.
+#[derive(Debug)]
+pub struct Marker {
+    regex: Regex,
+    outcome: Outcome,
+}
+
+impl Marker {
+    pub fn new(pattern: &str, outcome: Outcome) -> Result<Marker> {
+        match Regex::new(pattern) {
+            Ok(regex) => Ok(Marker { regex, outcome }),
+            Err(e) => bail!("Invalid test marker {:?}: {}", pattern, e),
+        }
+    }
+
+    /// Parses an --exit-marker value, written as "<code>=<regex>".
+    pub fn parse_exit(value: &str) -> Result<Marker> {
+        let mut parts = value.splitn(2, '=');
+        match (parts.next().map(|code| code.trim().parse::<i32>()), parts.next()) {
+            (Some(Ok(code)), Some(pattern)) => Marker::new(pattern, Outcome::Exit(code)),
+            _ => bail!("Invalid exit marker: {} (expected <code>=<regex>)", value),
+        }
+    }
+
+    /// Returns the option and value that give this marker on the command line.
+    pub fn arg(&self) -> (&'static str, String) {
+        let pattern = self.regex.as_str();
+        match self.outcome {
+            Outcome::Exit(code) => ("--exit-marker", format!("{}={}", code, pattern)),
+            Outcome::Fail => ("--fail-marker", pattern.to_string()),
+            _ => ("--pass-marker", pattern.to_string()),
+        }
+    }
+}
+
+/// Returns the markers given with --pass-marker, --fail-marker and --exit-marker followed by the
+/// [[test.marker]] entries of .bobbin/config.
+pub fn markers(cfg: &Config, cmd_args: &ArgMatches) -> Result<Vec<Marker>> {
+    let mut markers = Vec::new();
+    if let Some(patterns) = cmd_args.values_of("pass-marker") {
+        for pattern in patterns {
+            markers.push(Marker::new(pattern, Outcome::Done)?);
+        }
+    }
+    if let Some(patterns) = cmd_args.values_of("fail-marker") {
+        for pattern in patterns {
+            markers.push(Marker::new(pattern, Outcome::Fail)?);
+        }
+    }
+    if let Some(values) = cmd_args.values_of("exit-marker") {
+        for value in values {
+            markers.push(Marker::parse_exit(value)?);
+        }
+    }
+    for m in cfg.cfg_test_markers() {
+        let outcome = match (m.result.as_deref(), m.exit_code) {
+            (None, Some(code)) => Outcome::Exit(code),
+            (Some("pass"), None) => Outcome::Done,
+            (Some("fail"), None) => Outcome::Fail,
+            (None, None) => bail!("Test marker {:?} needs a result or an exit-code", m.pattern),
+            (Some(_), Some(_)) => bail!("Test marker {:?} has both a result and an exit-code", m.pattern),
+            (Some(result), None) => bail!("Unknown test marker result: {} (expected pass or fail)", result),
+        };
+        markers.push(Marker::new(&m.pattern, outcome)?);
+    }
+    Ok(markers)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Status {
+    Pass,
+    Fail(String),
+    Error(String),
+}
+
+#[derive(Debug)]
+pub struct TestCase {
+    pub name: String,
+    pub status: Status,
+    pub duration: Duration,
+    pub output: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct TestRun {
+    pub name: String,
+    pub cases: Vec<TestCase>,
+    pub output: Vec<String>,
+    pub outcome: Option<Outcome>,
+    pub message: String,
+    pub duration: Duration,
+    markers: Vec<Marker>,
+    start_time: Instant,
+    case_time: Instant,
+    current: Option<(String, Vec<String>)>,
+}
+
+fn tag<'a>(line: &'a str, tag: &str) -> Option<&'a str> {
+    line.strip_prefix(tag).map(str::trim)
+}
+
+fn tag_anywhere<'a>(line: &'a str, tag: &str) -> Option<&'a str> {
+    line.find(tag).map(|i| line[i + tag.len()..].trim())
+}
+
+impl TestRun {
+    pub fn new(name: &str, markers: Vec<Marker>) -> Self {
+        let now = Instant::now();
+        TestRun {
+            name: String::from(name),
+            cases: Vec::new(),
+            output: Vec::new(),
+            outcome: None,
+            message: String::new(),
+            duration: Duration::from_secs(0),
+            markers,
+            start_time: now,
+            case_time: now,
+            current: None,
+        }
+    }
+
+    /// Processes a line of console output, returning the outcome if the run has finished.
+    pub fn line(&mut self, line: &str) -> Option<Outcome> {
+        if let Some(outcome) = self.markers.iter().find(|m| m.regex.is_match(line)).map(|m| m.outcome) {
+            return Some(self.finish(outcome, line));
+        }
+        if let Some(name) = tag(line, "[test]") {
+            self.abandon_case();
+            self.case_time = Instant::now();
+            self.current = Some((String::from(name), Vec::new()));
+        } else if let Some(name) = tag(line, "[pass]") {
+            self.end_case(name, Status::Pass);
+        } else if let Some(reason) = tag(line, "[fail]") {
+            if self.current.is_some() {
+                self.end_case("", Status::Fail(String::from(reason)));
+            } else {
+                return Some(self.finish(Outcome::Fail, reason));
+            }
+        } else if let Some(text) = tag(line, "[done]") {
+            return Some(self.finish(Outcome::Done, text));
+        } else if let Some(text) = tag_anywhere(line, "[exception]") {
+            return Some(self.finish(Outcome::Exception, text));
+        } else if let Some(text) = tag_anywhere(line, "[panic]") {
+            return Some(self.finish(Outcome::Panic, text));
+        } else if let Some((_, ref mut output)) = self.current {
+            output.push(String::from(line));
+        } else {
+            self.output.push(String::from(line));
+            if tag(line, "[start]").is_some() {
+                self.case_time = Instant::now();
+            }
+        }
+        None
+    }
+
+    /// Ends the run without a completion tag.
+    pub fn timeout(&mut self, outcome: Outcome) -> Outcome {
+        self.finish(outcome, "")
+    }
+
+    /// Ends the run when the target program exits (e.g. through semihosting), as an exit-code
+    /// marker would.
+    pub fn exit(&mut self, code: i32) -> Outcome {
+        self.finish(Outcome::Exit(code), "")
+    }
+
+    fn end_case(&mut self, name: &str, status: Status) {
+        let now = Instant::now();
+        let (name, output) = match self.current.take() {
+            Some((current, output)) => (if current.is_empty() { String::from(name) } else { current }, output),
+            None => (String::from(name), Vec::new()),
+        };
+        let name = if name.is_empty() { format!("test {}", self.cases.len() + 1) } else { name };
+        self.cases.push(TestCase {
+            name,
+            status,
+            duration: now.duration_since(self.case_time),
+            output,
+        });
+        self.case_time = now;
+    }
+
+    fn abandon_case(&mut self) {
+        if self.current.is_some() {
+            self.end_case("", Status::Error(String::from("test case did not complete")));
+        }
+    }
+
+    fn finish(&mut self, outcome: Outcome, message: &str) -> Outcome {
+        let message = if message.is_empty() { outcome.name() } else { message };
+        match outcome {
+            Outcome::Done | Outcome::Exit(0) => self.abandon_case(),
+            Outcome::Fail => self.end_case(outcome.name(), Status::Fail(String::from(message))),
+            _ => self.end_case(outcome.name(), Status::Error(format!("[{}] {}", outcome.name(), message))),
+        }
+        self.outcome = Some(outcome);
+        self.message = String::from(message);
+        self.duration = Instant::now().duration_since(self.start_time);
+        outcome
+    }
+
+    pub fn failures(&self) -> usize {
+        self.cases.iter().filter(|c| matches!(c.status, Status::Fail(_))).count()
+    }
+
+    pub fn errors(&self) -> usize {
+        self.cases.iter().filter(|c| matches!(c.status, Status::Error(_))).count()
+    }
+
+    /// Returns the process exit code for the run: 0 if all test cases passed, 1 for failures
+    /// and timeouts, 2 for exceptions and 3 for panics. A marker with an exit-code returns that
+    /// code, unless it is 0 and a test case has failed.
+    pub fn exit_code(&self) -> i32 {
+        match self.outcome {
+            Some(Outcome::Done) | Some(Outcome::Exit(0)) if self.failures() == 0 && self.errors() == 0 => 0,
+            Some(Outcome::Exit(code)) if code != 0 => code,
+            Some(Outcome::Exception) => 2,
+            Some(Outcome::Panic) => 3,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    JUnit,
+    Tap,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Result<Format> {
+        match name {
+            "junit" => Ok(Format::JUnit),
+            "tap" => Ok(Format::Tap),
+            _ => bail!("Unknown report format: {}", name),
+        }
+    }
+
+    /// Infers the report format from the file extension, defaulting to JUnit XML.
+    pub fn from_path(path: &Path) -> Format {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("tap") => Format::Tap,
+            _ => Format::JUnit,
+        }
+    }
+}
+
+pub fn write(path: &Path, format: Format, run: &TestRun) -> Result<()> {
+    let mut f = File::create(path)?;
+    match format {
+        Format::JUnit => write_junit(&mut f, run),
+        Format::Tap => write_tap(&mut f, run),
+    }
+}
+
+fn seconds(d: Duration) -> String {
+    format!("{}.{:03}", d.as_secs(), d.subsec_millis())
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\t' | '\n' | '\r' => out.push(c),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\x{:02x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+pub fn write_junit<W: Write>(out: &mut W, run: &TestRun) -> Result<()> {
+    let name = xml_escape(&run.name);
+    writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(out, "<testsuites>")?;
+    writeln!(out, "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{}\">",
+        name, run.cases.len(), run.failures(), run.errors(), seconds(run.duration))?;
+    for case in run.cases.iter() {
+        writeln!(out, "    <testcase name=\"{}\" classname=\"{}\" time=\"{}\">",
+            xml_escape(&case.name), name, seconds(case.duration))?;
+        match case.status {
+            Status::Pass => {},
+            Status::Fail(ref message) => {
+                writeln!(out, "      <failure message=\"{}\"/>", xml_escape(message))?;
+            },
+            Status::Error(ref message) => {
+                writeln!(out, "      <error message=\"{}\"/>", xml_escape(message))?;
+            },
+        }
+        if !case.output.is_empty() {
+            writeln!(out, "      <system-out>{}</system-out>", xml_escape(&case.output.join("\n")))?;
+        }
+        writeln!(out, "    </testcase>")?;
+    }
+    if !run.output.is_empty() {
+        writeln!(out, "    <system-out>{}</system-out>", xml_escape(&run.output.join("\n")))?;
+    }
+    writeln!(out, "  </testsuite>")?;
+    writeln!(out, "</testsuites>")?;
+    Ok(())
+}
+
+fn yaml_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+pub fn write_tap<W: Write>(out: &mut W, run: &TestRun) -> Result<()> {
+    writeln!(out, "TAP version 13")?;
+    writeln!(out, "1..{}", run.cases.len())?;
+    for line in run.output.iter() {
+        writeln!(out, "# {}", line)?;
+    }
+    for (i, case) in run.cases.iter().enumerate() {
+        let ok = if case.status == Status::Pass { "ok" } else { "not ok" };
+        writeln!(out, "{} {} - {}", ok, i + 1, case.name.replace('#', "\\#"))?;
+        // The YAML block must follow the test line directly, so the output goes inside it.
+        writeln!(out, "  ---")?;
+        match case.status {
+            Status::Pass => {},
+            Status::Fail(ref message) | Status::Error(ref message) => {
+                writeln!(out, "  message: {}", yaml_quote(message))?;
+            },
+        }
+        writeln!(out, "  duration_ms: {}", case.duration.as_secs() * 1000 + u64::from(case.duration.subsec_millis()))?;
+        if !case.output.is_empty() {
+            // The explicit indentation keeps output lines that start with spaces intact.
+            writeln!(out, "  output: |2")?;
+            for line in case.output.iter() {
+                writeln!(out, "    {}", line)?;
+            }
+        }
+        writeln!(out, "  ...")?;
+    }
+    match run.outcome {
+        Some(Outcome::Done) | Some(Outcome::Fail) | Some(Outcome::Exit(0)) | None => {},
+        Some(outcome) => writeln!(out, "Bail out! [{}] {}", outcome.name(), run.message)?,
+    }
+    Ok(())
+}
+
+
@@ -0,0 +1,345 @@
+//! SEGGER RTT (Real-Time Transfer) console.
+//!
+//! The target firmware keeps a control block, named by the `_SEGGER_RTT` symbol and starting with
+//! the "SEGGER RTT" ID, which describes ring buffers for up (target to host) and down (host to
+//! target) channels. The native ST-Link debugger polls these buffers directly; OpenOCD and J-Link
+//! run their own RTT servers, which are read over TCP.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::process::Child;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::ArgMatches;
+use config::Config;
+use console::{Link, Port};
+use elf;
+use mem::{self, Memory};
+use Result;
+
+pub const CONTROL_BLOCK_SYMBOL: &str = "_SEGGER_RTT";
+pub const CONTROL_BLOCK_ID: &[u8] = b"SEGGER RTT\0";
+
+/// The local TCP port used for the OpenOCD and J-Link RTT servers.
+pub const TCP_PORT: u16 = 19021;
+
+const HEADER_SIZE: usize = 24;
+const DESCRIPTOR_SIZE: usize = 24;
+const MAX_CHANNELS: u32 = 32;
+const POLL_INTERVAL_MS: u64 = 10;
+const CONNECT_TIMEOUT_SECS: u64 = 10;
+
+/// Where to find the RTT control block and which channel to use.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    /// The control block address, if known. Otherwise the scan range is searched.
+    pub address: Option<u32>,
+    pub scan_start: u32,
+    pub scan_size: u32,
+    pub channel: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            address: None,
+            scan_start: 0x2000_0000,
+            scan_size: 0x1_0000,
+            channel: 0,
+        }
+    }
+}
+
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.address {
+            Some(addr) => write!(f, "control block 0x{:08x}", addr)?,
+            None => write!(f, "scan 0x{:08x}+0x{:x}", self.scan_start, self.scan_size)?,
+        }
+        write!(f, " channel {}", self.channel)
+    }
+}
+
+/// Resolves the RTT settings from --rtt-address and --rtt-channel (or the [console] section of
+/// .bobbin/config), falling back to the `_SEGGER_RTT` symbol in `elf` for the address.
+pub fn settings(cfg: &Config, cmd_args: &ArgMatches, elf: Option<&Path>) -> Result<Settings> {
+    let mut settings = Settings::default();
+    if let Some(value) = cfg.rtt_channel(cmd_args) {
+        settings.channel = match value.parse::<usize>() {
+            Ok(channel) => channel,
+            Err(_) => bail!("Invalid RTT channel: {}", value),
+        };
+    }
+    if let Some(value) = cfg.rtt_address(cmd_args) {
+        settings.address = Some(mem::parse_number("RTT address", &value)?);
+    } else if let Some(path) = elf {
+        // The binary may not be an ELF file (e.g. --stdin), in which case the block is scanned for.
+        if let Ok(elf) = elf::Elf::open(path) {
+            if let Some(sym) = elf.symbol(CONTROL_BLOCK_SYMBOL)? {
+                settings.address = Some(sym.value as u32);
+            }
+        }
+    }
+    Ok(settings)
+}
+
+fn write_u32(mem: &mut Memory, addr: u32, value: u32) -> Result<()> {
+    mem.write(addr, &[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    data[offset..offset + 4].iter().rev().fold(0, |v, b| v << 8 | *b as u32)
+}
+
+/// A ring buffer described by the control block.
+#[derive(Debug, Clone, Copy)]
+struct Channel {
+    /// Address of the buffer descriptor, which holds the write and read offsets.
+    desc: u32,
+    buffer: u32,
+    size: u32,
+}
+
+impl Channel {
+    fn offsets(&self, mem: &mut Memory) -> Result<(u32, u32)> {
+        let mut buf = [0u8; 8];
+        mem.read(self.desc + 12, &mut buf)?;
+        let (wr, rd) = (u32_at(&buf, 0), u32_at(&buf, 4));
+        if wr >= self.size || rd >= self.size {
+            bail!("RTT buffer at 0x{:08x} is corrupt (write {}, read {}, size {})", self.buffer, wr, rd, self.size);
+        }
+        Ok((wr, rd))
+    }
+}
+
+/// The channels of an RTT control block.
+#[derive(Debug)]
+pub struct ControlBlock {
+    pub addr: u32,
+    up: Vec<Channel>,
+    down: Vec<Channel>,
+}
+
+impl ControlBlock {
+    /// Reads the control block at the configured address, or the first one found in the scan range.
+    pub fn find(mem: &mut Memory, settings: &Settings) -> Result<ControlBlock> {
+        let addr = match settings.address {
+            Some(addr) => addr,
+            None => match scan(mem, settings.scan_start, settings.scan_size)? {
+                Some(addr) => addr,
+                None => bail!(
+                    "No RTT control block found in 0x{:08x}+0x{:x} (use --rtt-address)",
+                    settings.scan_start, settings.scan_size
+                ),
+            },
+        };
+        ControlBlock::read(mem, addr)
+    }
+
+    /// Finds the control block, retrying for a few seconds while it is missing, as it is until
+    /// the firmware has started and initialized RTT after being loaded or reset.
+    pub fn wait(mem: &mut Memory, settings: &Settings) -> Result<ControlBlock> {
+        let mut retries = 20;
+        loop {
+            match ControlBlock::find(mem, settings) {
+                Ok(cb) => return Ok(cb),
+                Err(e) => {
+                    if retries == 0 {
+                        return Err(e)
+                    }
+                    retries -= 1;
+                    thread::sleep(Duration::from_millis(250));
+                }
+            }
+        }
+    }
+
+    fn read(mem: &mut Memory, addr: u32) -> Result<ControlBlock> {
+        let mut header = [0u8; HEADER_SIZE];
+        mem.read(addr, &mut header)?;
+        if !header.starts_with(CONTROL_BLOCK_ID) {
+            bail!("No RTT control block at 0x{:08x}; has the target initialized RTT?", addr);
+        }
+        let (max_up, max_down) = (u32_at(&header, 16), u32_at(&header, 20));
+        if max_up > MAX_CHANNELS || max_down > MAX_CHANNELS {
+            bail!("RTT control block at 0x{:08x} is corrupt ({} up, {} down channels)", addr, max_up, max_down);
+        }
+        let count = (max_up + max_down) as usize;
+        let mut descs = vec![0u8; count * DESCRIPTOR_SIZE];
+        mem.read(addr + HEADER_SIZE as u32, &mut descs)?;
+        let mut channels = Vec::new();
+        for (i, d) in descs.chunks(DESCRIPTOR_SIZE).enumerate() {
+            let ch = Channel {
+                desc: addr + (HEADER_SIZE + i * DESCRIPTOR_SIZE) as u32,
+                buffer: u32_at(d, 4),
+                size: u32_at(d, 8),
+            };
+            // The block may still be half-initialized if the target is starting up.
+            if ch.buffer.checked_add(ch.size).is_none() {
+                bail!("RTT control block at 0x{:08x} is corrupt (buffer at 0x{:08x}, size {})", addr, ch.buffer, ch.size);
+            }
+            channels.push(ch);
+        }
+        let down = channels.split_off(max_up as usize);
+        Ok(ControlBlock { addr, up: channels, down })
+    }
+
+    fn channel(channels: &[Channel], index: usize, kind: &str) -> Result<Channel> {
+        match channels.get(index) {
+            Some(ch) if ch.size > 0 => Ok(*ch),
+            Some(_) => bail!("RTT {} channel {} has no buffer", kind, index),
+            None => bail!("RTT {} channel {} does not exist ({} channels)", kind, index, channels.len()),
+        }
+    }
+
+    /// Reads any pending data from an up channel.
+    pub fn read_up(&self, mem: &mut Memory, index: usize) -> Result<Vec<u8>> {
+        let ch = ControlBlock::channel(&self.up, index, "up")?;
+        let (wr, rd) = ch.offsets(mem)?;
+        if wr == rd {
+            return Ok(Vec::new())
+        }
+        let mut data = if wr > rd {
+            vec![0u8; (wr - rd) as usize]
+        } else {
+            vec![0u8; (ch.size - rd + wr) as usize]
+        };
+        let first = (ch.size - rd).min(data.len() as u32) as usize;
+        mem.read(ch.buffer + rd, &mut data[..first])?;
+        if first < data.len() {
+            mem.read(ch.buffer, &mut data[first..])?;
+        }
+        write_u32(mem, ch.desc + 16, wr)?;
+        Ok(data)
+    }
+
+    /// Writes as much of `data` to a down channel as fits, returning the number of bytes written.
+    pub fn write_down(&self, mem: &mut Memory, index: usize, data: &[u8]) -> Result<usize> {
+        let ch = ControlBlock::channel(&self.down, index, "down")?;
+        let (wr, rd) = ch.offsets(mem)?;
+        let free = if rd > wr { rd - wr - 1 } else { ch.size - wr + rd - 1 };
+        let n = (free as usize).min(data.len());
+        if n == 0 {
+            return Ok(0)
+        }
+        let first = ((ch.size - wr) as usize).min(n);
+        mem.write(ch.buffer + wr, &data[..first])?;
+        if first < n {
+            mem.write(ch.buffer, &data[first..n])?;
+        }
+        write_u32(mem, ch.desc + 12, (wr + n as u32) % ch.size)?;
+        Ok(n)
+    }
+}
+
+/// Searches target memory for the control block ID, returning its address.
+fn scan(mem: &mut Memory, start: u32, size: u32) -> Result<Option<u32>> {
+    const CHUNK: u32 = 1024;
+    let id_len = CONTROL_BLOCK_ID.len() as u32;
+    let mut data = vec![0u8; (CHUNK + id_len) as usize];
+    let mut offset = 0;
+    while offset < size {
+        let len = (CHUNK + id_len).min(size - offset) as usize;
+        mem.read(start + offset, &mut data[..len])?;
+        if let Some(i) = data[..len].windows(id_len as usize).position(|w| w == CONTROL_BLOCK_ID) {
+            return Ok(Some(start + offset + i as u32))
+        }
+        offset += CHUNK;
+    }
+    Ok(None)
+}
+
+/// Finds the control block and copies data between the selected channel and the console `link`
+/// until the console is closed.
+pub fn run(mem: &mut Memory, settings: &Settings, link: &Link) -> Result<()> {
+    let cb = ControlBlock::wait(mem, settings)?;
+    ControlBlock::channel(&cb.up, settings.channel, "up")?;
+    // Input is discarded if the firmware has no matching down channel.
+    let has_down = ControlBlock::channel(&cb.down, settings.channel, "down").is_ok();
+    link.ready();
+
+    loop {
+        let data = cb.read_up(mem, settings.channel)?;
+        let idle = data.is_empty();
+        if !idle && !link.send(data) {
+            return Ok(())
+        }
+        if link.is_closed() {
+            return Ok(())
+        }
+        link.consume_input(|input| {
+            if has_down { cb.write_down(mem, settings.channel, input) } else { Ok(input.len()) }
+        })?;
+        if idle {
+            thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+        }
+    }
+}
+
+/// Connects to the RTT server started by `child` on `TCP_PORT`. The server is stopped when the
+/// returned port is closed.
+pub fn connect(name: &str, mut child: Child) -> Result<Box<Port>> {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            bail!("{} exited ({}) before starting its RTT server", name, status);
+        }
+        match TcpStream::connect(("127.0.0.1", TCP_PORT)) {
+            Ok(stream) => return Ok(Box::new(TcpPort { stream, child, timeout: Duration::from_millis(100) })),
+            Err(e) => {
+                if start.elapsed() > Duration::from_secs(CONNECT_TIMEOUT_SECS) {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    bail!("Unable to connect to the {} RTT server on port {}: {}", name, TCP_PORT, e);
+                }
+            }
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+struct TcpPort {
+    stream: TcpStream,
+    child: Child,
+    timeout: Duration,
+}
+
+impl Read for TcpPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.stream.read(buf) {
+            Ok(0) if !buf.is_empty() => {
+                thread::sleep(self.timeout);
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, "RTT server closed the connection"))
+            }
+            result => result,
+        }
+    }
+}
+
+impl Write for TcpPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Port for TcpPort {
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.stream.set_read_timeout(Some(timeout))?;
+        self.timeout = timeout;
+        Ok(())
+    }
+}
+
+impl Drop for TcpPort {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,512 @@
+//! ARM semihosting host.
+//!
+//! A semihosting call is a `BKPT 0xAB` instruction with the operation number in r0 and a
+//! parameter (usually the address of a parameter block) in r1. With a native debugger, bobbin
+//! waits for the core to halt on one, performs the operation, writes the result to r0 and resumes
+//! the core after the breakpoint. Console output is sent to the console `Link`, console input is
+//! read from it, and the exit code of SYS_EXIT is reported to it.
+//!
+//! With OpenOCD, semihosting is serviced by OpenOCD itself: its standard output carries the
+//! program's console output, and it exits with the program's exit code.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::process::Child;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use console::{self, Link, Port};
+use mem::Memory;
+use Result;
+
+const BKPT_SEMIHOSTING: [u8; 2] = [0xab, 0xbe];
+
+const SYS_OPEN: u32 = 0x01;
+const SYS_CLOSE: u32 = 0x02;
+const SYS_WRITEC: u32 = 0x03;
+const SYS_WRITE0: u32 = 0x04;
+const SYS_WRITE: u32 = 0x05;
+const SYS_READ: u32 = 0x06;
+const SYS_READC: u32 = 0x07;
+const SYS_ISERROR: u32 = 0x08;
+const SYS_ISTTY: u32 = 0x09;
+const SYS_SEEK: u32 = 0x0a;
+const SYS_FLEN: u32 = 0x0c;
+const SYS_REMOVE: u32 = 0x0e;
+const SYS_RENAME: u32 = 0x0f;
+const SYS_CLOCK: u32 = 0x10;
+const SYS_TIME: u32 = 0x11;
+const SYS_ERRNO: u32 = 0x13;
+const SYS_GET_CMDLINE: u32 = 0x15;
+const SYS_HEAPINFO: u32 = 0x16;
+const SYS_EXIT: u32 = 0x18;
+const SYS_EXIT_EXTENDED: u32 = 0x20;
+const SYS_ELAPSED: u32 = 0x30;
+const SYS_TICKFREQ: u32 = 0x31;
+
+const ADP_STOPPED_APPLICATION_EXIT: u32 = 0x20026;
+
+/// Handles for the console, opened with the special file name ":tt". Other files are numbered
+/// after these.
+const HANDLE_STDIN: u32 = 1;
+const HANDLE_STDOUT: u32 = 2;
+const HANDLE_STDERR: u32 = 3;
+
+const EBADF: i32 = 9;
+const EINVAL: i32 = 22;
+const ENAMETOOLONG: i32 = 36;
+
+const POLL_INTERVAL_MS: u64 = 1;
+
+/// The most bytes transferred by one SYS_READ or SYS_WRITE call. Longer transfers are reported as
+/// partial, as the length comes from the target and may be garbage.
+const MAX_TRANSFER: u32 = 64 * 1024;
+/// The longest file name accepted from the target.
+const MAX_NAME: u32 = 4096;
+
+/// Core register access and run control, in addition to memory access, for a native debugger.
+pub trait Target: Memory {
+    fn register(&mut self, reg: u8) -> Result<u32>;
+    fn set_register(&mut self, reg: u8, value: u32) -> Result<()>;
+    fn is_halted(&mut self) -> Result<bool>;
+    fn resume(&mut self) -> Result<()>;
+}
+
+fn words(data: &[u8]) -> Vec<u32> {
+    data.chunks(4)
+        .map(|w| w.iter().rev().fold(0, |v, b| v << 8 | *b as u32))
+        .collect()
+}
+
+fn read_words(target: &mut Target, addr: u32, count: usize) -> Result<Vec<u32>> {
+    let mut data = vec![0u8; count * 4];
+    target.read(addr, &mut data)?;
+    Ok(words(&data))
+}
+
+fn write_words(target: &mut Target, addr: u32, values: &[u32]) -> Result<()> {
+    let mut data = Vec::with_capacity(values.len() * 4);
+    for v in values {
+        data.extend_from_slice(&[*v as u8, (*v >> 8) as u8, (*v >> 16) as u8, (*v >> 24) as u8]);
+    }
+    target.write(addr, &data)
+}
+
+/// Reads a file name, returning None if it is longer than MAX_NAME.
+fn read_string(target: &mut Target, addr: u32, len: u32) -> Result<Option<String>> {
+    if len > MAX_NAME {
+        return Ok(None)
+    }
+    let mut data = vec![0u8; len as usize];
+    target.read(addr, &mut data)?;
+    Ok(Some(String::from_utf8_lossy(&data).into_owned()))
+}
+
+/// Reads a NUL-terminated string, up to MAX_TRANSFER bytes.
+fn read_c_string(target: &mut Target, addr: u32) -> Result<Vec<u8>> {
+    let mut text = Vec::new();
+    let mut chunk = [0u8; 64];
+    while text.len() < MAX_TRANSFER as usize {
+        target.read(addr.wrapping_add(text.len() as u32), &mut chunk)?;
+        match chunk.iter().position(|b| *b == 0) {
+            Some(n) => {
+                text.extend_from_slice(&chunk[..n]);
+                return Ok(text)
+            }
+            None => text.extend_from_slice(&chunk),
+        }
+    }
+    Ok(text)
+}
+
+fn errno(e: &io::Error) -> i32 {
+    e.raw_os_error().unwrap_or(EINVAL)
+}
+
+/// The result of a semihosting call: a value for r0, or the program's exit code.
+enum Action {
+    Return(u32),
+    Exit(i32),
+}
+
+/// The host state for semihosting calls.
+pub struct Host {
+    files: Vec<Option<File>>,
+    errno: i32,
+    start: Instant,
+    /// Unsupported operations that have already been reported.
+    unsupported: Vec<u32>,
+}
+
+impl Default for Host {
+    fn default() -> Self {
+        Host { files: Vec::new(), errno: 0, start: Instant::now(), unsupported: Vec::new() }
+    }
+}
+
+impl Host {
+    pub fn new() -> Self {
+        Host::default()
+    }
+
+    fn file(&mut self, handle: u32) -> Option<&mut File> {
+        if handle <= HANDLE_STDERR {
+            return None
+        }
+        match self.files.get_mut((handle - HANDLE_STDERR - 1) as usize) {
+            Some(&mut Some(ref mut file)) => Some(file),
+            _ => None,
+        }
+    }
+
+    fn fail(&mut self, errno: i32) -> Action {
+        self.errno = errno;
+        Action::Return(u32::MAX)
+    }
+
+    /// Reads console input into the target, waiting for at least one byte. Returns the number of
+    /// bytes read, or None if the console was closed.
+    fn read_console(&mut self, target: &mut Target, link: &Link, addr: u32, len: u32) -> Result<Option<u32>> {
+        while !link.has_input() {
+            if link.is_closed() {
+                return Ok(None)
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let mut n = 0;
+        link.consume_input(|input| {
+            n = input.len().min(len as usize);
+            target.write(addr, &input[..n])?;
+            Ok(n)
+        })?;
+        Ok(Some(n as u32))
+    }
+
+    fn call(&mut self, target: &mut Target, link: &Link, op: u32, param: u32) -> Result<Action> {
+        Ok(match op {
+            SYS_OPEN => {
+                let p = read_words(target, param, 3)?;
+                let name = match read_string(target, p[0], p[2])? {
+                    Some(name) => name,
+                    None => return Ok(self.fail(ENAMETOOLONG)),
+                };
+                let mode = p[1];
+                if name == ":tt" {
+                    return Ok(Action::Return(match mode {
+                        0..=3 => HANDLE_STDIN,
+                        4..=7 => HANDLE_STDOUT,
+                        _ => HANDLE_STDERR,
+                    }))
+                }
+                let mut options = OpenOptions::new();
+                match mode & !1 {
+                    0 => options.read(true),
+                    2 => options.read(true).write(true),
+                    4 => options.write(true).create(true).truncate(true),
+                    6 => options.read(true).write(true).create(true).truncate(true),
+                    8 => options.append(true).create(true),
+                    10 => options.read(true).append(true).create(true),
+                    _ => return Ok(self.fail(EINVAL)),
+                };
+                match options.open(&name) {
+                    Ok(file) => {
+                        let index = match self.files.iter().position(|f| f.is_none()) {
+                            Some(index) => index,
+                            None => {
+                                self.files.push(None);
+                                self.files.len() - 1
+                            }
+                        };
+                        self.files[index] = Some(file);
+                        Action::Return(HANDLE_STDERR + 1 + index as u32)
+                    }
+                    Err(e) => self.fail(errno(&e)),
+                }
+            }
+            SYS_CLOSE => {
+                let handle = read_words(target, param, 1)?[0];
+                if handle <= HANDLE_STDERR {
+                    Action::Return(0)
+                } else if self.file(handle).is_some() {
+                    self.files[(handle - HANDLE_STDERR - 1) as usize] = None;
+                    Action::Return(0)
+                } else {
+                    self.fail(EBADF)
+                }
+            }
+            SYS_WRITEC => {
+                let mut c = [0u8];
+                target.read(param, &mut c)?;
+                link.send(c.to_vec());
+                Action::Return(0)
+            }
+            SYS_WRITE0 => {
+                link.send(read_c_string(target, param)?);
+                Action::Return(0)
+            }
+            SYS_WRITE => {
+                let p = read_words(target, param, 3)?;
+                let mut data = vec![0u8; p[2].min(MAX_TRANSFER) as usize];
+                target.read(p[1], &mut data)?;
+                // Returns the number of bytes that were not written.
+                match p[0] {
+                    HANDLE_STDOUT | HANDLE_STDERR => {
+                        let n = data.len() as u32;
+                        link.send(data);
+                        Action::Return(p[2] - n)
+                    }
+                    handle => match self.file(handle).map(|f| f.write(&data)) {
+                        Some(Ok(n)) => Action::Return(p[2] - n as u32),
+                        Some(Err(e)) => self.fail(errno(&e)),
+                        None => self.fail(EBADF),
+                    },
+                }
+            }
+            SYS_READ => {
+                let p = read_words(target, param, 3)?;
+                // Returns the number of bytes that were not read.
+                match p[0] {
+                    HANDLE_STDIN => match self.read_console(target, link, p[1], p[2])? {
+                        Some(n) => Action::Return(p[2] - n),
+                        None => Action::Return(p[2]),
+                    },
+                    handle => {
+                        let mut data = vec![0u8; p[2].min(MAX_TRANSFER) as usize];
+                        match self.file(handle).map(|f| f.read(&mut data)) {
+                            Some(Ok(n)) => {
+                                target.write(p[1], &data[..n])?;
+                                Action::Return(p[2] - n as u32)
+                            }
+                            Some(Err(e)) => self.fail(errno(&e)),
+                            None => self.fail(EBADF),
+                        }
+                    }
+                }
+            }
+            SYS_READC => {
+                let mut c = None;
+                while c.is_none() && !link.is_closed() {
+                    link.consume_input(|input| {
+                        c = input.first().cloned();
+                        Ok(c.map_or(0, |_| 1))
+                    })?;
+                    if c.is_none() {
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                }
+                Action::Return(c.map_or(u32::MAX, |c| c as u32))
+            }
+            SYS_ISERROR => {
+                let status = read_words(target, param, 1)?[0] as i32;
+                Action::Return(if status < 0 { 1 } else { 0 })
+            }
+            SYS_ISTTY => {
+                let handle = read_words(target, param, 1)?[0];
+                if handle <= HANDLE_STDERR {
+                    Action::Return(1)
+                } else if self.file(handle).is_some() {
+                    Action::Return(0)
+                } else {
+                    self.fail(EBADF)
+                }
+            }
+            SYS_SEEK => {
+                let p = read_words(target, param, 2)?;
+                match self.file(p[0]).map(|f| f.seek(SeekFrom::Start(p[1] as u64))) {
+                    Some(Ok(_)) => Action::Return(0),
+                    Some(Err(e)) => self.fail(errno(&e)),
+                    None => self.fail(EBADF),
+                }
+            }
+            SYS_FLEN => {
+                let handle = read_words(target, param, 1)?[0];
+                match self.file(handle).map(|f| f.metadata()) {
+                    Some(Ok(m)) => Action::Return(m.len() as u32),
+                    Some(Err(e)) => self.fail(errno(&e)),
+                    None => self.fail(EBADF),
+                }
+            }
+            SYS_REMOVE => {
+                let p = read_words(target, param, 2)?;
+                let name = match read_string(target, p[0], p[1])? {
+                    Some(name) => name,
+                    None => return Ok(self.fail(ENAMETOOLONG)),
+                };
+                match fs::remove_file(&name) {
+                    Ok(_) => Action::Return(0),
+                    Err(e) => self.fail(errno(&e)),
+                }
+            }
+            SYS_RENAME => {
+                let p = read_words(target, param, 4)?;
+                let (from, to) = match (read_string(target, p[0], p[1])?, read_string(target, p[2], p[3])?) {
+                    (Some(from), Some(to)) => (from, to),
+                    _ => return Ok(self.fail(ENAMETOOLONG)),
+                };
+                match fs::rename(&from, &to) {
+                    Ok(_) => Action::Return(0),
+                    Err(e) => self.fail(errno(&e)),
+                }
+            }
+            SYS_CLOCK => {
+                let elapsed = self.start.elapsed();
+                Action::Return((elapsed.as_secs() * 100 + elapsed.subsec_millis() as u64 / 10) as u32)
+            }
+            SYS_TIME => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0));
+                Action::Return(now.as_secs() as u32)
+            }
+            SYS_ERRNO => Action::Return(self.errno as u32),
+            SYS_GET_CMDLINE => {
+                // An empty command line.
+                let p = read_words(target, param, 2)?;
+                if p[1] == 0 {
+                    return Ok(self.fail(EINVAL))
+                }
+                target.write(p[0], &[0])?;
+                write_words(target, param + 4, &[0])?;
+                Action::Return(0)
+            }
+            SYS_HEAPINFO => {
+                // Zeros tell the C library to use its own heap and stack limits.
+                let block = read_words(target, param, 1)?[0];
+                write_words(target, block, &[0, 0, 0, 0])?;
+                Action::Return(0)
+            }
+            SYS_EXIT => {
+                // On 32-bit targets the parameter is the reason code itself.
+                Action::Exit(if param == ADP_STOPPED_APPLICATION_EXIT { 0 } else { 1 })
+            }
+            SYS_EXIT_EXTENDED => {
+                let p = read_words(target, param, 2)?;
+                Action::Exit(if p[0] == ADP_STOPPED_APPLICATION_EXIT { p[1] as i32 } else { 1 })
+            }
+            SYS_ELAPSED => {
+                let elapsed = self.start.elapsed();
+                let micros = elapsed.as_secs() * 1_000_000 + elapsed.subsec_micros() as u64;
+                write_words(target, param, &[micros as u32, (micros >> 32) as u32])?;
+                Action::Return(0)
+            }
+            SYS_TICKFREQ => Action::Return(1_000_000),
+            _ => {
+                // Fail the call as a C library would expect, rather than ending the session.
+                if !self.unsupported.contains(&op) {
+                    self.unsupported.push(op);
+                    let _ = writeln!(io::stderr(), "warning: unsupported semihosting operation 0x{:02x}", op);
+                }
+                self.fail(EINVAL)
+            }
+        })
+    }
+}
+
+/// Services semihosting calls until the target program exits or the console is closed. The
+/// target should have been reset with halting debug enabled, so that `BKPT 0xAB` halts the core.
+pub fn run(target: &mut Target, link: &Link) -> Result<()> {
+    let mut host = Host::new();
+    loop {
+        if !target.is_halted()? {
+            if link.is_closed() {
+                return Ok(())
+            }
+            thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+            continue
+        }
+        let pc = target.register(15)?;
+        let mut insn = [0u8; 2];
+        target.read(pc, &mut insn)?;
+        if insn != BKPT_SEMIHOSTING {
+            bail!("Target halted at 0x{:08x}, which is not a semihosting call", pc);
+        }
+        let op = target.register(0)?;
+        let param = target.register(1)?;
+        match host.call(target, link, op, param)? {
+            Action::Return(value) => {
+                target.set_register(0, value)?;
+                target.set_register(15, pc + 2)?;
+                target.resume()?;
+            }
+            Action::Exit(code) => {
+                link.exit(code);
+                return Ok(())
+            }
+        }
+    }
+}
+
+/// Returns a console port for an OpenOCD process with semihosting enabled. The program's output is
+/// read from OpenOCD's standard output, and OpenOCD exits with the program's exit code. OpenOCD's
+/// log is written to stderr if `verbose` is set.
+pub fn openocd(mut child: Child, verbose: bool) -> Result<Box<Port>> {
+    let stdout = match child.stdout.take() {
+        Some(stdout) => stdout,
+        None => bail!("openocd standard output is not available"),
+    };
+    let stderr = child.stderr.take();
+    console::spawn_port(move |link| {
+        // OpenOCD exiting before semihosting was enabled is an OpenOCD error, not a program exit.
+        let enabled = Arc::new(AtomicBool::new(false));
+        let last_error = Arc::new(Mutex::new(None));
+        if let Some(stderr) = stderr {
+            let enabled = enabled.clone();
+            let last_error = last_error.clone();
+            thread::spawn(move || {
+                for line in BufReader::new(stderr).lines() {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(_) => break,
+                    };
+                    if line.contains("semihosting is enabled") {
+                        enabled.store(true, Ordering::SeqCst);
+                    } else if line.starts_with("Error:") {
+                        *last_error.lock().unwrap() = Some(line.clone());
+                    }
+                    if verbose {
+                        let _ = writeln!(io::stderr(), "{}", line);
+                    }
+                }
+            });
+        }
+        link.ready();
+
+        let (data_tx, data_rx) = channel();
+        thread::spawn(move || {
+            let mut stdout = stdout;
+            let mut buf = [0u8; 1024];
+            loop {
+                match stdout.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => if data_tx.send(buf[..n].to_vec()).is_err() { break },
+                }
+            }
+        });
+        loop {
+            match data_rx.recv_timeout(Duration::from_millis(100)) {
+                Ok(data) => if !link.send(data) { break },
+                Err(RecvTimeoutError::Timeout) => if link.is_closed() { break },
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        if link.is_closed() {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(())
+        }
+        let status = child.wait()?;
+        // Give the stderr reader a moment to see the last lines.
+        thread::sleep(Duration::from_millis(50));
+        match status.code() {
+            Some(code) if enabled.load(Ordering::SeqCst) => {
+                link.exit(code);
+                Ok(())
+            }
+            _ => match last_error.lock().unwrap().take() {
+                Some(error) => bail!("openocd exited: {}", error),
+                None => bail!("openocd exited ({}) (run with --verbose for details)", status),
+            },
+        }
+    })
+}
@@ -0,0 +1,196 @@
+//! Target core clock detection.
+//!
+//! The clock is computed from the STM32 RCC registers when the clock tree is running from an
+//! internal oscillator. Otherwise (for example when an external crystal of unknown frequency is
+//! used) it is measured by sampling the DWT cycle counter, or SysTick on cores without one.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::flash::{self, Family};
+use super::{Debugger, Result};
+use super::constants::*;
+
+const MEASURE_MS: u64 = 150;
+
+/// Returns the core clock of the target in Hz, and how it was found.
+pub fn detect(d: &mut Debugger) -> Result<(u32, &'static str)> {
+    if let Ok(chip) = flash::identify(d) {
+        if let Some(hz) = rcc_clock(d, chip.family)? {
+            return Ok((hz, "RCC"))
+        }
+    }
+    measure(d)
+}
+
+/// Decodes the AHB prescaler field.
+fn hpre(value: u32) -> u32 {
+    match value & 0xf {
+        0b1000 => 2,
+        0b1001 => 4,
+        0b1010 => 8,
+        0b1011 => 16,
+        0b1100 => 64,
+        0b1101 => 128,
+        0b1110 => 256,
+        0b1111 => 512,
+        _ => 1,
+    }
+}
+
+/// Computes HCLK from the RCC registers, or returns None if it depends on an external clock.
+fn rcc_clock(d: &mut Debugger, family: Family) -> Result<Option<u32>> {
+    const HSI8: u32 = 8_000_000;
+    const HSI16: u32 = 16_000_000;
+    let sysclk = match family {
+        Family::F0 | Family::F1 | Family::F3 => {
+            let cfgr = d.read_32(0x4002_1004)?;
+            match (cfgr >> 2) & 0x3 {
+                0 => Some(HSI8),
+                2 => {
+                    let mul = (((cfgr >> 18) & 0xf) + 2).min(16);
+                    // PLLSRC is bit 16 on the F1, and bits 16:15 on the F0 and F3.
+                    let src = if family == Family::F1 { (cfgr >> 16) & 0x1 } else { (cfgr >> 15) & 0x3 };
+                    match src {
+                        0 => Some(HSI8 / 2 * mul),
+                        1 if family != Family::F1 => {
+                            let prediv = (d.read_32(0x4002_102C)? & 0xf) + 1;
+                            Some(HSI8 / prediv * mul)
+                        }
+                        _ => None,
+                    }
+                }
+                _ => None,
+            }
+            .map(|clk| clk / hpre(cfgr >> 4))
+        }
+        Family::F4 => {
+            let cfgr = d.read_32(0x4002_3808)?;
+            match (cfgr >> 2) & 0x3 {
+                0 => Some(HSI16),
+                2 => {
+                    let pllcfgr = d.read_32(0x4002_3804)?;
+                    if pllcfgr & (1 << 22) != 0 {
+                        None
+                    } else {
+                        let m = (pllcfgr & 0x3f).max(1);
+                        let n = (pllcfgr >> 6) & 0x1ff;
+                        let p = (((pllcfgr >> 16) & 0x3) + 1) * 2;
+                        Some((HSI16 / m * n) / p)
+                    }
+                }
+                _ => None,
+            }
+            .map(|clk| clk / hpre(cfgr >> 4))
+        }
+        Family::L4 => {
+            let cr = d.read_32(0x4002_1000)?;
+            let msi_range = if cr & (1 << 3) != 0 {
+                (cr >> 4) & 0xf
+            } else {
+                (d.read_32(0x4002_1094)? >> 8) & 0xf
+            };
+            const MSI: [u32; 12] = [
+                100_000, 200_000, 400_000, 800_000, 1_000_000, 2_000_000, 4_000_000, 8_000_000,
+                16_000_000, 24_000_000, 32_000_000, 48_000_000,
+            ];
+            let msi = MSI.get(msi_range as usize).cloned();
+            let cfgr = d.read_32(0x4002_1008)?;
+            match (cfgr >> 2) & 0x3 {
+                0 => msi,
+                1 => Some(HSI16),
+                3 => {
+                    let pllcfgr = d.read_32(0x4002_100C)?;
+                    let src = match pllcfgr & 0x3 {
+                        1 => msi,
+                        2 => Some(HSI16),
+                        _ => None,
+                    };
+                    let m = ((pllcfgr >> 4) & 0x7) + 1;
+                    let n = (pllcfgr >> 8) & 0x7f;
+                    let r = (((pllcfgr >> 25) & 0x3) + 1) * 2;
+                    src.map(|clk| clk / m * n / r)
+                }
+                _ => None,
+            }
+            .map(|clk| clk / hpre(cfgr >> 4))
+        }
+        Family::G0 => {
+            let cr = d.read_32(0x4002_1000)?;
+            let hsisys = HSI16 >> ((cr >> 11) & 0x7);
+            let cfgr = d.read_32(0x4002_1008)?;
+            match (cfgr >> 3) & 0x7 {
+                0 => Some(hsisys),
+                2 => {
+                    let pllcfgr = d.read_32(0x4002_100C)?;
+                    if pllcfgr & 0x3 != 2 {
+                        None
+                    } else {
+                        let m = ((pllcfgr >> 4) & 0x7) + 1;
+                        let n = (pllcfgr >> 8) & 0x7f;
+                        let r = ((pllcfgr >> 29) & 0x7) + 1;
+                        Some(HSI16 / m * n / r)
+                    }
+                }
+                _ => None,
+            }
+            .map(|clk| clk / hpre(cfgr >> 8))
+        }
+        Family::H7 => None,
+    };
+    Ok(sysclk.filter(|&clk| clk > 0))
+}
+
+/// Rounds a measured clock to the nearest MHz, or the nearest 100 kHz below 8 MHz.
+fn round_clock(hz: f64) -> u32 {
+    let step = if hz >= 8_000_000.0 { 1_000_000.0 } else { 100_000.0 };
+    ((hz / step).round() * step) as u32
+}
+
+/// Reads a counter, returning it with the time at which it was read.
+fn sample(d: &mut Debugger, addr: u32) -> Result<(u32, Instant)> {
+    let before = Instant::now();
+    let value = d.read_32(addr)?;
+    Ok((value, before + before.elapsed() / 2))
+}
+
+/// Measures the core clock by sampling a cycle counter while the core is running.
+fn measure(d: &mut Debugger) -> Result<(u32, &'static str)> {
+    if d.read_debug(DCB_DHCSR)? & DCB_DHCSR_S_HALT != 0 {
+        d.run()?;
+    }
+    let demcr = d.read_debug(DCB_DEMCR)?;
+    d.write_debug(DCB_DEMCR, demcr | DCB_DEMCR_TRCENA)?;
+    let ctrl = d.read_32(DWT_CTRL)?;
+    if ctrl & DWT_CTRL_NOCYCCNT == 0 {
+        d.write_32(DWT_CTRL, ctrl | DWT_CTRL_CYCCNTENA)?;
+        let (c0, t0) = sample(d, DWT_CYCCNT)?;
+        thread::sleep(Duration::from_millis(MEASURE_MS));
+        let (c1, t1) = sample(d, DWT_CYCCNT)?;
+        d.write_32(DWT_CTRL, ctrl)?;
+        let elapsed = duration_secs(t1 - t0);
+        return Ok((round_clock(c1.wrapping_sub(c0) as f64 / elapsed), "DWT cycle counter"))
+    }
+
+    // SysTick is borrowed from the application and restored afterwards. It is a 24-bit down
+    // counter, so this is accurate up to about 110 MHz.
+    let csr = d.read_32(SYST_CSR)?;
+    let rvr = d.read_32(SYST_RVR)?;
+    d.write_32(SYST_CSR, 0)?;
+    d.write_32(SYST_RVR, 0x00ff_ffff)?;
+    d.write_32(SYST_CVR, 0)?;
+    d.write_32(SYST_CSR, SYST_CSR_CLKSOURCE | SYST_CSR_ENABLE)?;
+    let (c0, t0) = sample(d, SYST_CVR)?;
+    thread::sleep(Duration::from_millis(MEASURE_MS));
+    let (c1, t1) = sample(d, SYST_CVR)?;
+    d.write_32(SYST_CSR, 0)?;
+    d.write_32(SYST_RVR, rvr)?;
+    d.write_32(SYST_CVR, 0)?;
+    d.write_32(SYST_CSR, csr)?;
+    let elapsed = duration_secs(t1 - t0);
+    Ok((round_clock((c0.wrapping_sub(c1) & 0x00ff_ffff) as f64 / elapsed), "SysTick"))
+}
+
+fn duration_secs(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9
+}
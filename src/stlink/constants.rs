@@ -77,16 +77,22 @@ pub const DEBUG_APIV2_STOP_TRACE_RX: u8 = 0x41;
 pub const DEBUG_APIV2_GET_TRACE_NB: u8 = 0x42;
 pub const DEBUG_APIV2_SWD_SET_FREQ: u8 = 0x43;
 
+pub const DEBUG_APIV2_READMEM_16BIT: u8 = 0x47;
+pub const DEBUG_APIV2_WRITEMEM_16BIT: u8 = 0x48;
+
 pub const DEBUG_APIV2_DRIVE_NRST_LOW: u8 = 0x00;
 pub const DEBUG_APIV2_DRIVE_NRST_HIGH: u8 = 0x01;
 pub const DEBUG_APIV2_DRIVE_NRST_PULSE: u8 = 0x02;
 
+pub const STLINK_TRACE_MAX_HZ: u32 = 2_000_000;
+
 // Temporary Register Constants
 
 pub const SCS_LAR_KEY: u32 = 0xC5ACCE55;
 pub const SCS_AIRCR: u32 = 0xe000ed0c;
 pub const SCS_AIRCR_KEY: u32 = (0x05fa << 16);
 pub const SCS_AIRCR_VECTCLRACTIVE: u32 = (1 << 1);
+pub const SCS_CPUID: u32 = 0xE000ED00;
 
 pub const DCB_DEMCR: u32 = 0xE000EDFC;
 pub const DCB_DEMCR_TRCENA: u32 = (1 << 24);
@@ -96,6 +102,7 @@ pub const DCB_DHCSR: u32 = 0xE000EDF0;
 pub const DCB_DHCSR_DBGKEY: u32 = (0xA05F << 16);
 pub const DCB_DHCSR_C_DEBUGEN: u32 = (1 << 0);
 pub const DCB_DHCSR_C_HALT: u32 = (1 << 1);
+pub const DCB_DHCSR_S_HALT: u32 = (1 << 17);
 
 pub const TPIU_CSPSR: u32 = 0xe0040004;
 pub const TPIU_ACPR: u32 = 0xE0040010;
@@ -116,6 +123,20 @@ pub const ITM_TCR_TSENA: u32 = (1 << 1);
 pub const ITM_TCR_ITMENA: u32 = (1 << 0);
 
 pub const DWT_CTRL: u32 = 0xE0001000;
+pub const DWT_CTRL_CYCCNTENA: u32 = (1 << 0);
+pub const DWT_CTRL_POSTPRESET_SHIFT: u32 = 1;
+pub const DWT_CTRL_POSTINIT_SHIFT: u32 = 5;
+pub const DWT_CTRL_CYCTAP: u32 = (1 << 9);
+pub const DWT_CTRL_PCSAMPLENA: u32 = (1 << 12);
+pub const DWT_CTRL_EXCTRCENA: u32 = (1 << 16);
+pub const DWT_CTRL_NOCYCCNT: u32 = (1 << 25);
+pub const DWT_CYCCNT: u32 = 0xE0001004;
+
+pub const SYST_CSR: u32 = 0xE000E010;
+pub const SYST_CSR_ENABLE: u32 = (1 << 0);
+pub const SYST_CSR_CLKSOURCE: u32 = (1 << 2);
+pub const SYST_RVR: u32 = 0xE000E014;
+pub const SYST_CVR: u32 = 0xE000E018;
 
 // STM32 stuff
 pub const DBGMCU_CR: u32 = 0xe0042004;
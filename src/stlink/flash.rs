@@ -0,0 +1,482 @@
+//! STM32 flash programming over the ST-Link debug interface.
+//!
+//! The flash controller registers are written directly from the host, so no flash loader runs
+//! on the target. The core should be halted before erasing or programming.
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{Debugger, Result};
+
+pub const FLASH_BASE: u32 = 0x0800_0000;
+
+const SCS_CPUID: u32 = 0xE000_ED00;
+const DBGMCU_IDCODE: u32 = 0xE004_2000;
+const DBGMCU_IDCODE_M0: u32 = 0x4001_5800;
+const DBGMCU_IDCODE_H7: u32 = 0x5C00_1000;
+
+const FLASH_KEY1: u32 = 0x4567_0123;
+const FLASH_KEY2: u32 = 0xCDEF_89AB;
+
+// Bytes per ST-Link memory transfer; a multiple of every flash write unit.
+const CHUNK_SIZE: usize = 1024;
+
+const ERASE_TIMEOUT_MS: u64 = 10_000;
+const PROGRAM_TIMEOUT_MS: u64 = 1_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Family {
+    F0,
+    F1,
+    F3,
+    F4,
+    L4,
+    G0,
+    H7,
+}
+
+struct Part {
+    dev_id: u16,
+    name: &'static str,
+    family: Family,
+    page_size: u32,
+    dual_bank: bool,
+}
+
+const fn part(dev_id: u16, name: &'static str, family: Family, page_size: u32, dual_bank: bool) -> Part {
+    Part { dev_id, name, family, page_size, dual_bank }
+}
+
+const PARTS: &[Part] = &[
+    part(0x440, "STM32F05x", Family::F0, 1 << 10, false),
+    part(0x442, "STM32F09x", Family::F0, 2 << 10, false),
+    part(0x444, "STM32F03x", Family::F0, 1 << 10, false),
+    part(0x445, "STM32F04x", Family::F0, 1 << 10, false),
+    part(0x448, "STM32F07x", Family::F0, 2 << 10, false),
+    part(0x410, "STM32F1 medium-density", Family::F1, 1 << 10, false),
+    part(0x412, "STM32F1 low-density", Family::F1, 1 << 10, false),
+    part(0x414, "STM32F1 high-density", Family::F1, 2 << 10, false),
+    part(0x418, "STM32F1 connectivity line", Family::F1, 2 << 10, false),
+    part(0x420, "STM32F1 value line", Family::F1, 1 << 10, false),
+    part(0x428, "STM32F1 high-density value line", Family::F1, 2 << 10, false),
+    part(0x430, "STM32F1 XL-density", Family::F1, 2 << 10, true),
+    part(0x422, "STM32F30x", Family::F3, 2 << 10, false),
+    part(0x432, "STM32F37x", Family::F3, 2 << 10, false),
+    part(0x438, "STM32F334", Family::F3, 2 << 10, false),
+    part(0x439, "STM32F301", Family::F3, 2 << 10, false),
+    part(0x446, "STM32F303xD/E", Family::F3, 2 << 10, false),
+    part(0x413, "STM32F405/407", Family::F4, 0, false),
+    part(0x419, "STM32F42x/43x", Family::F4, 0, false),
+    part(0x421, "STM32F446", Family::F4, 0, false),
+    part(0x423, "STM32F401xB/C", Family::F4, 0, false),
+    part(0x431, "STM32F411", Family::F4, 0, false),
+    part(0x433, "STM32F401xD/E", Family::F4, 0, false),
+    part(0x434, "STM32F469/479", Family::F4, 0, false),
+    part(0x441, "STM32F412", Family::F4, 0, false),
+    part(0x458, "STM32F410", Family::F4, 0, false),
+    part(0x463, "STM32F413/423", Family::F4, 0, false),
+    part(0x415, "STM32L47x/48x", Family::L4, 2 << 10, true),
+    part(0x435, "STM32L43x/44x", Family::L4, 2 << 10, false),
+    part(0x461, "STM32L49x/4Ax", Family::L4, 2 << 10, true),
+    part(0x462, "STM32L45x/46x", Family::L4, 2 << 10, false),
+    part(0x464, "STM32L41x/42x", Family::L4, 2 << 10, false),
+    part(0x456, "STM32G05x/06x", Family::G0, 2 << 10, false),
+    part(0x460, "STM32G07x/08x", Family::G0, 2 << 10, false),
+    part(0x466, "STM32G03x/04x", Family::G0, 2 << 10, false),
+    part(0x450, "STM32H74x/75x", Family::H7, 128 << 10, true),
+];
+
+/// An identified STM32 device.
+#[derive(Debug, Clone)]
+pub struct Chip {
+    pub dev_id: u16,
+    pub name: &'static str,
+    pub family: Family,
+    pub flash_size: u32,
+    page_size: u32,
+    bank_size: u32,
+}
+
+/// Reads the DBGMCU device id and flash size register to identify the target.
+pub fn identify(d: &mut Debugger) -> Result<Chip> {
+    let idcode_addrs: &[u32] = match (d.read_32(SCS_CPUID)? >> 4) & 0xfff {
+        0xc20 | 0xc60 => &[DBGMCU_IDCODE_M0],
+        0xc27 => &[DBGMCU_IDCODE_H7, DBGMCU_IDCODE],
+        _ => &[DBGMCU_IDCODE],
+    };
+    let mut dev_id = 0;
+    for &addr in idcode_addrs {
+        dev_id = (d.read_32(addr)? & 0xfff) as u16;
+        if let Some(part) = PARTS.iter().find(|p| p.dev_id == dev_id) {
+            let flash_size = read_u16(d, flash_size_addr(part.family))? << 10;
+            if flash_size == 0 {
+                bail!("{}: could not read flash size", part.name);
+            }
+            let bank_size = match part.family {
+                // Bank 1 of the XL-density parts is always 512K.
+                Family::F1 if part.dual_bank => 512 << 10,
+                Family::F4 if flash_size >= 2 << 20 => flash_size / 2,
+                _ if part.dual_bank => flash_size / 2,
+                _ => flash_size,
+            };
+            return Ok(Chip {
+                dev_id,
+                name: part.name,
+                family: part.family,
+                flash_size,
+                page_size: part.page_size,
+                bank_size,
+            });
+        }
+    }
+    bail!("Unsupported STM32 device id 0x{:03x}", dev_id)
+}
+
+fn flash_size_addr(family: Family) -> u32 {
+    match family {
+        Family::F0 | Family::F3 => 0x1FFF_F7CC,
+        Family::F1 => 0x1FFF_F7E0,
+        Family::F4 => 0x1FFF_7A22,
+        Family::L4 | Family::G0 => 0x1FFF_75E0,
+        Family::H7 => 0x1FF1_E880,
+    }
+}
+
+fn read_u16(d: &mut Debugger, addr: u32) -> Result<u32> {
+    let value = d.read_32(addr & !3)?;
+    Ok((value >> ((addr & 3) * 8)) & 0xffff)
+}
+
+/// An erasable page or sector.
+#[derive(Debug, Clone, Copy)]
+pub struct Sector {
+    pub addr: u32,
+    pub size: u32,
+    bank: u32,
+    number: u32,
+}
+
+impl Sector {
+    fn end(&self) -> u32 {
+        self.addr + self.size
+    }
+}
+
+struct Regs {
+    keyr: u32,
+    sr: u32,
+    cr: u32,
+    ar: u32,
+    ccr: u32,
+}
+
+pub struct Flash<'d, 'a: 'd> {
+    d: &'d mut Debugger<'a>,
+    chip: Chip,
+}
+
+impl<'d, 'a> Flash<'d, 'a> {
+    pub fn new(d: &'d mut Debugger<'a>, chip: Chip) -> Result<Self> {
+        match chip.family {
+            Family::F0 | Family::F1 | Family::F3 => {
+                let version = d.version()?;
+                if version.stlink() == 2 && version.jtag() < 26 {
+                    bail!("Programming the {} requires ST-Link/V2 firmware J26 or later (found J{})",
+                        chip.name, version.jtag());
+                }
+            }
+            _ => {}
+        }
+        Ok(Flash { d, chip })
+    }
+
+    pub fn chip(&self) -> &Chip {
+        &self.chip
+    }
+
+    fn banks(&self) -> u32 {
+        if self.chip.bank_size < self.chip.flash_size { 2 } else { 1 }
+    }
+
+    /// Returns the pages or sectors of each bank, in address order.
+    pub fn sectors(&self) -> Vec<Sector> {
+        let mut sectors = Vec::new();
+        for bank in 0..self.banks() {
+            let (base, size) = if bank == 0 {
+                (FLASH_BASE, self.chip.bank_size)
+            } else if self.chip.family == Family::H7 {
+                (0x0810_0000, self.chip.flash_size - self.chip.bank_size)
+            } else {
+                (FLASH_BASE + self.chip.bank_size, self.chip.flash_size - self.chip.bank_size)
+            };
+            let mut offset = 0;
+            let mut number = 0;
+            while offset < size {
+                let sector_size = match self.chip.family {
+                    Family::F4 => match number {
+                        0..=3 => 16 << 10,
+                        4 => 64 << 10,
+                        _ => 128 << 10,
+                    },
+                    _ => self.chip.page_size,
+                };
+                sectors.push(Sector { addr: base + offset, size: sector_size, bank, number });
+                offset += sector_size;
+                number += 1;
+            }
+        }
+        sectors
+    }
+
+    /// Returns the sectors that must be erased to program `len` bytes at `addr`. Fails if any
+    /// part of the range is not flash.
+    pub fn sectors_for(&self, addr: u32, len: usize) -> Result<Vec<Sector>> {
+        let end = addr as u64 + len as u64;
+        let sectors: Vec<Sector> = self.sectors().into_iter()
+            .filter(|s| (s.addr as u64) < end && s.end() > addr)
+            .collect();
+        let mut pos = addr as u64;
+        for s in sectors.iter() {
+            if s.addr as u64 > pos {
+                break;
+            }
+            pos = s.end() as u64;
+        }
+        if pos < end {
+            bail!("0x{:08x} is outside of the {}K flash of the {}", pos, self.chip.flash_size >> 10, self.chip.name);
+        }
+        Ok(sectors)
+    }
+
+    fn regs(&self, bank: u32) -> Regs {
+        match self.chip.family {
+            Family::F0 | Family::F1 | Family::F3 => {
+                let base = 0x4002_2000 + bank * 0x40;
+                Regs { keyr: base + 0x04, sr: base + 0x0C, cr: base + 0x10, ar: base + 0x14, ccr: 0 }
+            }
+            Family::F4 => {
+                let base = 0x4002_3C00;
+                Regs { keyr: base + 0x04, sr: base + 0x0C, cr: base + 0x10, ar: 0, ccr: 0 }
+            }
+            Family::L4 | Family::G0 => {
+                let base = 0x4002_2000;
+                Regs { keyr: base + 0x08, sr: base + 0x10, cr: base + 0x14, ar: 0, ccr: 0 }
+            }
+            Family::H7 => {
+                let base = 0x5200_2000 + bank * 0x100;
+                Regs { keyr: base + 0x04, sr: base + 0x10, cr: base + 0x0C, ar: 0, ccr: base + 0x14 }
+            }
+        }
+    }
+
+    fn lock_bit(&self) -> u32 {
+        match self.chip.family {
+            Family::F0 | Family::F1 | Family::F3 => 1 << 7,
+            Family::H7 => 1 << 0,
+            _ => 1 << 31,
+        }
+    }
+
+    fn busy_bits(&self) -> u32 {
+        match self.chip.family {
+            Family::F0 | Family::F1 | Family::F3 => 1 << 0,
+            // BSY and QW
+            Family::H7 => (1 << 0) | (1 << 2),
+            _ => 1 << 16,
+        }
+    }
+
+    fn error_bits(&self) -> u32 {
+        match self.chip.family {
+            // PGERR, WRPRTERR
+            Family::F0 | Family::F1 | Family::F3 => 0x0000_0014,
+            // OPERR, WRPERR, PGAERR, PGPERR, PGSERR, RDERR
+            Family::F4 => 0x0000_01F2,
+            // OPERR, PROGERR, WRPERR, PGAERR, SIZERR, PGSERR, MISERR, FASTERR, RDERR, OPTVERR
+            Family::L4 | Family::G0 => 0x0000_C3FA,
+            // WRPERR, PGSERR, STRBERR, INCERR, OPERR, RDPERR, RDSERR, SNECCERR, DBECCERR
+            Family::H7 => 0x07EE_0000,
+        }
+    }
+
+    fn eop_bit(&self) -> u32 {
+        match self.chip.family {
+            Family::F0 | Family::F1 | Family::F3 => 1 << 5,
+            Family::H7 => 1 << 16,
+            _ => 1 << 0,
+        }
+    }
+
+    /// The number of bytes written to flash at a time.
+    pub fn write_unit(&self) -> usize {
+        match self.chip.family {
+            Family::F0 | Family::F1 | Family::F3 => 2,
+            Family::F4 => 4,
+            Family::L4 | Family::G0 => 8,
+            Family::H7 => 32,
+        }
+    }
+
+    fn clear_status(&mut self, bank: u32) -> Result<()> {
+        let regs = self.regs(bank);
+        let bits = self.error_bits() | self.eop_bit();
+        if self.chip.family == Family::H7 {
+            self.d.write_32(regs.ccr, bits)
+        } else {
+            self.d.write_32(regs.sr, bits)
+        }
+    }
+
+    fn wait(&mut self, bank: u32, addr: u32, timeout_ms: u64) -> Result<()> {
+        let regs = self.regs(bank);
+        let start = Instant::now();
+        let sr = loop {
+            let sr = self.d.read_32(regs.sr)?;
+            if sr & self.busy_bits() == 0 {
+                break sr;
+            }
+            if start.elapsed() > Duration::from_millis(timeout_ms) {
+                bail!("Timeout waiting for flash at 0x{:08x} (SR=0x{:08x})", addr, sr);
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        if sr & self.error_bits() != 0 {
+            self.clear_status(bank)?;
+            bail!("Flash error at 0x{:08x} (SR=0x{:08x})", addr, sr);
+        }
+        Ok(())
+    }
+
+    /// Unlocks the flash controller of every bank.
+    pub fn unlock(&mut self) -> Result<()> {
+        for bank in 0..self.banks() {
+            let regs = self.regs(bank);
+            if self.d.read_32(regs.cr)? & self.lock_bit() != 0 {
+                self.d.write_32(regs.keyr, FLASH_KEY1)?;
+                self.d.write_32(regs.keyr, FLASH_KEY2)?;
+                if self.d.read_32(regs.cr)? & self.lock_bit() != 0 {
+                    bail!("Could not unlock the flash of the {}", self.chip.name);
+                }
+            }
+            self.clear_status(bank)?;
+        }
+        Ok(())
+    }
+
+    /// Locks the flash controller of every bank.
+    pub fn lock(&mut self) -> Result<()> {
+        for bank in 0..self.banks() {
+            let regs = self.regs(bank);
+            let lock = self.lock_bit();
+            self.d.write_32(regs.cr, lock)?;
+        }
+        Ok(())
+    }
+
+    /// Erases a single page or sector.
+    pub fn erase_sector(&mut self, sector: &Sector) -> Result<()> {
+        let regs = self.regs(sector.bank);
+        match self.chip.family {
+            Family::F0 | Family::F1 | Family::F3 => {
+                // PER, then STRT
+                self.d.write_32(regs.cr, 1 << 1)?;
+                self.d.write_32(regs.ar, sector.addr)?;
+                self.d.write_32(regs.cr, (1 << 1) | (1 << 6))?;
+            }
+            Family::F4 => {
+                // SER, PSIZE x32 and SNB, then STRT. Bank 2 sectors are numbered from 0x10.
+                let snb = if sector.bank == 1 { 0x10 | sector.number } else { sector.number };
+                let cr = (1 << 1) | (2 << 8) | (snb << 3);
+                self.d.write_32(regs.cr, cr)?;
+                self.d.write_32(regs.cr, cr | (1 << 16))?;
+            }
+            Family::L4 | Family::G0 => {
+                // PER, PNB and BKER, then STRT
+                let cr = (1 << 1) | (sector.number << 3) | (sector.bank << 11);
+                self.d.write_32(regs.cr, cr)?;
+                self.d.write_32(regs.cr, cr | (1 << 16))?;
+            }
+            Family::H7 => {
+                // SER, PSIZE x64 and SNB, then START
+                let cr = (1 << 2) | (3 << 4) | (sector.number << 8);
+                self.d.write_32(regs.cr, cr)?;
+                self.d.write_32(regs.cr, cr | (1 << 7))?;
+            }
+        }
+        let result = self.wait(sector.bank, sector.addr, ERASE_TIMEOUT_MS);
+        self.d.write_32(regs.cr, 0)?;
+        result
+    }
+
+    /// Programs `data` at `addr`. Both must be aligned to the write unit, and the flash must
+    /// already be erased.
+    pub fn program(&mut self, addr: u32, data: &[u8]) -> Result<()> {
+        let unit = self.write_unit();
+        if addr as usize % unit != 0 || data.len() % unit != 0 {
+            bail!("Flash writes must be aligned to {} bytes", unit);
+        }
+        let sectors = self.sectors_for(addr, data.len())?;
+        for sector in sectors.iter() {
+            let start = sector.addr.max(addr);
+            let end = sector.end().min(addr + data.len() as u32);
+            let offset = (start - addr) as usize;
+            self.program_bank(sector.bank, start, &data[offset..offset + (end - start) as usize])?;
+        }
+        Ok(())
+    }
+
+    fn program_bank(&mut self, bank: u32, addr: u32, data: &[u8]) -> Result<()> {
+        let regs = self.regs(bank);
+        let cr = match self.chip.family {
+            // PG
+            Family::F0 | Family::F1 | Family::F3 | Family::L4 | Family::G0 => 1 << 0,
+            // PG, PSIZE x32
+            Family::F4 => (1 << 0) | (2 << 8),
+            // PG, PSIZE x64
+            Family::H7 => (1 << 1) | (3 << 4),
+        };
+        self.d.write_32(regs.cr, cr)?;
+        let mut result = Ok(());
+        for (i, chunk) in data.chunks(CHUNK_SIZE).enumerate() {
+            let chunk_addr = addr + (i * CHUNK_SIZE) as u32;
+            result = self.write_chunk(chunk_addr, chunk)
+                .and_then(|_| self.wait(bank, chunk_addr, PROGRAM_TIMEOUT_MS));
+            if result.is_err() {
+                break;
+            }
+        }
+        self.d.write_32(regs.cr, 0)?;
+        result
+    }
+
+    fn write_chunk(&mut self, addr: u32, chunk: &[u8]) -> Result<()> {
+        match self.chip.family {
+            // The F0/F1/F3 flash controllers only accept half-word writes.
+            Family::F0 | Family::F1 | Family::F3 => self.d.write_mem16(addr, chunk),
+            _ => {
+                let words: Vec<u32> = chunk.chunks(4).map(LittleEndian::read_u32).collect();
+                self.d.write_mem32(addr, &words)
+            }
+        }
+    }
+
+    /// Reads back the flash at `addr` and compares it with `data`.
+    pub fn verify(&mut self, addr: u32, data: &[u8]) -> Result<()> {
+        let mut buf = [0u32; CHUNK_SIZE / 4];
+        for (i, chunk) in data.chunks(CHUNK_SIZE).enumerate() {
+            let chunk_addr = addr + (i * CHUNK_SIZE) as u32;
+            let words = (chunk.len() + 3) / 4;
+            self.d.read_mem32(chunk_addr, &mut buf[..words])?;
+            for (j, &expected) in chunk.iter().enumerate() {
+                let actual = (buf[j / 4] >> ((j % 4) * 8)) as u8;
+                if actual != expected {
+                    bail!("Verify failed at 0x{:08x}: expected 0x{:02x}, read 0x{:02x}",
+                        chunk_addr + j as u32, expected, actual);
+                }
+            }
+        }
+        Ok(())
+    }
+}
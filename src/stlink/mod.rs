@@ -1,5 +1,7 @@
 mod util;
 mod constants;
+pub mod clock;
+pub mod flash;
 
 use libusb;
 pub use self::constants::*;
@@ -7,6 +9,11 @@ pub use self::constants::*;
 
 use byteorder::{ByteOrder, LittleEndian};
 
+use device::{Device, UsbDevice};
+use itm::{self, TraceConfig};
+use mem::Memory;
+use semihosting;
+
 use std::time::Duration;
 use std::thread;
 use std::convert::{AsRef, AsMut};
@@ -254,6 +261,28 @@ impl<'a> Debugger<'a> {
         self.send_req([DFU_COMMAND, DFU_EXIT])
     }
 
+    /// Switches the ST-Link into SWD debug mode, resetting the probe if it does not respond.
+    pub fn enter_debug_mode(&mut self) -> Result<()> {
+        let mode = match self.mode() {
+            Ok(mode) => mode,
+            Err(_) => {
+                self.reinit()?;
+                self.configure(true)?;
+                self.mode()?
+            }
+        };
+        if mode == Mode::Dfu {
+            self.exit_dfu_mode()?;
+        }
+        if mode != Mode::Debug {
+            self.enter_swd_mode()?;
+            if self.mode()? != Mode::Debug {
+                bail!("Could not enter Debug mode");
+            }
+        }
+        Ok(())
+    }
+
     pub fn reset(&mut self) -> Result<()> {
         self.cmd([DEBUG_COMMAND, DEBUG_APIV2_RESETSYS])
     }
@@ -270,6 +299,36 @@ impl<'a> Debugger<'a> {
         self.cmd([DEBUG_COMMAND, DEBUG_STEPCORE])
     }
 
+    /// Resets the target and halts it at the reset vector.
+    pub fn reset_halt(&mut self) -> Result<()> {
+        self.write_debug(DCB_DHCSR, DCB_DHCSR_DBGKEY | DCB_DHCSR_C_DEBUGEN | DCB_DHCSR_C_HALT)?;
+        let demcr = self.read_debug(DCB_DEMCR)?;
+        self.write_debug(DCB_DEMCR, demcr | DCB_DEMCR_VC_CORERESET)?;
+        self.reset()?;
+        let mut halted = false;
+        for _ in 0..50 {
+            if self.read_debug(DCB_DHCSR)? & DCB_DHCSR_S_HALT != 0 {
+                halted = true;
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        self.write_debug(DCB_DEMCR, demcr & !DCB_DEMCR_VC_CORERESET)?;
+        if !halted {
+            bail!("Target did not halt after reset");
+        }
+        Ok(())
+    }
+
+    /// Resets the target and lets it run.
+    pub fn reset_run(&mut self) -> Result<()> {
+        let demcr = self.read_debug(DCB_DEMCR)?;
+        self.write_debug(DCB_DEMCR, demcr & !DCB_DEMCR_VC_CORERESET)?;
+        self.write_debug(DCB_DHCSR, DCB_DHCSR_DBGKEY | DCB_DHCSR_C_DEBUGEN)?;
+        self.reset()?;
+        self.run()
+    }
+
 
     pub fn read_regs(&mut self) -> Result<Response> {
         let cmd = Request::new([DEBUG_COMMAND, DEBUG_APIV2_READALLREGS]);
@@ -323,6 +382,26 @@ impl<'a> Debugger<'a> {
         self.check_rw_status()
     }
 
+    /// Reads `dst` using half-word accesses. Requires ST-Link/V2 firmware J26 or later.
+    pub fn read_mem16(&mut self, addr: u32, dst: &mut [u8]) -> Result<()> {
+        let cmd = Request::new([DEBUG_COMMAND, DEBUG_APIV2_READMEM_16BIT])
+            .write_u32(addr)
+            .write_u16(dst.len() as u16);
+        self.send_req(cmd)?;
+        self.recv(dst)?;
+        self.check_rw_status()
+    }
+
+    /// Writes `src` using half-word accesses. Requires ST-Link/V2 firmware J26 or later.
+    pub fn write_mem16(&mut self, addr: u32, src: &[u8]) -> Result<()> {
+        let cmd = Request::new([DEBUG_COMMAND, DEBUG_APIV2_WRITEMEM_16BIT])
+            .write_u32(addr)
+            .write_u16(src.len() as u16);
+        self.send_req(cmd)?;
+        self.send(src)?;
+        self.check_rw_status()
+    }
+
 
     pub fn read_mem32(&mut self, addr: u32, dst: &mut [u32]) -> Result<()> {
         let cmd = Request::new([DEBUG_COMMAND, DEBUG_READMEM_32BIT])
@@ -420,6 +499,7 @@ impl<'a> Debugger<'a> {
     pub fn trace_setup(
         &mut self,
         stim_bits: u32,
+        priv_bits: u32,
         sync_packets: u32,
         cpu_hz: u32,
         swo_hz: u32,
@@ -433,7 +513,7 @@ impl<'a> Debugger<'a> {
         self.write_32(DBGMCU_CR, reg)?;
         // ST ref man says we set this to 1 even in async mode, it's still "one" pin wide
         self.write_32(TPIU_CSPSR, 1)?; // currently selelct parallel size register ==> 1 bit wide.
-        let prescaler = (cpu_hz / swo_hz) - 1;
+        let prescaler = itm::prescaler(cpu_hz, swo_hz)?;
         self.write_32(TPIU_ACPR, prescaler)?; // async prescalar
         self.write_32(TPIU_SPPR, TPIU_SPPR_TXMODE_NRZ)?;
         self.write_32(TPIU_FFCR, 0)?; // Disable tpiu formatting
@@ -443,7 +523,7 @@ impl<'a> Debugger<'a> {
             ((1 << 16) | ITM_TCR_SYNCENA | ITM_TCR_ITMENA),
         )?;
         self.write_32(ITM_TER, stim_bits)?;
-        self.write_32(ITM_TPR, stim_bits)?;
+        self.write_32(ITM_TPR, priv_bits)?;
         self.set_dwt_sync_tap(sync_packets)?;
         Ok(())
     }
@@ -466,54 +546,86 @@ impl<'a> Debugger<'a> {
         Ok(())
     }
 
-    pub fn run_trace(&mut self) -> Result<()> {
-        use std::time::Duration;
-        use std::thread;
-        use std::io::{self, Write};
-
-        let mode = match self.mode() {
-            Ok(mode) => mode,
-            Err(_) => {
-                self.reinit()?;
-                self.configure(true)?;
-                self.mode()?
-            }
-        };
-        if mode == Mode::Dfu {
-            self.exit_dfu_mode()?;
-        }
-        if mode != Mode::Debug {
-            self.enter_swd_mode()?;
+    /// Starts SWO trace and passes the raw trace data to `sink` until it returns false. `sink` is
+    /// also called with no data while the trace is idle.
+    pub fn run_trace(&mut self, trace: &TraceConfig, sink: &mut FnMut(&[u8]) -> Result<bool>) -> Result<()> {
+        if trace.trace_clk > STLINK_TRACE_MAX_HZ {
+            bail!("The ST-Link supports ITM trace clocks up to {} Hz", STLINK_TRACE_MAX_HZ);
         }
-
-        if mode != Mode::Debug {
-            bail!("Could not enter Debug mode");
-        }
-
+        self.enter_debug_mode()?;
         self.halt()?;
 
-        let (target_clk, trace_clk) = (self.config.target_clk, self.config.trace_clk);
-
-        self.trace_setup(0xffffffff, 0, target_clk, trace_clk)?;
-        self.trace_start_rx(trace_clk)?;
+        self.trace_setup(trace.enable_mask, trace.privilege_mask, 0, trace.target_clk, trace.trace_clk)?;
+        if trace.timestamps {
+            let tcr = self.read_32(ITM_TCR)?;
+            self.write_32(ITM_TCR, tcr | ITM_TCR_TSENA | ITM_TCR_TXENA)?;
+            let ctrl = self.read_32(DWT_CTRL)?;
+            self.write_32(DWT_CTRL, ctrl | DWT_CTRL_EXCTRCENA)?;
+        }
+        if let Some(ref sampling) = trace.pc_sampling {
+            let tcr = self.read_32(ITM_TCR)?;
+            self.write_32(ITM_TCR, tcr | ITM_TCR_TXENA)?;
+            let mut ctrl = self.read_32(DWT_CTRL)?;
+            ctrl &= !(DWT_CTRL_CYCTAP | (0xf << DWT_CTRL_POSTINIT_SHIFT) | (0xf << DWT_CTRL_POSTPRESET_SHIFT));
+            if sampling.cyctap {
+                ctrl |= DWT_CTRL_CYCTAP;
+            }
+            ctrl |= (sampling.postpreset as u32) << DWT_CTRL_POSTPRESET_SHIFT;
+            ctrl |= (sampling.postpreset as u32) << DWT_CTRL_POSTINIT_SHIFT;
+            self.write_32(DWT_CTRL, ctrl | DWT_CTRL_CYCCNTENA)?;
+            self.write_32(DWT_CTRL, ctrl | DWT_CTRL_CYCCNTENA | DWT_CTRL_PCSAMPLENA)?;
+        }
+        self.trace_start_rx(trace.trace_clk)?;
         self.run()?;
 
         let mut trace_buf = [0u8; 4096];
-        let stdout = io::stdout();
-        let mut stdout = stdout.lock();
         loop {
             let n = self.trace_read(&mut trace_buf)?;
-            if n > 0 {
-                let mut r = Reader::new(&trace_buf[..n]);
-                while let Some((port, data)) = r.next() {
-                    if port == 0 {
-                        stdout.write_all(data)?
-                    }
-                }
-                stdout.flush()?;
+            if !sink(&trace_buf[..n])? {
+                break;
             }
             thread::sleep(Duration::from_millis(10));
         }
+        if trace.pc_sampling.is_some() {
+            let ctrl = self.read_32(DWT_CTRL)?;
+            self.write_32(DWT_CTRL, ctrl & !DWT_CTRL_PCSAMPLENA)?;
+        }
+        self.trace_stop_rx()
+    }
+}
+
+impl<'a> Memory for Debugger<'a> {
+    // Byte transfers are limited to 64 bytes.
+    fn read(&mut self, addr: u32, data: &mut [u8]) -> Result<()> {
+        for (i, chunk) in data.chunks_mut(64).enumerate() {
+            self.read_mem8(addr + (i * 64) as u32, chunk)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, addr: u32, data: &[u8]) -> Result<()> {
+        for (i, chunk) in data.chunks(64).enumerate() {
+            self.write_mem8(addr + (i * 64) as u32, chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> semihosting::Target for Debugger<'a> {
+    fn register(&mut self, reg: u8) -> Result<u32> {
+        self.read_reg(reg)
+    }
+
+    fn set_register(&mut self, reg: u8, value: u32) -> Result<()> {
+        self.write_reg(reg, value)
+    }
+
+    fn is_halted(&mut self) -> Result<bool> {
+        Ok(self.read_debug(DCB_DHCSR)? & DCB_DHCSR_S_HALT != 0)
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        self.run()
     }
 }
 
@@ -524,6 +636,54 @@ pub fn context() -> Result<Context> {
     })
 }
 
+/// Connects to the ST-Link probe for `device`, switches it into debug mode and calls `f`.
+///
+/// The send, receive and trace endpoints come from the device table; `target_clk` and
+/// `trace_clk` are only used for SWO trace.
+pub fn with_debugger<T, F>(device: &Device, target_clk: u32, trace_clk: u32, f: F) -> Result<T>
+    where F: FnOnce(&mut Debugger) -> Result<T>
+{
+    with_probe(device, target_clk, trace_clk, |d| {
+        d.enter_debug_mode()?;
+        f(d)
+    })
+}
+
+/// Connects to the ST-Link probe for `device` and calls `f`, leaving the probe in its current mode.
+pub fn with_probe<T, F>(device: &Device, target_clk: u32, trace_clk: u32, f: F) -> Result<T>
+    where F: FnOnce(&mut Debugger) -> Result<T>
+{
+    let ep = match device.stlink_endpoints() {
+        Some(ep) if ep.len() == 3 => ep,
+        _ => bail!("No ST-Link endpoints configured for {}", device.device_type().unwrap_or("this device")),
+    };
+    with_usb(device.usb(), ep, target_clk, trace_clk, f)
+}
+
+/// Connects to the ST-Link probe `usb` using the send, receive and trace endpoints in `ep` and
+/// calls `f`. Unlike `with_probe`, this needs no `Device`, so it can be used from another thread.
+pub fn with_usb<T, F>(usb: &UsbDevice, ep: &[u8], target_clk: u32, trace_clk: u32, f: F) -> Result<T>
+    where F: FnOnce(&mut Debugger) -> Result<T>
+{
+    let mut ctx = context()?;
+    let cfg = Config::new(
+        usb.vendor_id,
+        usb.product_id,
+        ep[0],
+        ep[1],
+        ep[2],
+        target_clk,
+        trace_clk,
+        &usb.serial_number,
+    );
+    if let Some(mut d) = ctx.connect(cfg)? {
+        d.configure(false)?;
+        f(&mut d)
+    } else {
+        bail!("No device found");
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Mode {
     Dfu = 0x0,
@@ -552,6 +712,18 @@ impl Version {
         (self.version() & 0x3f) as u8
     }
 
+    /// Returns the debug API level: 3 on ST-Link/V3, 2 from JTAG firmware version 11, and 1
+    /// before that.
+    pub fn api(&self) -> u8 {
+        if self.stlink() >= 3 {
+            3
+        } else if self.jtag() >= 11 {
+            2
+        } else {
+            1
+        }
+    }
+
     pub fn vid(&self) -> u16 {
         LittleEndian::read_u16(&self.0[2..4])
     }
@@ -657,49 +829,3 @@ impl AsMut<[u8]> for Response {
         &mut self.buf[..self.cap]
     }
 }
-
-
-pub struct Reader<'a> {
-    buf: &'a [u8],
-    pos: usize,
-}
-
-impl<'a> Reader<'a> {
-    pub fn new(buf: &[u8]) -> Reader {
-        Reader { buf: buf, pos: 0 }
-    }
-
-    pub fn next(&mut self) -> Option<(u8, &'a [u8])> {
-        if self.pos >= self.buf.len() {
-            return None;
-        }
-        let len = self.buf.len();
-        let pos = self.pos;
-        let header = self.buf[pos];
-        let port = header >> 3;
-        match header & 0b111 {
-            0b01 => {
-                self.pos += 2;
-                if pos + 2 > len {
-                    return None;
-                };
-                Some((port, &self.buf[pos + 1..pos + 2]))
-            }
-            0b10 => {
-                self.pos += 3;
-                if pos + 3 > len {
-                    return None;
-                };
-                Some((port, &self.buf[pos + 1..pos + 3]))
-            }
-            0b11 => {
-                self.pos += 5;
-                if pos + 5 > len {
-                    return None;
-                };
-                Some((port, &self.buf[pos + 1..pos + 5]))
-            }
-            _ => None,
-        }
-    }
-}
@@ -0,0 +1,109 @@
+//! Raw-mode terminal support for the interactive console.
+//!
+//! In raw mode every key is sent to the device as it is typed, including control characters such
+//! as Ctrl-C and the escape sequences of arrow keys. Local commands are typed after the escape
+//! key (Ctrl-A by default, as in picocom).
+
+use std::io::{self, Read};
+use std::os::unix::io::RawFd;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+use termios::{self, Termios};
+
+use Result;
+
+const STDIN: RawFd = 0;
+
+/// Returns true if stdin is a terminal.
+pub fn is_terminal() -> bool {
+    Termios::from_fd(STDIN).is_ok()
+}
+
+/// Puts the terminal on stdin in raw mode, restoring its settings when dropped.
+pub struct RawMode {
+    saved: Termios,
+}
+
+impl RawMode {
+    pub fn enable() -> Result<RawMode> {
+        let saved = match Termios::from_fd(STDIN) {
+            Ok(saved) => saved,
+            Err(_) => bail!("stdin is not a terminal"),
+        };
+        let mut raw = saved;
+        termios::cfmakeraw(&mut raw);
+        // Keep translating "\n" to "\r\n" on output, so that local messages, defmt frames and
+        // devices that only send "\n" are displayed normally.
+        raw.c_oflag |= termios::OPOST | termios::ONLCR;
+        termios::tcsetattr(STDIN, termios::TCSANOW, &raw)?;
+        Ok(RawMode { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = termios::tcsetattr(STDIN, termios::TCSANOW, &self.saved);
+    }
+}
+
+/// Reads stdin on a background thread, returning the bytes as they are typed.
+pub fn stdin_bytes() -> Receiver<Vec<u8>> {
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        let mut buf = [0u8; 256];
+        let stdin = io::stdin();
+        let mut stdin = stdin.lock();
+        loop {
+            match stdin.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => if tx.send(buf[..n].to_vec()).is_err() { break },
+            }
+        }
+    });
+    rx
+}
+
+/// Parses the escape key: a letter, optionally written as "C-a" or "^a", meaning Ctrl and that
+/// letter.
+pub fn parse_escape(value: &str) -> Result<u8> {
+    let key = value.trim_start_matches("C-").trim_start_matches('^');
+    match key.as_bytes() {
+        [c] if c.is_ascii_alphabetic() => Ok(c.to_ascii_lowercase() - b'a' + 1),
+        _ => bail!("Invalid escape key: {} (expected a letter, e.g. a for Ctrl-A)", value),
+    }
+}
+
+/// Returns the name of a control key, e.g. "C-a".
+pub fn key_name(key: u8) -> String {
+    format!("C-{}", (key + b'a' - 1) as char)
+}
+
+/// Formats received bytes for the hex view, 16 to a line. `column` is the number of bytes
+/// already on the current line.
+pub fn hex_dump(data: &[u8], column: &mut usize) -> String {
+    let mut out = String::new();
+    for b in data {
+        out.push_str(&format!("{:02x}", b));
+        *column += 1;
+        if *column == 16 {
+            out.push('\n');
+            *column = 0;
+        } else {
+            out.push(' ');
+        }
+    }
+    out
+}
+
+/// The local commands, shown by the help command.
+pub const COMMANDS: &[(&str, &str)] = &[
+    ("q / x", "quit"),
+    ("b", "send break"),
+    ("d", "toggle DTR"),
+    ("r", "toggle RTS"),
+    ("s", "change the baud rate"),
+    ("h", "toggle the hex view"),
+    ("f", "send a file"),
+    ("?", "show this help"),
+];
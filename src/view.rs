@@ -0,0 +1,237 @@
+//! Console views for binary protocols.
+//!
+//! The hex view dumps the received bytes, and the COBS and SLIP views decode the byte stream into
+//! frames. Each line starts with the seconds since the console was opened. Payloads typed into the
+//! console as hex are sent with the same framing.
+
+use std::time::Instant;
+
+use clap::ArgMatches;
+use config::Config;
+use Result;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum View {
+    /// Display the console output as-is.
+    Text,
+    /// Display a hexdump of the received bytes.
+    Hex,
+    /// Decode frames delimited by a zero byte with Consistent Overhead Byte Stuffing.
+    Cobs,
+    /// Decode frames using the Serial Line Internet Protocol (RFC 1055) framing.
+    Slip,
+}
+
+impl View {
+    pub fn from_name(name: &str) -> Result<View> {
+        match name {
+            "text" => Ok(View::Text),
+            "hex" => Ok(View::Hex),
+            "cobs" => Ok(View::Cobs),
+            "slip" => Ok(View::Slip),
+            _ => bail!("Unknown console view: {} (expected hex, cobs, slip or text)", name),
+        }
+    }
+
+    /// Frames a payload for sending.
+    pub fn encode(&self, payload: &[u8]) -> Vec<u8> {
+        match *self {
+            View::Text | View::Hex => payload.to_vec(),
+            View::Cobs => cobs_encode(payload),
+            View::Slip => slip_encode(payload),
+        }
+    }
+}
+
+/// Returns the view selected with --view or the [console] section of .bobbin/config, text by
+/// default.
+pub fn view(cfg: &Config, cmd_args: &ArgMatches) -> Result<View> {
+    match cfg.console_view(cmd_args) {
+        Some(name) => View::from_name(&name),
+        None => Ok(View::Text),
+    }
+}
+
+const SLIP_END: u8 = 0xc0;
+const SLIP_ESC: u8 = 0xdb;
+const SLIP_ESC_END: u8 = 0xdc;
+const SLIP_ESC_ESC: u8 = 0xdd;
+
+/// Encodes a payload with COBS, followed by the zero delimiter.
+pub fn cobs_encode(payload: &[u8]) -> Vec<u8> {
+    let mut out = vec![0];
+    let mut code_index = 0;
+    for b in payload {
+        if *b != 0 {
+            out.push(*b);
+        }
+        if *b == 0 || out.len() - code_index == 0xff {
+            out[code_index] = (out.len() - code_index) as u8;
+            code_index = out.len();
+            out.push(0);
+        }
+    }
+    out[code_index] = (out.len() - code_index) as u8;
+    out.push(0);
+    out
+}
+
+/// Decodes a COBS frame without its zero delimiter.
+pub fn cobs_decode(frame: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < frame.len() {
+        let code = frame[i] as usize;
+        if code == 0 || i + code > frame.len() {
+            bail!("invalid COBS frame")
+        }
+        out.extend_from_slice(&frame[i + 1..i + code]);
+        i += code;
+        if code < 0xff && i < frame.len() {
+            out.push(0);
+        }
+    }
+    Ok(out)
+}
+
+/// Encodes a payload with SLIP, with an END byte before and after it.
+pub fn slip_encode(payload: &[u8]) -> Vec<u8> {
+    let mut out = vec![SLIP_END];
+    for b in payload {
+        match *b {
+            SLIP_END => out.extend_from_slice(&[SLIP_ESC, SLIP_ESC_END]),
+            SLIP_ESC => out.extend_from_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+            b => out.push(b),
+        }
+    }
+    out.push(SLIP_END);
+    out
+}
+
+/// Decodes a SLIP frame without its END bytes.
+pub fn slip_decode(frame: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut bytes = frame.iter();
+    while let Some(b) = bytes.next() {
+        if *b != SLIP_ESC {
+            out.push(*b);
+            continue
+        }
+        match bytes.next() {
+            Some(&SLIP_ESC_END) => out.push(SLIP_END),
+            Some(&SLIP_ESC_ESC) => out.push(SLIP_ESC),
+            _ => bail!("invalid SLIP escape"),
+        }
+    }
+    Ok(out)
+}
+
+/// Parses a payload typed as hex bytes, e.g. "01 02 ff", "0102ff" or "0x01 0x02".
+pub fn parse_hex(text: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    for token in text.split(|c: char| c.is_whitespace() || c == ',') {
+        let digits = token.trim_start_matches("0x").trim_start_matches("0X");
+        if !digits.bytes().all(|b| b.is_ascii_hexdigit()) || digits.len() % 2 != 0 {
+            bail!("Invalid hex payload: {} (expected pairs of hex digits)", token);
+        }
+        for pair in digits.as_bytes().chunks(2) {
+            let hex = |b: u8| (b as char).to_digit(16).unwrap_or(0) as u8;
+            out.push(hex(pair[0]) << 4 | hex(pair[1]));
+        }
+    }
+    Ok(out)
+}
+
+/// Formats up to 16 bytes as hex followed by their printable ASCII characters.
+fn hex_line(data: &[u8]) -> String {
+    let hex: Vec<String> = data.iter().map(|b| format!("{:02x}", b)).collect();
+    let ascii: String = data.iter()
+        .map(|b| if *b >= 0x20 && *b < 0x7f { *b as char } else { '.' })
+        .collect();
+    format!("{:<47}  |{}|", hex.join(" "), ascii)
+}
+
+/// Decodes the console output for a view into timestamped lines.
+pub struct Viewer {
+    view: View,
+    start: Instant,
+    offset: usize,
+    frame: Vec<u8>,
+}
+
+impl Viewer {
+    pub fn new(view: View) -> Viewer {
+        Viewer { view, start: Instant::now(), offset: 0, frame: Vec::new() }
+    }
+
+    pub fn view(&self) -> View {
+        self.view
+    }
+
+    /// Returns the lines to display for data received from the device.
+    pub fn push(&mut self, data: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+        match self.view {
+            View::Text => lines.push(String::from_utf8_lossy(data).into_owned()),
+            View::Hex => {
+                for chunk in data.chunks(16) {
+                    let line = format!("{:08x}  {}", self.offset, hex_line(chunk));
+                    lines.push(self.stamp(&line));
+                    self.offset += chunk.len();
+                }
+            }
+            View::Cobs | View::Slip => {
+                let end = if self.view == View::Cobs { 0 } else { SLIP_END };
+                for b in data {
+                    if *b != end {
+                        self.frame.push(*b);
+                    } else if !self.frame.is_empty() {
+                        let frame: Vec<u8> = self.frame.drain(..).collect();
+                        lines.extend(self.frame_lines(&frame));
+                    }
+                }
+            }
+        }
+        lines
+    }
+
+    fn frame_lines(&self, frame: &[u8]) -> Vec<String> {
+        let decoded = match self.view {
+            View::Cobs => cobs_decode(frame),
+            _ => slip_decode(frame),
+        };
+        let payload = match decoded {
+            Ok(payload) => payload,
+            Err(e) => return vec![self.stamp(&format!("{}: {}", e, hex_line(&frame[..frame.len().min(16)])))],
+        };
+        let mut lines = Vec::new();
+        let header = format!("[{}]", payload.len());
+        for (i, chunk) in payload.chunks(16).enumerate() {
+            let label = if i == 0 { header.as_str() } else { "" };
+            lines.push(self.stamp(&format!("{:<6} {}", label, hex_line(chunk))));
+        }
+        if payload.is_empty() {
+            lines.push(self.stamp(&header));
+        }
+        lines
+    }
+
+    fn stamp(&self, text: &str) -> String {
+        let since = self.start.elapsed();
+        format!("+{}.{:06} {}", since.as_secs(), since.subsec_micros(), text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_hex_payloads() {
+        assert_eq!(parse_hex("01 02ff 0xAB,0x0c").unwrap(), vec![0x01, 0x02, 0xff, 0xab, 0x0c]);
+        assert!(parse_hex("123").is_err());
+        assert!(parse_hex("zz").is_err());
+        // Non-ASCII input with an even byte length is rejected rather than split mid-character.
+        assert!(parse_hex("aéb").is_err());
+    }
+}
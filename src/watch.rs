@@ -0,0 +1,115 @@
+//! Polling file watcher for `bobbin run --watch`.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use Result;
+
+const POLL_INTERVAL_MS: u64 = 500;
+const SETTLE_MS: u64 = 200;
+
+pub struct Watcher {
+    roots: Vec<PathBuf>,
+    skip: Vec<PathBuf>,
+    snapshot: HashMap<PathBuf, SystemTime>,
+    last_poll: Instant,
+}
+
+impl Watcher {
+    /// Watches the given files and directories. Directories are scanned recursively, skipping
+    /// hidden directories other than .cargo and .bobbin, and the cargo target directory.
+    pub fn new(roots: Vec<PathBuf>, target_dir: Option<&str>) -> Result<Watcher> {
+        let mut skip = vec![PathBuf::from("target")];
+        if let Some(dir) = env::var_os("CARGO_TARGET_DIR") {
+            skip.push(PathBuf::from(dir));
+        }
+        if let Some(dir) = target_dir {
+            skip.push(PathBuf::from(dir));
+        }
+        let skip = skip.iter().filter_map(|p| fs::canonicalize(p).ok()).collect();
+        let mut w = Watcher {
+            roots,
+            skip,
+            snapshot: HashMap::new(),
+            last_poll: Instant::now(),
+        };
+        w.snapshot = w.scan()?;
+        Ok(w)
+    }
+
+    /// Returns true if a watched file has been added, removed or modified since the last change
+    /// was reported. The file system is scanned at most every 500 ms.
+    pub fn changed(&mut self) -> Result<bool> {
+        if self.last_poll.elapsed() < Duration::from_millis(POLL_INTERVAL_MS) {
+            return Ok(false)
+        }
+        self.last_poll = Instant::now();
+        let snapshot = self.scan()?;
+        if snapshot == self.snapshot {
+            return Ok(false)
+        }
+        // Give editors a moment to finish writing before rebuilding.
+        thread::sleep(Duration::from_millis(SETTLE_MS));
+        self.snapshot = self.scan()?;
+        Ok(true)
+    }
+
+    /// Blocks until a watched file changes.
+    pub fn wait(&mut self) -> Result<()> {
+        while !self.changed()? {
+            thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+        }
+        Ok(())
+    }
+
+    fn scan(&self) -> Result<HashMap<PathBuf, SystemTime>> {
+        let mut snapshot = HashMap::new();
+        for root in self.roots.iter() {
+            self.scan_path(root, &mut snapshot)?;
+        }
+        Ok(snapshot)
+    }
+
+    fn scan_path(&self, path: &Path, snapshot: &mut HashMap<PathBuf, SystemTime>) -> Result<()> {
+        let meta = match fs::metadata(path) {
+            Ok(meta) => meta,
+            // Files may disappear while an editor saves them.
+            Err(_) => return Ok(()),
+        };
+        if meta.is_file() {
+            snapshot.insert(path.to_path_buf(), meta.modified()?);
+        } else if meta.is_dir() {
+            if let Ok(canonical) = fs::canonicalize(path) {
+                if self.skip.contains(&canonical) {
+                    return Ok(())
+                }
+            }
+            // Directories may also disappear between reading their metadata and their entries.
+            let entries = match fs::read_dir(path) {
+                Ok(entries) => entries,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+            for entry in entries {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e.into()),
+                };
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                if name.starts_with('.') && name != ".cargo" && name != ".bobbin" {
+                    continue
+                }
+                self.scan_path(&entry.path(), snapshot)?;
+            }
+        }
+        Ok(())
+    }
+}
+